@@ -0,0 +1,208 @@
+use std::path::Path;
+
+use k8s_openapi::chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use tokio::sync::mpsc;
+
+use crate::output::LogLine;
+
+/// How many lines are stored with one transaction at most.
+const MAX_BATCH: usize = 500;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS lines (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    context TEXT,
+    namespace TEXT NOT NULL,
+    pod TEXT NOT NULL,
+    container TEXT,
+    resource TEXT,
+    timestamp TEXT,
+    line TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS lines_time ON lines (time);
+";
+
+/// Restricts which archived lines are returned by `query`.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    /// Only lines from this time on.
+    pub since: Option<DateTime<Utc>>,
+    pub namespace: Option<String>,
+    /// Only lines of pods whose names contain this.
+    pub pod: Option<String>,
+    /// Only lines containing this.
+    pub grep: Option<String>,
+}
+
+/// Opens an archive, creating it if needed.
+pub fn open(path: &Path) -> anyhow::Result<Connection> {
+    let connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+/// The time of a line as recorded by Kubernetes if requested, or else now, formatted so
+/// that times sort as text.
+fn time(line: &LogLine) -> String {
+    line.timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or_else(Utc::now, |timestamp| timestamp.with_timezone(&Utc))
+        .to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn store(connection: &mut Connection, lines: &[LogLine]) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare_cached(
+            "INSERT INTO lines (time, context, namespace, pod, container, resource, timestamp, line)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for line in lines {
+            insert.execute(params![
+                time(line),
+                line.context,
+                line.namespace,
+                line.pod,
+                line.container,
+                line.resource,
+                line.timestamp,
+                line.line,
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+/// Stores lines until all senders are dropped, committing whatever arrived together in
+/// one transaction. Blocks, so it is meant to run with `spawn_blocking`.
+pub fn run(mut connection: Connection, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<()> {
+    while let Some(first) = lines.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match lines.try_recv() {
+                Ok(line) => batch.push(line),
+                Err(_) => break,
+            }
+        }
+        store(&mut connection, &batch)?;
+    }
+    Ok(())
+}
+
+/// Returns the archived lines matching the query, oldest first.
+pub fn query(connection: &Connection, query: &Query) -> anyhow::Result<Vec<LogLine>> {
+    let since = query
+        .since
+        .map(|since| since.to_rfc3339_opts(SecondsFormat::Nanos, true));
+    let mut select = connection.prepare(
+        "SELECT context, namespace, pod, container, resource, timestamp, line FROM lines
+         WHERE (?1 IS NULL OR time >= ?1)
+           AND (?2 IS NULL OR namespace = ?2)
+           AND (?3 IS NULL OR instr(pod, ?3) > 0)
+           AND (?4 IS NULL OR instr(line, ?4) > 0)
+         ORDER BY time, id",
+    )?;
+    let lines = select
+        .query_map(
+            params![since, query.namespace, query.pod, query.grep],
+            |row| {
+                Ok(LogLine {
+                    context: row.get(0)?,
+                    namespace: row.get(1)?,
+                    pod: row.get(2)?,
+                    container: row.get(3)?,
+                    resource: row.get(4)?,
+                    timestamp: row.get(5)?,
+                    line: row.get(6)?,
+                    restart: false,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn log_line(pod: &str, timestamp: &str, line: &str) -> LogLine {
+        LogLine {
+            container: Some("app".to_string()),
+            resource: Some("deployment/api".to_string()),
+            timestamp: Some(timestamp.to_string()),
+            ..test_util::log_line(pod, line)
+        }
+    }
+
+    fn archive(lines: &[LogLine]) -> Connection {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        store(&mut connection, lines).unwrap();
+        connection
+    }
+
+    #[test]
+    fn test_query() {
+        let lines = [
+            log_line("api-1", "2024-01-01T00:00:00Z", "request timeout"),
+            log_line("web-1", "2024-01-01T01:00:00Z", "request timeout"),
+            log_line("api-2", "2024-01-01T01:30:00+01:00", "ok"),
+            log_line("api-2", "2024-01-01T03:00:00Z", "timeout again"),
+        ];
+        let connection = archive(&lines);
+
+        assert_eq!(
+            query(&connection, &Query::default()).unwrap(),
+            vec![
+                lines[0].clone(),
+                lines[2].clone(),
+                lines[1].clone(),
+                lines[3].clone()
+            ]
+        );
+
+        let found = query(
+            &connection,
+            &Query {
+                since: Some("2024-01-01T00:30:00Z".parse().unwrap()),
+                pod: Some("api".to_string()),
+                grep: Some("timeout".to_string()),
+                ..Query::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(found, vec![lines[3].clone()]);
+
+        let other_namespace = Query {
+            namespace: Some("default".to_string()),
+            ..Query::default()
+        };
+        assert!(query(&connection, &other_namespace).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let path = std::env::temp_dir().join(format!("klog-archive-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (tx, rx) = mpsc::channel(100);
+        let connection = open(&path).unwrap();
+        let task = tokio::task::spawn_blocking(move || run(connection, rx));
+
+        tx.try_send(log_line("api-1", "2024-01-01T00:00:00Z", "one"))
+            .unwrap();
+        tx.try_send(log_line("api-1", "2024-01-01T00:00:01Z", "two"))
+            .unwrap();
+        drop(tx);
+        task.await.unwrap().unwrap();
+
+        let lines = query(&open(&path).unwrap(), &Query::default()).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].line, "two");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+/// The contents of klog's config file.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Named sets of command line options, keyed by their long names, e.g. `namespace`,
+    /// `deployments` or `color-salt`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+/// Where the config file is looked for: `$XDG_CONFIG_HOME/klog/config.yaml`, or
+/// `~/.config/klog/config.yaml` without it.
+pub fn path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("klog").join("config.yaml"))
+}
+
+pub fn load(path: &Path) -> anyhow::Result<Config> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read config {}: {}", path.display(), e))?;
+    serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))
+}
+
+impl Config {
+    /// Lists the options of a profile as option names with their values. Flags set to
+    /// `true` have no values, flags set to `false` are left out.
+    pub fn profile_args(&self, name: &str) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Profile {} not found", name))?;
+        let mut args = vec![];
+        for (option, value) in profile {
+            let values = match value {
+                Value::Bool(false) => continue,
+                Value::Bool(true) => vec![],
+                Value::Sequence(values) => values
+                    .iter()
+                    .map(|value| scalar(option, value))
+                    .collect::<anyhow::Result<_>>()?,
+                value => vec![scalar(option, value)?],
+            };
+            args.push((option.clone(), values));
+        }
+        Ok(args)
+    }
+}
+
+fn scalar(option: &str, value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => anyhow::bail!(
+            "Invalid value for {}, expected a string, number or list",
+            option
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+profiles:
+  payments:
+    namespace: payments
+    deployments: [api, worker]
+    filter: ERROR
+    follow: true
+    merge-identical-pods: false
+    refresh-interval: 10
+"#;
+
+    #[test]
+    fn test_profile_args() {
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+
+        let args = config.profile_args("payments").unwrap();
+
+        let expected = vec![
+            ("deployments", vec!["api", "worker"]),
+            ("filter", vec!["ERROR"]),
+            ("follow", vec![]),
+            ("namespace", vec!["payments"]),
+            ("refresh-interval", vec!["10"]),
+        ];
+        let expected: Vec<(String, Vec<String>)> = expected
+            .into_iter()
+            .map(|(option, values)| {
+                (
+                    option.to_string(),
+                    values.into_iter().map(String::from).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(args, expected);
+        assert!(config.profile_args("billing").is_err());
+    }
+
+    #[test]
+    fn test_profile_args_rejects_nested_values() {
+        let config: Config =
+            serde_yaml::from_str("profiles:\n  web:\n    filter: { text: ERROR }\n").unwrap();
+
+        assert!(config.profile_args("web").is_err());
+    }
+}
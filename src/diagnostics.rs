@@ -0,0 +1,100 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use colored::{ColoredString, Colorize};
+use tokio::sync::mpsc;
+use tracing_subscriber::EnvFilter;
+
+use crate::util;
+
+/// Whether notices are left out, with `--quiet`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Where messages go instead of stderr while the TUI owns the terminal.
+static CAPTURED: Mutex<Option<mpsc::UnboundedSender<String>>> = Mutex::new(None);
+
+/// Renders a message of klog itself, as opposed to a log line.
+fn render(label: ColoredString, message: &dyn Display) -> String {
+    format!("klog: {}: {}", label, message)
+}
+
+fn emit(label: ColoredString, message: &dyn Display) {
+    if let Some(captured) = CAPTURED.lock().unwrap().as_ref() {
+        let _ = captured.send(util::strip_ansi(&render(label, message)).into_owned());
+        return;
+    }
+    // Messages go to stderr so that stdout only carries log lines and can be piped or
+    // redirected as is.
+    let _ = writeln!(io::stderr().lock(), "{}", render(label, message));
+}
+
+/// Reports an error, e.g. a failed request of a sink or the one klog ends with.
+pub fn error(message: impl Display) {
+    emit("error".red().bold(), &message);
+}
+
+/// Reports a change in what is streamed, e.g. a pod that was replaced.
+pub fn notice(message: impl Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        emit("notice".cyan(), &message);
+    }
+}
+
+/// Sends messages to the returned receiver without colors instead of writing them to
+/// stderr, until `release` is called.
+pub fn capture() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    *CAPTURED.lock().unwrap() = Some(tx);
+    rx
+}
+
+/// Writes messages to stderr again after `capture`.
+pub fn release() {
+    *CAPTURED.lock().unwrap() = None;
+}
+
+/// The filter for klog's own tracing without `RUST_LOG`: warnings by default, debug output
+/// of klog with `-v`, and of the Kubernetes client as well with `-vv`.
+fn default_filter(verbose: u8, quiet: bool) -> String {
+    match (quiet, verbose) {
+        (true, _) => "klog=error".to_string(),
+        (false, 0) => "klog=warn".to_string(),
+        (false, 1) => "klog=debug".to_string(),
+        (false, _) => "klog=trace,kube=debug".to_string(),
+    }
+}
+
+/// Sends klog's tracing of API calls, discovery and reconnects to stderr, filtered by
+/// `RUST_LOG` if it is set. `quiet` also leaves out notices.
+pub fn init(verbose: u8, quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(default_filter(verbose, quiet)));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(colored::control::SHOULD_COLORIZE.should_colorize())
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let message = render("error".red(), &format_args!("pod {} gone", "web-1"));
+
+        assert_eq!(util::strip_ansi(&message), "klog: error: pod web-1 gone");
+    }
+
+    #[test]
+    fn test_default_filter() {
+        assert_eq!(default_filter(0, false), "klog=warn");
+        assert_eq!(default_filter(1, false), "klog=debug");
+        assert_eq!(default_filter(3, false), "klog=trace,kube=debug");
+        assert_eq!(default_filter(2, true), "klog=error");
+    }
+}
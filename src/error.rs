@@ -0,0 +1,163 @@
+use std::fmt;
+use std::time::Duration;
+
+// Exit codes, leaving 1 for other errors and 2 for invalid arguments.
+const AUTH: u8 = 3;
+const NAMESPACE_NOT_FOUND: u8 = 4;
+const RESOURCE_NOT_FOUND: u8 = 5;
+const FORBIDDEN: u8 = 6;
+const STREAM: u8 = 7;
+const IDLE: u8 = 8;
+
+/// The errors klog ends with that scripts may want to react to, each with its own exit code.
+#[derive(Debug)]
+pub enum KlogError {
+    /// The credentials for the cluster could not be loaded or were rejected.
+    Auth(anyhow::Error),
+    /// A namespace to stream from does not exist.
+    NamespaceNotFound(String),
+    /// A pod or workload to stream does not exist, in the given namespace or in any of them.
+    ResourceNotFound {
+        kind: String,
+        name: String,
+        namespace: Option<String>,
+    },
+    /// klog is not allowed to read something it needs, e.g. the logs of pods.
+    Forbidden(String),
+    /// A log stream failed.
+    Stream {
+        namespace: String,
+        pod: String,
+        source: anyhow::Error,
+    },
+    /// No pod logged a line within `--idle-timeout`.
+    Idle(Duration),
+}
+
+impl KlogError {
+    /// The exit code klog ends with on this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            KlogError::Auth(_) => AUTH,
+            KlogError::NamespaceNotFound(_) => NAMESPACE_NOT_FOUND,
+            KlogError::ResourceNotFound { .. } => RESOURCE_NOT_FOUND,
+            KlogError::Forbidden(_) => FORBIDDEN,
+            KlogError::Stream { .. } => STREAM,
+            KlogError::Idle(_) => IDLE,
+        }
+    }
+}
+
+impl fmt::Display for KlogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KlogError::Auth(_) => write!(f, "Could not authenticate with the cluster"),
+            KlogError::NamespaceNotFound(namespace) => {
+                write!(f, "Namespace {} not found", namespace)
+            }
+            KlogError::ResourceNotFound {
+                kind,
+                name,
+                namespace: Some(namespace),
+            } => write!(f, "{} {} not found in namespace {}", kind, name, namespace),
+            KlogError::ResourceNotFound { kind, name, .. } => {
+                write!(f, "{} {} not found in any namespace", kind, name)
+            }
+            KlogError::Forbidden(message) => write!(f, "Permission denied: {}", message),
+            KlogError::Stream { namespace, pod, .. } => {
+                write!(f, "Could not stream logs for pod {}/{}", namespace, pod)
+            }
+            KlogError::Idle(timeout) => {
+                write!(f, "No log lines received for {}s", timeout.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for KlogError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KlogError::Auth(source) | KlogError::Stream { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Turns a failed API request that was not turned into a `KlogError` where it was made into
+/// one if its status tells what went wrong, like a 403 for missing permissions.
+pub fn classify(error: anyhow::Error) -> anyhow::Error {
+    if error.downcast_ref::<KlogError>().is_some() {
+        return error;
+    }
+    let response = error
+        .chain()
+        .find_map(|cause| match cause.downcast_ref::<kube::Error>() {
+            Some(kube::Error::Api(response)) => Some(response.clone()),
+            _ => None,
+        });
+    match response {
+        Some(response) if response.code == 401 => KlogError::Auth(error).into(),
+        Some(response) if response.code == 403 => KlogError::Forbidden(response.message).into(),
+        _ => error,
+    }
+}
+
+/// The exit code for `error`, from the `KlogError` behind it.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<KlogError>())
+        .map_or(1, KlogError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::core::ErrorResponse;
+
+    fn api_error(code: u16) -> anyhow::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: "denied".to_string(),
+            reason: String::new(),
+            code,
+        })
+        .into()
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let not_found = KlogError::ResourceNotFound {
+            kind: "Deployment".to_string(),
+            name: "web".to_string(),
+            namespace: Some("shop".to_string()),
+        };
+        assert_eq!(
+            not_found.to_string(),
+            "Deployment web not found in namespace shop"
+        );
+        assert_eq!(exit_code(&not_found.into()), 5);
+
+        let stream = anyhow::Error::new(KlogError::Stream {
+            namespace: "shop".to_string(),
+            pod: "web-1".to_string(),
+            source: anyhow::anyhow!("connection reset"),
+        });
+        assert_eq!(
+            format!("{:#}", stream),
+            "Could not stream logs for pod shop/web-1: connection reset"
+        );
+        assert_eq!(exit_code(&stream), 7);
+        assert_eq!(
+            exit_code(&KlogError::Idle(Duration::from_secs(300)).into()),
+            8
+        );
+
+        assert_eq!(exit_code(&classify(api_error(401))), 3);
+        let forbidden = classify(api_error(403).context("listing pods"));
+        assert_eq!(forbidden.to_string(), "Permission denied: denied");
+        assert_eq!(exit_code(&forbidden), 6);
+        assert_eq!(exit_code(&classify(api_error(500))), 1);
+        assert_eq!(exit_code(&anyhow::anyhow!("invalid target")), 1);
+    }
+}
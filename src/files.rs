@@ -0,0 +1,458 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use k8s_openapi::chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::diagnostics;
+use crate::output::LogLine;
+
+/// Writes the lines of each pod to its own file in a directory, named
+/// `<namespace>_<pod>.log`, or `<context>_<namespace>_<pod>.log` when streaming several
+/// clusters. Files are appended to and created with the directory when their pod logs its
+/// first line.
+pub struct PodFiles {
+    dir: PathBuf,
+    files: HashMap<PathBuf, LineWriter<File>>,
+}
+
+impl PodFiles {
+    pub fn new(dir: PathBuf) -> Self {
+        PodFiles {
+            dir,
+            files: HashMap::new(),
+        }
+    }
+
+    fn path(&self, line: &LogLine) -> PathBuf {
+        let name = line
+            .context
+            .iter()
+            .chain([&line.namespace, &line.pod])
+            .map(|part| file_name_part(part))
+            .collect::<Vec<_>>()
+            .join("_");
+        self.dir.join(format!("{}.log", name))
+    }
+
+    /// Appends the line as the container wrote it, after its timestamp if requested.
+    pub fn write(&mut self, line: &LogLine) -> io::Result<()> {
+        let file = match self.files.entry(self.path(line)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                fs::create_dir_all(&self.dir)?;
+                let file = File::options()
+                    .create(true)
+                    .append(true)
+                    .open(entry.key())?;
+                entry.insert(LineWriter::new(file))
+            }
+        };
+        match &line.timestamp {
+            Some(timestamp) => writeln!(file, "{} {}", timestamp, line.line),
+            None => writeln!(file, "{}", line.line),
+        }
+    }
+
+    /// Flushes and closes all files.
+    pub fn close(&mut self) -> io::Result<()> {
+        for (_, mut file) in self.files.drain() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A rotated file of a `RotatingFile` to upload. It is a file of its own, so later
+/// rotations do not touch it, and whoever receives it deletes it.
+#[derive(Debug)]
+pub struct Segment {
+    /// A name for the segment that is unique over rotations, like
+    /// `klog.log.20240101T120000.000Z.gz`.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// A file that all lines are appended to, which is renamed to `<path>.1` once it reaches
+/// `max_size`, moving older files to `<path>.2` and so on, up to `<path>.<keep>`.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    rotation: Rotation,
+    /// When the file was last rotated, in milliseconds, to keep the names of rotated files
+    /// unique.
+    last_rotated: i64,
+    rotator: Option<Rotator>,
+    file: Option<LineWriter<File>>,
+    size: u64,
+}
+
+/// What is done with rotated files. Compressing a big file takes a while, so this happens
+/// on a thread of its own instead of holding up the lines written meanwhile.
+#[derive(Clone)]
+struct Rotation {
+    path: PathBuf,
+    keep: usize,
+    compress: bool,
+    segments: Option<mpsc::Sender<Segment>>,
+}
+
+/// The thread that moves rotated files, one after another.
+struct Rotator {
+    files: std_mpsc::Sender<PathBuf>,
+    thread: thread::JoinHandle<io::Result<()>>,
+}
+
+impl Rotation {
+    fn archive(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        if self.compress {
+            path.push(".gz");
+        }
+        path.into()
+    }
+
+    /// Moves a rotated file, which was renamed to `<path>.<time>`, to `<path>.1` and sends
+    /// it to `segments`, as `<path>.<time>` or `<path>.<time>.gz`.
+    fn rotate(&self, rotated: &Path) -> io::Result<()> {
+        let segment = self.segments.is_some().then(|| {
+            let mut path = rotated.to_path_buf().into_os_string();
+            if self.compress {
+                path.push(".gz");
+            }
+            PathBuf::from(path)
+        });
+        let archive = match (&segment, self.keep) {
+            (None, 0) => return fs::remove_file(rotated),
+            (Some(segment), 0) => segment.clone(),
+            _ => {
+                for index in (1..self.keep).rev() {
+                    if self.archive(index).exists() {
+                        fs::rename(self.archive(index), self.archive(index + 1))?;
+                    }
+                }
+                self.archive(1)
+            }
+        };
+        if self.compress {
+            let mut gz = GzEncoder::new(File::create(&archive)?, Compression::default());
+            io::copy(&mut File::open(rotated)?, &mut gz)?;
+            gz.finish()?;
+            fs::remove_file(rotated)?;
+        } else {
+            fs::rename(rotated, &archive)?;
+        }
+
+        let (Some(path), Some(segments)) = (segment, &self.segments) else {
+            return Ok(());
+        };
+        if path != archive {
+            // The archive is renamed by the next rotation, so the segment is a link to it.
+            fs::hard_link(&archive, &path).or_else(|_| fs::copy(&archive, &path).map(drop))?;
+        }
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        match segments.try_send(Segment { name, path }) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(segment)) => {
+                diagnostics::error(format_args!(
+                    "Dropped {}, as the files before it are still being uploaded",
+                    segment.name
+                ));
+                fs::remove_file(segment.path)
+            }
+            Err(TrySendError::Closed(segment)) => fs::remove_file(segment.path),
+        }
+    }
+}
+
+impl RotatingFile {
+    pub fn new(path: PathBuf, max_size: Option<u64>, keep: usize) -> Self {
+        RotatingFile {
+            rotation: Rotation {
+                path: path.clone(),
+                keep,
+                compress: false,
+                segments: None,
+            },
+            path,
+            max_size,
+            last_rotated: 0,
+            rotator: None,
+            file: None,
+            size: 0,
+        }
+    }
+
+    /// Gzips rotated files, which are then called `<path>.1.gz` and so on.
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.rotation.compress = compress;
+        self
+    }
+
+    /// Sends each rotated file to `segments`, e.g. to upload it. Files are dropped while
+    /// `segments` is full.
+    pub fn segments(mut self, segments: Option<mpsc::Sender<Segment>>) -> Self {
+        self.rotation.segments = segments;
+        self
+    }
+
+    /// Renames the file to `<path>.20240101T120000.000Z` and leaves the rest to the
+    /// rotator thread.
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let millis = Utc::now().timestamp_millis().max(self.last_rotated + 1);
+        self.last_rotated = millis;
+        let time = DateTime::from_timestamp_millis(millis).unwrap_or_default();
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{}", time.format("%Y%m%dT%H%M%S%.3fZ")));
+        fs::rename(&self.path, &rotated)?;
+
+        let rotator = self.rotator.get_or_insert_with(|| {
+            let (files, queue) = std_mpsc::channel::<PathBuf>();
+            let rotation = self.rotation.clone();
+            let thread = thread::spawn(move || {
+                queue
+                    .iter()
+                    .try_for_each(|rotated| rotation.rotate(&rotated))
+            });
+            Rotator { files, thread }
+        });
+        if rotator.files.send(rotated.into()).is_err() {
+            // The thread stopped, as it failed to move an earlier file.
+            return self.join_rotator();
+        }
+        Ok(())
+    }
+
+    /// Waits for the rotated files to be moved, returning why that failed if it did.
+    fn join_rotator(&mut self) -> io::Result<()> {
+        let Some(Rotator { files, thread }) = self.rotator.take() else {
+            return Ok(());
+        };
+        drop(files);
+        thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Rotating the file panicked")))
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::options().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(LineWriter::new(file));
+        Ok(())
+    }
+
+    /// Appends a line, rotating the file first if the line would make it too big.
+    pub fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        self.open()?;
+        if self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + len > max_size)
+        {
+            self.rotate()?;
+            self.open()?;
+        }
+        writeln!(self.file.as_mut().unwrap(), "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Flushes and closes the file, once the files rotated so far are moved.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.join_rotator()?;
+        match self.file.take() {
+            Some(mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Replaces characters that are not safe in file names, e.g. the `/` and `:` in contexts
+/// like `arn:aws:eks:eu-west-1:123:cluster/prod`.
+fn file_name_part(part: &str) -> String {
+    part.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use std::io::Read;
+
+    /// A directory to write to that no other test uses.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("klog-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn log_line(context: Option<&str>, pod: &str, line: &str) -> LogLine {
+        LogLine {
+            context: context.map(str::to_string),
+            ..test_util::log_line(pod, line)
+        }
+    }
+
+    #[test]
+    fn test_pod_files() {
+        let dir = test_dir("pod-files");
+        let mut files = PodFiles::new(dir.join("logs"));
+
+        files.write(&log_line(None, "web-1", "one")).unwrap();
+        files.write(&log_line(None, "web-2", "other")).unwrap();
+        files.write(&log_line(None, "web-1", "two")).unwrap();
+        files
+            .write(&log_line(
+                Some("arn:aws:eks:cluster/prod"),
+                "web-1",
+                "three",
+            ))
+            .unwrap();
+        files.close().unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join("logs").join(name)).unwrap();
+        assert_eq!(read("shop_web-1.log"), "one\ntwo\n");
+        assert_eq!(read("shop_web-2.log"), "other\n");
+        assert_eq!(read("arn-aws-eks-cluster-prod_shop_web-1.log"), "three\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = test_dir("rotating-file");
+        let path = dir.join("klog.log");
+        let mut file = RotatingFile::new(path.clone(), Some(10), 2);
+
+        for line in ["l1", "l2", "l3", "l4", "l5", "l6", "l7"] {
+            file.write(line).unwrap();
+        }
+        file.close().unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("klog.log"), "l7\n");
+        assert_eq!(read("klog.log.1"), "l4\nl5\nl6\n");
+        assert_eq!(read("klog.log.2"), "l1\nl2\nl3\n");
+
+        // Reopening appends to the existing file and keeps rotating.
+        let mut file = RotatingFile::new(path, Some(10), 2);
+        for line in ["l8", "l9", "l10"] {
+            file.write(line).unwrap();
+        }
+        file.close().unwrap();
+        assert_eq!(read("klog.log"), "l10\n");
+        assert_eq!(read("klog.log.1"), "l7\nl8\nl9\n");
+        assert_eq!(read("klog.log.2"), "l4\nl5\nl6\n");
+        assert!(!dir.join("klog.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file_compress() {
+        let dir = test_dir("rotating-file-compress");
+        let mut file = RotatingFile::new(dir.join("klog.log"), Some(10), 2).compress(true);
+
+        for line in ["l1", "l2", "l3", "l4", "l5", "l6", "l7"] {
+            file.write(line).unwrap();
+        }
+        file.close().unwrap();
+
+        let gunzip = |name: &str| {
+            let mut contents = String::new();
+            flate2::read::GzDecoder::new(File::open(dir.join(name)).unwrap())
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(fs::read_to_string(dir.join("klog.log")).unwrap(), "l7\n");
+        assert_eq!(gunzip("klog.log.1.gz"), "l4\nl5\nl6\n");
+        assert_eq!(gunzip("klog.log.2.gz"), "l1\nl2\nl3\n");
+        assert!(!dir.join("klog.log.1").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file_segments() {
+        let dir = test_dir("rotating-file-segments");
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut file = RotatingFile::new(dir.join("klog.log"), Some(10), 1).segments(Some(tx));
+
+        for line in ["l1", "l2", "l3", "l4", "l5", "l6", "l7"] {
+            file.write(line).unwrap();
+        }
+        file.close().unwrap();
+
+        let segment = rx.try_recv().unwrap();
+        assert!(segment.name.starts_with("klog.log.2"));
+        assert!(segment.name.ends_with('Z'));
+        assert_eq!(segment.path, dir.join(&segment.name));
+        assert_eq!(fs::read(&segment.path).unwrap(), b"l1\nl2\nl3\n");
+        // The second one did not fit into the channel.
+        assert!(rx.try_recv().is_err());
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["klog.log", "klog.log.1", segment.name.as_str()]);
+        assert_eq!(fs::read(dir.join("klog.log.1")).unwrap(), b"l4\nl5\nl6\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file_compressed_segments() {
+        let dir = test_dir("rotating-file-compressed-segments");
+        let (tx, mut rx) = mpsc::channel(2);
+        let mut file = RotatingFile::new(dir.join("klog.log"), Some(10), 1)
+            .compress(true)
+            .segments(Some(tx));
+
+        for line in ["l1", "l2", "l3", "l4", "l5", "l6", "l7"] {
+            file.write(line).unwrap();
+        }
+        file.close().unwrap();
+
+        let gunzip = |path: &Path| {
+            let mut contents = String::new();
+            flate2::read::GzDecoder::new(File::open(path).unwrap())
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        let first = rx.try_recv().unwrap();
+        let second = rx.try_recv().unwrap();
+        assert!(first.name.ends_with("Z.gz"));
+        assert_eq!(gunzip(&first.path), "l1\nl2\nl3\n");
+        assert_eq!(gunzip(&second.path), "l4\nl5\nl6\n");
+        assert_eq!(gunzip(&dir.join("klog.log.1.gz")), "l4\nl5\nl6\n");
+        // Only the compressed files are left besides the current one.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::sync::Arc;
+
+use k8s_openapi::chrono::SecondsFormat;
+
+use crate::output::{LogEvent, LogLine};
+
+/// Processes lines before they are output, e.g. to redact secrets or to add fields.
+pub trait Hook: Send + Sync {
+    /// Returns the event to output, changed or not, or `None` to drop it.
+    fn process(&self, event: LogEvent) -> Option<LogEvent>;
+}
+
+impl<F> Hook for F
+where
+    F: Fn(LogEvent) -> Option<LogEvent> + Send + Sync,
+{
+    fn process(&self, event: LogEvent) -> Option<LogEvent> {
+        self(event)
+    }
+}
+
+/// The hooks that lines pass through in the order they were added, before the filters.
+#[derive(Clone, Default)]
+pub struct Hooks(Vec<Arc<dyn Hook>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hooks({})", self.0.len())
+    }
+}
+
+impl Hooks {
+    pub fn push(&mut self, hook: impl Hook + 'static) {
+        self.0.push(Arc::new(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Passes the event through all hooks, unless one of them drops it.
+    pub fn apply(&self, event: LogEvent) -> Option<LogEvent> {
+        self.0
+            .iter()
+            .try_fold(event, |event, hook| hook.process(event))
+    }
+
+    /// Passes a line to write through all hooks. Changes to `parsed` are lost, as written
+    /// lines are parsed again from `raw`.
+    pub fn apply_line(&self, line: LogLine) -> Option<LogLine> {
+        if self.is_empty() {
+            return Some(line);
+        }
+        let timestamp = line.timestamp.clone();
+        let event = LogEvent::from(line);
+        let parsed_timestamp = event.timestamp;
+        let event = self.apply(event)?;
+        Some(LogLine {
+            // Keep the API server's timestamp as it was, unless a hook changed it.
+            timestamp: match event.timestamp {
+                time if time == parsed_timestamp => timestamp,
+                time => time.map(|time| time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            },
+            context: event.context,
+            namespace: event.namespace,
+            pod: event.pod,
+            container: event.container,
+            resource: event.resource,
+            line: event.raw,
+            restart: event.restart,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn log_line(line: &str) -> LogLine {
+        LogLine {
+            timestamp: Some("2024-01-01T12:00:00.12345678Z".to_string()),
+            ..test_util::log_line("web-1", line)
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut hooks = Hooks::default();
+        assert_eq!(hooks.apply_line(log_line("GET /")), Some(log_line("GET /")));
+
+        hooks.push(|event: LogEvent| (!event.raw.contains("/healthz")).then_some(event));
+        hooks.push(|mut event: LogEvent| {
+            event.raw = event.raw.replace("secret", "***");
+            Some(event)
+        });
+        hooks.push(|mut event: LogEvent| {
+            event.container.get_or_insert_with(|| "app".to_string());
+            Some(event)
+        });
+
+        assert_eq!(hooks.apply_line(log_line("GET /healthz")), None);
+        let line = hooks.apply_line(log_line("token=secret")).unwrap();
+        assert_eq!(line.line, "token=***");
+        assert_eq!(line.container.as_deref(), Some("app"));
+        assert_eq!(
+            line.timestamp.as_deref(),
+            Some("2024-01-01T12:00:00.12345678Z")
+        );
+
+        let event = hooks.apply(LogEvent::from(log_line("secret"))).unwrap();
+        assert_eq!(event.raw, "***");
+    }
+}
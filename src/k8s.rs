@@ -1,45 +1,146 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Ok;
-use colored::Colorize;
+use futures_util::future;
 use futures_util::AsyncBufReadExt;
-use futures_util::TryStreamExt;
+use futures_util::{Stream, StreamExt, TryStreamExt};
 
-use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::{ContainerStatus, Namespace, Pod, Service};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use k8s_openapi::chrono::{DateTime, Utc};
 use k8s_openapi::serde::Deserialize;
 use k8s_openapi::NamespaceResourceScope;
 use k8s_openapi::Resource;
 use kube::api::ObjectMeta;
-use kube::api::{Api, ListParams, LogParams};
+use kube::api::{Api, ApiResource, DynamicObject, ListParams, LogParams, PostParams};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::core::{GroupVersion, GroupVersionKind};
 use kube::runtime::reflector::Lookup;
-use kube::ResourceExt;
+use kube::runtime::wait::await_condition;
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Config, ResourceExt};
 
-use itertools::Itertools;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
+use crate::error::KlogError;
+use crate::metrics::{StreamState, METRICS};
+use crate::output::LogLine;
 use crate::traits;
 use crate::traits::SpecSelector;
 use crate::util;
 
+/// The longest wait before retrying an API request that failed transiently.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The longest wait before resuming a stream that ended while its container kept running.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Settings for connecting to a cluster.
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    /// Kubeconfig file to read instead of the default one.
+    pub kubeconfig: Option<PathBuf>,
+    /// Kubeconfig context to use instead of the current one.
+    pub context: Option<String>,
+    /// Use the service account of the pod klog runs in.
+    pub in_cluster: bool,
+    /// User to impersonate.
+    pub impersonate: Option<String>,
+    /// Groups to impersonate.
+    pub impersonate_groups: Vec<String>,
+}
+
+/// Builds a client from the given options, falling back to the default configuration
+/// (`KUBECONFIG`, `~/.kube/config` or the in-cluster environment).
+pub async fn client(options: &ClientOptions) -> Result<kube::Client, anyhow::Error> {
+    let kubeconfig_options = KubeConfigOptions {
+        context: options.context.clone(),
+        ..KubeConfigOptions::default()
+    };
+    let config = async {
+        if options.in_cluster {
+            return Config::incluster()
+                .map_err(|e| anyhow::anyhow!("Failed to load in-cluster configuration: {}", e));
+        }
+        let config = match (&options.kubeconfig, &options.context) {
+            (None, None) => Config::infer().await?,
+            (Some(path), _) => {
+                let kubeconfig = Kubeconfig::read_from(path)?;
+                Config::from_custom_kubeconfig(kubeconfig, &kubeconfig_options).await?
+            }
+            (None, Some(_)) => Config::from_kubeconfig(&kubeconfig_options).await?,
+        };
+        Ok(config)
+    };
+    let mut config = config.await.map_err(KlogError::Auth)?;
+    tracing::debug!(cluster = %config.cluster_url, namespace = %config.default_namespace, "Connecting to cluster");
+    if options.impersonate.is_some() {
+        config.auth_info.impersonate = options.impersonate.clone();
+    }
+    if !options.impersonate_groups.is_empty() {
+        config.auth_info.impersonate_groups = Some(options.impersonate_groups.clone());
+    }
+    Ok(kube::Client::try_from(config)?)
+}
+
 async fn get_pod_list(
     client: &kube::Client,
     ns_name: &str,
-    match_labels: BTreeMap<String, String>,
+    labels: &str,
 ) -> Result<Vec<String>, anyhow::Error> {
-    let labels: String = match_labels
-        .iter()
-        .map(|(key, value)| format!("{}={}", key, value))
-        .join(",");
+    let pods = get_pods_by_selector(client, Some(ns_name), labels, "").await?;
+    Ok(pods.into_iter().map(|(_, name)| name).collect())
+}
 
-    let pod_api: Api<Pod> = Api::namespaced(client.clone(), ns_name);
-    let list_params = ListParams::default().labels(&labels);
+/// Lists the pods matching a label and a field selector as `(namespace, name)` pairs.
+/// Without a namespace, pods of all namespaces are listed.
+pub async fn get_pods_by_selector(
+    client: &kube::Client,
+    ns_name: Option<&str>,
+    labels: &str,
+    fields: &str,
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let pod_api: Api<Pod> = match ns_name {
+        Some(ns_name) => Api::namespaced(client.clone(), ns_name),
+        None => Api::all(client.clone()),
+    };
+    let list_params = ListParams::default().labels(labels).fields(fields);
     let pod_list = pod_api.list(&list_params).await?;
 
-    let mut pod_name_list: std::vec::Vec<std::string::String> = vec![];
+    let mut pods = vec![];
     for pod in pod_list.iter() {
-        pod_name_list.push(pod.name().unwrap().to_string());
+        let namespace = ns_name
+            .map(String::from)
+            .or_else(|| ResourceExt::namespace(pod))
+            .unwrap_or_default();
+        pods.push((namespace, pod.name().unwrap().to_string()));
     }
-    Ok(pod_name_list)
+    Ok(pods)
+}
+
+fn label_selector_for_resource<T>(resource: &T) -> Result<String, anyhow::Error>
+where
+    T: traits::HasSpec,
+{
+    // Retrieve `selector` from `spec` using `SpecSelector` trait.
+    let selector = resource
+        .spec()
+        .and_then(|spec| spec.selector())
+        .ok_or_else(|| anyhow::anyhow!("Missing selector"))?;
+    let labels = util::label_selector(selector)?;
+    if labels.is_empty() {
+        anyhow::bail!("Missing match labels");
+    }
+    Ok(labels)
 }
 
 pub async fn get_pod_list_for_resource<T>(
@@ -47,6 +148,24 @@ pub async fn get_pod_list_for_resource<T>(
     resource_name: &str,
     ns_name: &str,
 ) -> Result<Vec<String>, anyhow::Error>
+where
+    T: Resource<Scope = NamespaceResourceScope>
+        + Clone
+        + for<'a> Deserialize<'a>
+        + Debug
+        + k8s_openapi::Metadata<Ty = ObjectMeta>
+        + traits::HasSpec,
+{
+    let labels = get_selector_for_resource::<T>(client, resource_name, ns_name).await?;
+    get_pod_list(client, ns_name, &labels).await
+}
+
+/// Looks up the label selector of the pods of a workload.
+pub async fn get_selector_for_resource<T>(
+    client: &kube::Client,
+    resource_name: &str,
+    ns_name: &str,
+) -> Result<String, anyhow::Error>
 where
     T: Resource<Scope = NamespaceResourceScope>
         + Clone
@@ -57,63 +176,1099 @@ where
 {
     let api: Api<T> = Api::namespaced(client.clone(), ns_name);
     let resource = api.get(resource_name).await?;
+    label_selector_for_resource(&resource)
+}
 
-    // Retrieve `selector` from `spec` using `SpecSelector` trait.
-    let match_labels = resource
-        .spec()
-        .and_then(|spec| spec.selector())
-        .ok_or_else(|| anyhow::anyhow!("Missing selector"))?
-        .match_labels
-        .clone()
-        .ok_or_else(|| anyhow::anyhow!("Missing match labels"))?;
+pub async fn namespace_exists(client: &kube::Client, name: &str) -> Result<bool, anyhow::Error> {
+    let api: Api<Namespace> = Api::all(client.clone());
+    Ok(api.get_opt(name).await?.is_some())
+}
 
-    let pod_name_list = get_pod_list(client, ns_name, match_labels).await?;
+/// Asks the API server whether the user may `verb` a resource like `pods` or `pods/log` in
+/// `namespace`, or in all namespaces if it is `None`.
+pub async fn can_i(
+    client: &kube::Client,
+    verb: &str,
+    resource: &str,
+    namespace: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let (resource, subresource) = match resource.split_once('/') {
+        Some((resource, subresource)) => (resource, Some(subresource)),
+        None => (resource, None),
+    };
+    let review = SelfSubjectAccessReview {
+        spec: SelfSubjectAccessReviewSpec {
+            resource_attributes: Some(ResourceAttributes {
+                verb: Some(verb.to_string()),
+                resource: Some(resource.to_string()),
+                subresource: subresource.map(String::from),
+                namespace: namespace.map(String::from),
+                ..ResourceAttributes::default()
+            }),
+            ..SelfSubjectAccessReviewSpec::default()
+        },
+        ..SelfSubjectAccessReview::default()
+    };
+    let api: Api<SelfSubjectAccessReview> = Api::all(client.clone());
+    let review = api.create(&PostParams::default(), &review).await?;
+    Ok(review.status.is_some_and(|status| status.allowed))
+}
+
+pub async fn resource_exists<T>(
+    client: &kube::Client,
+    name: &str,
+    ns_name: &str,
+) -> Result<bool, anyhow::Error>
+where
+    T: Resource<Scope = NamespaceResourceScope>
+        + Clone
+        + for<'a> Deserialize<'a>
+        + Debug
+        + k8s_openapi::Metadata<Ty = ObjectMeta>,
+{
+    let api: Api<T> = Api::namespaced(client.clone(), ns_name);
+    Ok(api.get_opt(name).await?.is_some())
+}
+
+/// Finds the namespaces that contain a `T` called `name`.
+pub async fn find_namespaces<T>(
+    client: &kube::Client,
+    name: &str,
+) -> Result<Vec<String>, anyhow::Error>
+where
+    T: Resource + Clone + for<'a> Deserialize<'a> + Debug + k8s_openapi::Metadata<Ty = ObjectMeta>,
+{
+    let api: Api<T> = Api::all(client.clone());
+    let list_params = ListParams::default().fields(&format!("metadata.name={}", name));
+    let resources = api.list(&list_params).await?;
+    Ok(resources
+        .iter()
+        .filter_map(ResourceExt::namespace)
+        .collect())
+}
+
+/// Lists all resources of type `T` in the namespace along with the pods each resolves to.
+/// Without a namespace, resources of all namespaces are listed as `namespace/name`.
+///
+/// Resources without a usable selector resolve to no pods.
+pub async fn get_pod_lists_for_all_resources<T>(
+    client: &kube::Client,
+    ns_name: Option<&str>,
+) -> Result<BTreeMap<String, Vec<String>>, anyhow::Error>
+where
+    T: Resource<Scope = NamespaceResourceScope>
+        + Clone
+        + for<'a> Deserialize<'a>
+        + Debug
+        + k8s_openapi::Metadata<Ty = ObjectMeta>
+        + traits::HasSpec,
+{
+    let api: Api<T> = match ns_name {
+        Some(ns_name) => Api::namespaced(client.clone(), ns_name),
+        None => Api::all(client.clone()),
+    };
+    let mut pod_lists = BTreeMap::new();
+
+    for resource in api.list(&ListParams::default()).await? {
+        let resource_ns = match ns_name {
+            Some(ns_name) => ns_name.to_string(),
+            None => ResourceExt::namespace(&resource).unwrap_or_default(),
+        };
+        let pod_name_list = match label_selector_for_resource(&resource) {
+            Result::Ok(labels) => get_pod_list(client, &resource_ns, &labels).await?,
+            Err(_) => vec![],
+        };
+        let key = match ns_name {
+            Some(_) => resource.name_any(),
+            None => format!("{}/{}", resource_ns, resource.name_any()),
+        };
+        pod_lists.insert(key, pod_name_list);
+    }
+    Ok(pod_lists)
+}
+
+pub async fn get_pod_list_for_service(
+    client: &kube::Client,
+    service_name: &str,
+    ns_name: &str,
+) -> Result<Vec<String>, anyhow::Error> {
+    let labels = get_selector_for_service(client, service_name, ns_name).await?;
+    get_pod_list(client, ns_name, &labels).await
+}
+
+/// Looks up the label selector of the pods backing a service.
+pub async fn get_selector_for_service(
+    client: &kube::Client,
+    service_name: &str,
+    ns_name: &str,
+) -> Result<String, anyhow::Error> {
+    let api: Api<Service> = Api::namespaced(client.clone(), ns_name);
+    let service = api.get(service_name).await?;
+
+    let selector = service
+        .spec
+        .and_then(|spec| spec.selector)
+        .filter(|selector| !selector.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Service {} has no selector", service_name))?;
+    Ok(util::match_labels_selector(&selector))
+}
+
+/// Finds the Jobs spawned by a CronJob by walking their owner references.
+///
+/// Only Jobs with running pods are returned, unless `latest_only` is set, in which case
+/// the most recently created Job is returned regardless of its state.
+pub async fn get_jobs_for_cronjob(
+    client: &kube::Client,
+    cronjob_name: &str,
+    ns_name: &str,
+    latest_only: bool,
+) -> Result<Vec<Job>, anyhow::Error> {
+    let api: Api<Job> = Api::namespaced(client.clone(), ns_name);
+    let jobs = api.list(&ListParams::default()).await?;
+
+    let children = jobs.into_iter().filter(|job| {
+        job.owner_references()
+            .iter()
+            .any(|owner| owner.kind == "CronJob" && owner.name == cronjob_name)
+    });
+
+    if latest_only {
+        return Ok(children
+            .max_by_key(|job| job.metadata.creation_timestamp.clone())
+            .into_iter()
+            .collect());
+    }
+    Ok(children
+        .filter(|job| {
+            job.status
+                .as_ref()
+                .and_then(|status| status.active)
+                .unwrap_or(0)
+                > 0
+        })
+        .collect())
+}
+
+pub async fn get_pod_list_for_cronjob(
+    client: &kube::Client,
+    cronjob_name: &str,
+    ns_name: &str,
+    latest_only: bool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut pod_name_list = vec![];
+    for job in get_jobs_for_cronjob(client, cronjob_name, ns_name, latest_only).await? {
+        let labels = label_selector_for_resource(&job)?;
+        pod_name_list.append(&mut get_pod_list(client, ns_name, &labels).await?);
+    }
     Ok(pod_name_list)
 }
 
+/// Resolves owners of pods to their own owner references, caching every lookup so
+/// pods of the same ReplicaSet only cost a single request.
+struct OwnerWalker<'a> {
+    client: &'a kube::Client,
+    api_resources: HashMap<String, Option<ApiResource>>,
+    parents: HashMap<String, Vec<OwnerReference>>,
+}
+
+impl OwnerWalker<'_> {
+    async fn api_resource(&mut self, api_version: &str, kind: &str) -> Option<ApiResource> {
+        let key = format!("{}/{}", api_version, kind);
+        if let Some(api_resource) = self.api_resources.get(&key) {
+            return api_resource.clone();
+        }
+        // Owners of kinds the API server doesn't know are treated as having no owners.
+        let api_resource = match api_version.parse::<GroupVersion>() {
+            Result::Ok(gv) => kube::discovery::pinned_kind(self.client, &gv.with_kind(kind))
+                .await
+                .ok()
+                .map(|(api_resource, _)| api_resource),
+            Err(_) => None,
+        };
+        self.api_resources.insert(key, api_resource.clone());
+        api_resource
+    }
+
+    async fn parents(
+        &mut self,
+        owner: &OwnerReference,
+        ns_name: &str,
+    ) -> Result<Vec<OwnerReference>, anyhow::Error> {
+        if let Some(parents) = self.parents.get(&owner.uid) {
+            return Ok(parents.clone());
+        }
+        let parents = match self.api_resource(&owner.api_version, &owner.kind).await {
+            Some(api_resource) => {
+                let api: Api<DynamicObject> =
+                    Api::namespaced_with(self.client.clone(), ns_name, &api_resource);
+                api.get_opt(&owner.name)
+                    .await?
+                    .map(|object| object.owner_references().to_vec())
+                    .unwrap_or_default()
+            }
+            None => vec![],
+        };
+        self.parents.insert(owner.uid.clone(), parents.clone());
+        Ok(parents)
+    }
+
+    /// Whether any owner in the chain starting at `owners` has one of the `targets` UIDs.
+    async fn is_owned_by(
+        &mut self,
+        owners: &[OwnerReference],
+        ns_name: &str,
+        targets: &HashSet<String>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut queue = owners.to_vec();
+        let mut seen = HashSet::new();
+        while let Some(owner) = queue.pop() {
+            if targets.contains(&owner.uid) {
+                return Ok(true);
+            }
+            if seen.insert(owner.uid.clone()) {
+                queue.extend(self.parents(&owner, ns_name).await?);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Lists the pods, as `(namespace, name)` pairs, whose owner references lead back to
+/// the `gvk` object called `name`, so any controller, including custom resources, can
+/// be followed. Without a namespace, objects of that name in all namespaces are used.
+///
+/// Returns `None` if no such object exists.
+pub async fn get_pod_list_for_owner(
+    client: &kube::Client,
+    gvk: &GroupVersionKind,
+    name: &str,
+    ns_name: Option<&str>,
+) -> Result<Option<Vec<(String, String)>>, anyhow::Error> {
+    let (api_resource, _) = kube::discovery::pinned_kind(client, gvk).await?;
+    let targets: Vec<DynamicObject> = match ns_name {
+        Some(ns_name) => {
+            let api: Api<DynamicObject> =
+                Api::namespaced_with(client.clone(), ns_name, &api_resource);
+            api.get_opt(name).await?.into_iter().collect()
+        }
+        None => {
+            let api: Api<DynamicObject> = Api::all_with(client.clone(), &api_resource);
+            let list_params = ListParams::default().fields(&format!("metadata.name={}", name));
+            api.list(&list_params).await?.items
+        }
+    };
+    if targets.is_empty() {
+        return Ok(None);
+    }
+    let target_uids: HashSet<String> = targets.iter().filter_map(ResourceExt::uid).collect();
+
+    let pod_api: Api<Pod> = match ns_name {
+        Some(ns_name) => Api::namespaced(client.clone(), ns_name),
+        None => Api::all(client.clone()),
+    };
+    let mut walker = OwnerWalker {
+        client,
+        api_resources: HashMap::new(),
+        parents: HashMap::new(),
+    };
+    let mut pods = vec![];
+    for pod in pod_api.list(&ListParams::default()).await? {
+        let pod_ns = ns_name
+            .map(String::from)
+            .or_else(|| ResourceExt::namespace(&pod))
+            .unwrap_or_default();
+        if walker
+            .is_owned_by(pod.owner_references(), &pod_ns, &target_uids)
+            .await?
+        {
+            pods.push((pod_ns, pod.name_any()));
+        }
+    }
+    Ok(Some(pods))
+}
+
+/// Settings applied to every pod log stream.
+#[derive(Clone, Debug, Default)]
+pub struct StreamOptions {
+    pub follow: bool,
+    /// Emit a marker line when a followed container restarts.
+    pub annotate_restarts: bool,
+    /// Name or regex of the container to stream instead of all of the pod's containers.
+    pub container: Option<String>,
+    /// Stream only the pod's first container instead of all of them.
+    pub first_container: bool,
+    /// Tag each line with its container even if only one container of a pod is streamed.
+    pub tag_containers: bool,
+    /// Also stream init containers, one after another before the main containers.
+    pub init_containers: bool,
+    /// Also stream ephemeral containers added by `kubectl debug`.
+    pub ephemeral_containers: bool,
+    /// Only return logs newer than this many seconds.
+    pub since_seconds: Option<i64>,
+    /// Only return logs written after this time.
+    pub since_time: Option<DateTime<Utc>>,
+    /// Show the RFC3339 timestamp the API server prefixes each line with.
+    pub timestamps: bool,
+    /// Name of the kubeconfig context the pods belong to, shown when streaming several
+    /// clusters.
+    pub context: Option<String>,
+    /// How often API requests that fail transiently are retried, see `retry`.
+    pub api_retries: u32,
+    /// Ends the streams between two lines once cancelled, e.g. on Ctrl-C.
+    pub shutdown: CancellationToken,
+    /// How long to wait for a pending container to start, or indefinitely if `None`.
+    pub wait_timeout: Option<Duration>,
+}
+
+impl StreamOptions {
+    /// The container to tag lines with, if several containers of a pod may be streamed.
+    fn container_tag(&self, container: &str) -> Option<String> {
+        (self.tag_containers || self.init_containers || self.ephemeral_containers)
+            .then(|| container.to_string())
+    }
+}
+
+/// Names the pod's init containers that run to completion, or with `sidecars` those with
+/// restart policy `Always` that keep running alongside the main containers.
+fn init_containers(pod: &Pod, sidecars: bool) -> Vec<String> {
+    pod.spec
+        .as_ref()
+        .and_then(|spec| spec.init_containers.as_ref())
+        .into_iter()
+        .flatten()
+        .filter(|container| (container.restart_policy.as_deref() == Some("Always")) == sidecars)
+        .map(|container| container.name.clone())
+        .collect()
+}
+
+/// Picks the containers to stream: the first one matching `options.container`, the pod's
+/// first container with `options.first_container`, or otherwise all of them.
+fn select_containers(pod: &Pod, options: &StreamOptions) -> Result<Vec<String>, anyhow::Error> {
+    let containers = pod
+        .spec
+        .as_ref()
+        .map(|spec| spec.containers.as_slice())
+        .unwrap_or_default();
+    if options.container.is_none() && !options.first_container && !containers.is_empty() {
+        return Ok(containers
+            .iter()
+            .map(|container| container.name.clone())
+            .collect());
+    }
+    let container = match &options.container {
+        Some(pattern) => {
+            let regex = util::pod_name_regex(pattern)?;
+            containers.iter().find(|container| match &regex {
+                Some(regex) => regex.is_match(&container.name),
+                None => &container.name == pattern,
+            })
+        }
+        None => containers.first(),
+    };
+    container
+        .map(|container| vec![container.name.clone()])
+        .ok_or_else(|| match &options.container {
+            Some(pattern) => anyhow::anyhow!(
+                "Pod {} has no container matching {}",
+                pod.name_any(),
+                pattern
+            ),
+            None => anyhow::anyhow!("Pod {} has no containers", pod.name_any()),
+        })
+}
+
+/// Tells whether an API request failed in a way that may go away by itself, like when the
+/// API server is overloaded, restarting or unreachable for a moment.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|error| match error.downcast_ref::<kube::Error>() {
+            Some(kube::Error::Api(response)) => response.code == 429 || response.code >= 500,
+            Some(kube::Error::HyperError(_) | kube::Error::Service(_)) => true,
+            _ => false,
+        })
+}
+
+/// Sends an API request until it succeeds, fails for good, or failed transiently
+/// `retries` more times, waiting longer before each retry.
+pub async fn retry<T, E, F, Fut>(retries: u32, mut request: F) -> anyhow::Result<T>
+where
+    E: Into<anyhow::Error>,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await.map_err(Into::into) {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                tracing::debug!(attempt, error = %e, "Retrying API request after a transient error");
+                tokio::time::sleep(util::backoff(attempt, MAX_RETRY_BACKOFF)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Tells whether an API request failed because what it asked for does not exist.
+pub fn is_not_found(error: &anyhow::Error) -> bool {
+    error.chain().any(|error| {
+        matches!(
+            error.downcast_ref::<kube::Error>(),
+            Some(kube::Error::Api(response)) if response.code == 404
+        )
+    })
+}
+
+fn container_status<'p>(pod: &'p Pod, container: &str) -> Option<&'p ContainerStatus> {
+    pod.status
+        .as_ref()
+        .into_iter()
+        .flat_map(|status| {
+            let statuses = status.container_statuses.iter().flatten();
+            statuses
+                .chain(status.init_container_statuses.iter().flatten())
+                .chain(status.ephemeral_container_statuses.iter().flatten())
+        })
+        .find(|status| status.name == container)
+}
+
+fn restart_count(pod: &Pod, container: &str) -> i32 {
+    container_status(pod, container).map_or(0, |status| status.restart_count)
+}
+
+/// The exit code of the previous instance of a container, e.g. 137 when it was killed
+/// for running out of memory.
+fn last_exit_code(pod: &Pod, container: &str) -> Option<i32> {
+    container_status(pod, container)?
+        .last_state
+        .as_ref()?
+        .terminated
+        .as_ref()
+        .map(|terminated| terminated.exit_code)
+}
+
+/// Tells whether a container of a pending pod is yet to start, e.g. while its image is
+/// pulled, so it has no logs to read yet.
+fn is_waiting(pod: &Pod, container: &str) -> bool {
+    let pending = pod
+        .status
+        .as_ref()
+        .is_some_and(|status| status.phase.as_deref() == Some("Pending"));
+    let started = container_status(pod, container)
+        .and_then(|status| status.state.as_ref())
+        .is_some_and(|state| state.running.is_some() || state.terminated.is_some());
+    pending && !started
+}
+
+/// Watches a pod until its container started, returning the pod then, or `None` if the
+/// pod was deleted or the streams are shut down first.
+async fn wait_for_container(
+    pods: &Api<Pod>,
+    pod_name: &str,
+    container: &str,
+    options: &StreamOptions,
+) -> Result<Option<Pod>, anyhow::Error> {
+    let started = await_condition(pods.clone(), pod_name, |pod: Option<&Pod>| match pod {
+        Some(pod) => !is_waiting(pod, container),
+        None => true,
+    });
+    let timeout = options.wait_timeout.unwrap_or(Duration::MAX);
+    tokio::select! {
+        _ = options.shutdown.cancelled() => Ok(None),
+        result = tokio::time::timeout(timeout, started) => {
+            let pod = result.map_err(|_| {
+                anyhow::anyhow!(
+                    "Container {} of pod {} did not start within {}s",
+                    container,
+                    pod_name,
+                    timeout.as_secs()
+                )
+            })??;
+            Ok(pod)
+        }
+    }
+}
+
+/// Tells whether a container that stopped will be started again, so there will be more
+/// logs to follow.
+fn will_restart(pod: &Pod, container: &str) -> bool {
+    let phase = pod
+        .status
+        .as_ref()
+        .and_then(|status| status.phase.as_deref());
+    if pod.metadata.deletion_timestamp.is_some() || matches!(phase, Some("Succeeded" | "Failed")) {
+        return false;
+    }
+    let failed = container_status(pod, container)
+        .and_then(|status| status.state.as_ref()?.terminated.as_ref())
+        .map(|terminated| terminated.exit_code)
+        != Some(0);
+    let spec = pod.spec.as_ref();
+    let ephemeral = spec
+        .and_then(|spec| spec.ephemeral_containers.as_ref())
+        .is_some_and(|containers| containers.iter().any(|c| c.name == container));
+    // Init containers other than sidecars only run again if they failed.
+    let runs_once = spec
+        .and_then(|spec| spec.init_containers.as_ref())
+        .is_some_and(|containers| {
+            containers
+                .iter()
+                .any(|c| c.name == container && c.restart_policy.as_deref() != Some("Always"))
+        });
+    match spec.and_then(|spec| spec.restart_policy.as_deref()) {
+        _ if ephemeral => false,
+        Some("Never") => false,
+        Some("OnFailure") => failed,
+        _ if runs_once => failed,
+        _ => true,
+    }
+}
+
+/// Watches a pod until a new instance of its container runs, returning the pod then, or
+/// `None` if the pod was deleted, the container will not restart or the streams are shut
+/// down first. Crash looping containers can take minutes to come back, so there is no
+/// timeout.
+async fn wait_for_restart(
+    pods: &Api<Pod>,
+    pod: Pod,
+    container: &str,
+    restarts: i32,
+    options: &StreamOptions,
+) -> Result<Option<Pod>, anyhow::Error> {
+    let restarted =
+        |pod: &Pod| is_running(pod, container) && restart_count(pod, container) > restarts;
+    if restarted(&pod) {
+        return Ok(Some(pod));
+    }
+    if !will_restart(&pod, container) {
+        return Ok(None);
+    }
+    let pod_name = pod.name_any();
+    let changed = await_condition(pods.clone(), &pod_name, |pod: Option<&Pod>| match pod {
+        Some(pod) => restarted(pod) || !will_restart(pod, container),
+        None => true,
+    });
+    tokio::select! {
+        _ = options.shutdown.cancelled() => Ok(None),
+        pod = changed => Ok(pod?.filter(|pod| restarted(pod))),
+    }
+}
+
+/// Waits until a `T` called `name` exists in one of `namespaces`, or in any namespace if
+/// none are given. Returns false if shut down first.
+pub async fn wait_for_resource<T>(
+    client: &kube::Client,
+    name: &str,
+    namespaces: &[String],
+    shutdown: &CancellationToken,
+) -> Result<bool, anyhow::Error>
+where
+    T: Resource<Scope = NamespaceResourceScope>
+        + Clone
+        + for<'a> Deserialize<'a>
+        + Debug
+        + Send
+        + 'static
+        + k8s_openapi::Metadata<Ty = ObjectMeta>,
+{
+    let apis: Vec<Api<T>> = match namespaces.is_empty() {
+        true => vec![Api::all(client.clone())],
+        false => namespaces
+            .iter()
+            .map(|namespace| Api::namespaced(client.clone(), namespace))
+            .collect(),
+    };
+    let created = future::select_ok(apis.into_iter().map(|api| {
+        Box::pin(await_condition(api, name, |object: Option<&T>| {
+            object.is_some()
+        }))
+    }));
+    tokio::select! {
+        _ = shutdown.cancelled() => Ok(false),
+        created = created => created.map(|_| true).map_err(anyhow::Error::from),
+    }
+}
+
+fn is_running(pod: &Pod, container: &str) -> bool {
+    container_status(pod, container)
+        .and_then(|status| status.state.as_ref())
+        .is_some_and(|state| state.running.is_some())
+}
+
+/// Streams the logs of a container. Lines are always requested with their timestamps so
+/// that `last_seen` can track the newest one; if it is set already, the stream resumes
+/// after it instead of starting over.
+#[allow(clippy::too_many_arguments)]
 pub async fn stream_single_pod_logs(
     client: &kube::Client,
     pod_name: &str,
+    container: &str,
     ns_name: &str,
-    follow: &bool,
-    filter: &str,
+    options: &StreamOptions,
+    resource: Option<&str>,
+    output: &mpsc::Sender<LogLine>,
+    last_seen: &mut Option<DateTime<Utc>>,
 ) -> Result<(), anyhow::Error> {
     let pods: Api<Pod> = Api::namespaced(client.clone(), ns_name);
-    let pod = pods.get(pod_name).await?;
-
-    let spec = &pod.spec.clone().unwrap();
-    let container = &spec.containers.first();
-    let name = &container.unwrap().name;
+    let resume_after = *last_seen;
+    tracing::debug!(
+        namespace = ns_name,
+        pod = pod_name,
+        container,
+        resume_after = ?resume_after,
+        "Opening log stream"
+    );
     let mut logs = pods
         .log_stream(
             pod_name,
             &LogParams {
-                follow: *follow,
+                follow: options.follow,
                 pretty: true,
-                container: Some(name.clone()),
+                container: Some(container.to_string()),
+                since_seconds: options.since_seconds.filter(|_| resume_after.is_none()),
+                since_time: resume_after.or(options.since_time),
+                timestamps: true,
                 ..LogParams::default()
             },
         )
         .await?
         .lines();
+    let _stream = METRICS.stream_started(ns_name, pod_name, container);
 
-    let color = util::get_rnd_color();
+    loop {
+        let line = tokio::select! {
+            biased;
+            _ = options.shutdown.cancelled() => break,
+            line = logs.try_next() => match line? {
+                Some(line) => line,
+                None => break,
+            },
+        };
+        let parsed = line.split_once(' ').and_then(|(timestamp, rest)| {
+            let parsed = DateTime::parse_from_rfc3339(timestamp).ok()?;
+            Some((parsed.with_timezone(&Utc), timestamp, rest))
+        });
+        let (timestamp, line) = match parsed {
+            Some((parsed, timestamp, rest)) => {
+                // Since times only have second precision, so a resumed stream repeats the
+                // lines of the second it resumes in.
+                if resume_after.is_some_and(|resume_after| parsed <= resume_after) {
+                    continue;
+                }
+                *last_seen = Some(parsed);
+                (Some(timestamp.to_string()), rest.to_string())
+            }
+            None => (None, line),
+        };
+        METRICS.line_received(ns_name, pod_name, container, &line);
+        let log_line = LogLine {
+            context: options.context.clone(),
+            namespace: ns_name.to_string(),
+            pod: pod_name.to_string(),
+            container: options.container_tag(container),
+            resource: resource.map(String::from),
+            timestamp: timestamp.filter(|_| options.timestamps),
+            line,
+            restart: false,
+        };
+        if output.send(log_line).await.is_err() {
+            break;
+        }
+    }
+    tracing::debug!(
+        namespace = ns_name,
+        pod = pod_name,
+        container,
+        "Log stream ended"
+    );
 
-    while let Some(line) = logs.try_next().await? {
-        let pretty_pod_name = &pod.name_any().truecolor(color.r, color.g, color.b);
-        if (!filter.is_empty() && line.contains(filter)) || filter.is_empty() {
-            println!("{} {}", pretty_pod_name, line);
+    Ok(())
+}
+
+/// Streams a pod's logs, re-attaching whenever a container restarts while following.
+pub async fn follow_pod_logs(
+    client: &kube::Client,
+    pod_name: &str,
+    ns_name: &str,
+    options: &StreamOptions,
+    resource: Option<&str>,
+    output: &mpsc::Sender<LogLine>,
+) -> Result<(), anyhow::Error> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), ns_name);
+    let mut pod = retry(options.api_retries, || pods.get(pod_name)).await?;
+    let mut containers = select_containers(&pod, options)?;
+    let tagged;
+    let options = match containers.len() > 1 && !options.tag_containers {
+        true => {
+            // Lines of pods with several containers are told apart by their container.
+            tagged = StreamOptions {
+                tag_containers: true,
+                ..options.clone()
+            };
+            &tagged
         }
+        false => options,
+    };
+    if options.init_containers {
+        // Init containers run one at a time, so each is drained before the next starts.
+        for container in init_containers(&pod, false) {
+            follow_container_logs(client, pod.clone(), &container, options, resource, output)
+                .await?;
+        }
+        pod = retry(options.api_retries, || pods.get(pod_name)).await?;
+        containers.extend(init_containers(&pod, true));
     }
 
+    future::try_join_all(containers.iter().map(|container| {
+        follow_container_logs(client, pod.clone(), container, options, resource, output)
+    }))
+    .await?;
     Ok(())
 }
 
+async fn follow_container_logs(
+    client: &kube::Client,
+    mut pod: Pod,
+    container: &str,
+    options: &StreamOptions,
+    resource: Option<&str>,
+    output: &mpsc::Sender<LogLine>,
+) -> Result<(), anyhow::Error> {
+    let pod_name = pod.name_any();
+    let ns_name = ResourceExt::namespace(&pod).unwrap_or_default();
+    let pods: Api<Pod> = Api::namespaced(client.clone(), &ns_name);
+
+    if is_waiting(&pod, container) {
+        METRICS.set_state(&ns_name, &pod_name, container, StreamState::Waiting);
+        tracing::debug!(
+            namespace = ns_name,
+            pod = pod_name,
+            container,
+            "Waiting for container to start"
+        );
+        pod = match wait_for_container(&pods, &pod_name, container, options).await? {
+            Some(started) => started,
+            None => return Ok(()),
+        };
+    }
+
+    let mut last_seen = None;
+    let mut attempt = 0;
+    loop {
+        let restarts = restart_count(&pod, container);
+        let seen_before = last_seen;
+
+        let result = stream_single_pod_logs(
+            client,
+            &pod_name,
+            container,
+            &ns_name,
+            options,
+            resource,
+            output,
+            &mut last_seen,
+        )
+        .await;
+        if !options.follow || output.is_closed() || options.shutdown.is_cancelled() {
+            return result;
+        }
+
+        let current = match retry(options.api_retries, || pods.get_opt(&pod_name)).await? {
+            Some(current) if current.metadata.deletion_timestamp.is_none() => current,
+            _ => return result,
+        };
+        if is_running(&current, container) && restart_count(&current, container) <= restarts {
+            METRICS.reconnected(&ns_name, &pod_name, container);
+            tracing::debug!(
+                namespace = ns_name,
+                pod = pod_name,
+                container,
+                attempt,
+                "Resuming log stream of a running container"
+            );
+            // The stream ended while the container kept running, e.g. because the node
+            // or the connection to it went away, so resume after the last line seen.
+            attempt = if last_seen == seen_before {
+                attempt + 1
+            } else {
+                1
+            };
+            tokio::select! {
+                _ = options.shutdown.cancelled() => return result,
+                _ = tokio::time::sleep(util::backoff(attempt, MAX_RECONNECT_BACKOFF)) => {}
+            }
+            pod = current;
+            continue;
+        }
+        // Until the new instance runs, e.g. during a crash loop back-off, the kubelet
+        // serves the logs of the one that stopped, so they would be printed again.
+        if !is_running(&current, container) {
+            METRICS.set_state(&ns_name, &pod_name, container, StreamState::Waiting);
+            tracing::debug!(
+                namespace = ns_name,
+                pod = pod_name,
+                container,
+                "Waiting for container to restart"
+            );
+        }
+        pod = match wait_for_restart(&pods, current, container, restarts, options).await? {
+            Some(restarted) => restarted,
+            None => return result,
+        };
+        last_seen = None;
+        attempt = 0;
+        METRICS.reconnected(&ns_name, &pod_name, container);
+        tracing::debug!(
+            namespace = ns_name,
+            pod = pod_name,
+            container,
+            restarts = restart_count(&pod, container),
+            "Re-attaching to a restarted container"
+        );
+
+        if options.annotate_restarts {
+            let exit_code = last_exit_code(&pod, container)
+                .map_or_else(|| "unknown".to_string(), |code| code.to_string());
+            let marker = format!(
+                "--- {}/{} restarted (count {}, exit code {}) ---",
+                pod_name,
+                container,
+                restart_count(&pod, container),
+                exit_code
+            );
+            let log_line = LogLine {
+                context: options.context.clone(),
+                namespace: ns_name.clone(),
+                pod: pod_name.clone(),
+                container: options.container_tag(container),
+                resource: resource.map(String::from),
+                timestamp: None,
+                line: marker,
+                restart: true,
+            };
+            if output.send(log_line).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Streams the logs of a single container of a pod, re-attaching whenever it restarts
+/// while following.
+pub async fn follow_single_container_logs(
+    client: &kube::Client,
+    pod_name: &str,
+    container: &str,
+    ns_name: &str,
+    options: &StreamOptions,
+    resource: Option<&str>,
+    output: &mpsc::Sender<LogLine>,
+) -> Result<(), anyhow::Error> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), ns_name);
+    let pod = retry(options.api_retries, || pods.get(pod_name)).await?;
+    follow_container_logs(client, pod, container, options, resource, output).await
+}
+
+/// Names the containers of a pod that `follow_pod_logs` would stream, for `--dry-run`.
+pub async fn containers_to_stream(
+    client: &kube::Client,
+    pod_name: &str,
+    ns_name: &str,
+    options: &StreamOptions,
+) -> Result<Vec<String>, anyhow::Error> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), ns_name);
+    let pod = retry(options.api_retries, || pods.get(pod_name)).await?;
+    let mut containers = vec![];
+    if options.init_containers {
+        containers.extend(init_containers(&pod, false));
+    }
+    containers.extend(select_containers(&pod, options)?);
+    if options.init_containers {
+        containers.extend(init_containers(&pod, true));
+    }
+    if options.ephemeral_containers {
+        containers.extend(get_ephemeral_containers(client, pod_name, ns_name).await?);
+    }
+    Ok(containers)
+}
+
+/// Names the ephemeral containers of a pod that have started, so their logs can be read.
+pub async fn get_ephemeral_containers(
+    client: &kube::Client,
+    pod_name: &str,
+    ns_name: &str,
+) -> Result<Vec<String>, anyhow::Error> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), ns_name);
+    let Some(pod) = pods.get_opt(pod_name).await? else {
+        return Ok(vec![]);
+    };
+    let containers = pod
+        .status
+        .and_then(|status| status.ephemeral_container_statuses)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|status| {
+            status
+                .state
+                .as_ref()
+                .is_some_and(|state| state.running.is_some() || state.terminated.is_some())
+        })
+        .map(|status| status.name)
+        .collect();
+    Ok(containers)
+}
+
+/// A change to a watched pod.
+#[derive(Debug, PartialEq)]
+pub enum PodChange {
+    /// The pod's containers started, so its logs can be read. Reported again whenever
+    /// the pod changes, e.g. when an ephemeral container is added.
+    Started {
+        namespace: String,
+        pod: String,
+    },
+    Deleted {
+        namespace: String,
+        pod: String,
+    },
+}
+
+/// The status of a pod shown in the TUI's pod list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PodStatus {
+    /// The phase of the pod, or why a container is waiting, like `CrashLoopBackOff`.
+    pub phase: String,
+    /// The restarts of all its containers.
+    pub restarts: i32,
+    pub created: Option<DateTime<Utc>>,
+}
+
+impl PodStatus {
+    pub fn of(pod: &Pod) -> Self {
+        let status = pod.status.as_ref();
+        let containers = status
+            .and_then(|status| status.container_statuses.as_deref())
+            .unwrap_or_default();
+        let waiting = containers.iter().find_map(|container| {
+            container
+                .state
+                .as_ref()?
+                .waiting
+                .as_ref()?
+                .reason
+                .clone()
+                .filter(|reason| reason != "ContainerCreating")
+        });
+        let phase = match pod.metadata.deletion_timestamp {
+            Some(_) => Some("Terminating".to_string()),
+            None => waiting.or_else(|| status.and_then(|status| status.phase.clone())),
+        };
+        PodStatus {
+            phase: phase.unwrap_or_else(|| "Unknown".to_string()),
+            restarts: containers
+                .iter()
+                .map(|container| container.restart_count)
+                .sum(),
+            created: pod.metadata.creation_timestamp.as_ref().map(|time| time.0),
+        }
+    }
+}
+
+fn has_started(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.phase.as_deref())
+        .is_some_and(|phase| phase != "Pending")
+}
+
+/// Watches the pods matching a label and a field selector, in all namespaces without a
+/// namespace. Errors are yielded while the watch is retried with a backoff.
+pub fn watch_pods(
+    client: &kube::Client,
+    ns_name: Option<&str>,
+    labels: &str,
+    fields: &str,
+) -> impl Stream<Item = Result<PodChange, watcher::Error>> {
+    let api: Api<Pod> = match ns_name {
+        Some(ns_name) => Api::namespaced(client.clone(), ns_name),
+        None => Api::all(client.clone()),
+    };
+    let mut config = watcher::Config::default();
+    if !labels.is_empty() {
+        config = config.labels(labels);
+    }
+    if !fields.is_empty() {
+        config = config.fields(fields);
+    }
+    watcher(api, config).default_backoff().filter_map(|event| {
+        let change = event.map(|event| match event {
+            watcher::Event::Apply(pod) | watcher::Event::InitApply(pod) if has_started(&pod) => {
+                Some(PodChange::Started {
+                    namespace: ResourceExt::namespace(&pod).unwrap_or_default(),
+                    pod: pod.name_any(),
+                })
+            }
+            watcher::Event::Delete(pod) => Some(PodChange::Deleted {
+                namespace: ResourceExt::namespace(&pod).unwrap_or_default(),
+                pod: pod.name_any(),
+            }),
+            _ => None,
+        });
+        future::ready(change.transpose())
+    })
+}
+
+/// Watches the status of the pods in a namespace, or in all namespaces without one,
+/// yielding `None` for deleted pods. Errors are yielded while the watch is retried with a
+/// backoff.
+pub fn watch_pod_statuses(
+    client: &kube::Client,
+    ns_name: Option<&str>,
+) -> impl Stream<Item = Result<(String, String, Option<PodStatus>), watcher::Error>> {
+    let api: Api<Pod> = match ns_name {
+        Some(ns_name) => Api::namespaced(client.clone(), ns_name),
+        None => Api::all(client.clone()),
+    };
+    watcher(api, watcher::Config::default())
+        .default_backoff()
+        .filter_map(|event| {
+            let change = event.map(|event| match event {
+                watcher::Event::Apply(pod) | watcher::Event::InitApply(pod) => Some((
+                    ResourceExt::namespace(&pod).unwrap_or_default(),
+                    pod.name_any(),
+                    Some(PodStatus::of(&pod)),
+                )),
+                watcher::Event::Delete(pod) => Some((
+                    ResourceExt::namespace(&pod).unwrap_or_default(),
+                    pod.name_any(),
+                    None,
+                )),
+                _ => None,
+            });
+            future::ready(change.transpose())
+        })
+}
+
+pub async fn is_pod_terminating(
+    client: &kube::Client,
+    pod_name: &str,
+    ns_name: &str,
+) -> Result<bool, anyhow::Error> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), ns_name);
+    let pod = pods.get_opt(pod_name).await?;
+    Ok(pod.is_none_or(|pod| pod.metadata.deletion_timestamp.is_some()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util;
     use k8s_openapi::api::apps::v1::StatefulSet;
+    use k8s_openapi::serde_json::json;
     use kube::Client;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn test_get_pod_list() {
@@ -127,8 +1282,8 @@ mod tests {
         let statefulset = statefulset_api.get(statefulset_name).await;
 
         let spec = statefulset.unwrap().spec.unwrap();
-        let match_labels = spec.selector.match_labels.unwrap();
-        let pod_list_result = get_pod_list(&client, "statefulset", match_labels).await;
+        let labels = util::label_selector(&spec.selector).unwrap();
+        let pod_list_result = get_pod_list(&client, "statefulset", &labels).await;
         let pod_list = pod_list_result.unwrap();
         assert_eq!(pod_list.first().unwrap(), expected_pod_list_item);
     }
@@ -145,4 +1300,731 @@ mod tests {
             get_pod_list_for_resource::<StatefulSet>(&client, resource_name, ns_name).await;
         assert_eq!(result.unwrap().first().unwrap(), expected_pod_list_item);
     }
+
+    #[tokio::test]
+    async fn test_watch_pods() {
+        let pod = |name: &str, phase: &str| {
+            let mut pod = test_util::pod(name, &["app"]);
+            pod["status"] = json!({ "phase": phase });
+            pod
+        };
+        let running = pod("web-1", "Running");
+        let pending = pod("web-2", "Pending");
+        let started = pod("web-2", "Running");
+        let (client, _) = test_util::fake_client(move |req| {
+            if req.uri().query().unwrap_or_default().contains("watch=true") {
+                let events = [("MODIFIED", &started), ("DELETED", &running)]
+                    .map(|(kind, pod)| json!({ "type": kind, "object": pod }).to_string() + "\n");
+                return test_util::open_response(&events.concat());
+            }
+            test_util::json_response(test_util::list(
+                "PodList",
+                vec![running.clone(), pending.clone()],
+            ))
+        });
+
+        let changes: Vec<PodChange> = watch_pods(&client, Some("default"), "app=web", "")
+            .take(3)
+            .try_collect()
+            .await
+            .unwrap();
+
+        let started = |pod: &str| PodChange::Started {
+            namespace: "default".to_string(),
+            pod: pod.to_string(),
+        };
+        assert_eq!(
+            changes,
+            vec![
+                started("web-1"),
+                started("web-2"),
+                PodChange::Deleted {
+                    namespace: "default".to_string(),
+                    pod: "web-1".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_pod_terminating() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/running" => {
+                test_util::json_response(test_util::pod("running", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/terminating" => {
+                let mut pod = test_util::pod("terminating", &["app"]);
+                pod["metadata"]["deletionTimestamp"] = json!("2024-01-01T00:00:00Z");
+                test_util::json_response(pod)
+            }
+            _ => test_util::not_found(),
+        });
+
+        assert!(!is_pod_terminating(&client, "running", "default")
+            .await
+            .unwrap());
+        assert!(is_pod_terminating(&client, "terminating", "default")
+            .await
+            .unwrap());
+        assert!(is_pod_terminating(&client, "deleted", "default")
+            .await
+            .unwrap());
+    }
+
+    fn job(name: &str, owner: &str, created: &str, active: i32) -> k8s_openapi::serde_json::Value {
+        json!({
+            "metadata": {
+                "name": name,
+                "creationTimestamp": created,
+                "ownerReferences": [{
+                    "apiVersion": "batch/v1",
+                    "kind": "CronJob",
+                    "name": owner,
+                    "uid": owner
+                }]
+            },
+            "spec": {
+                "selector": { "matchLabels": { "batch.kubernetes.io/job-name": name } },
+                "template": {}
+            },
+            "status": { "active": active }
+        })
+    }
+
+    fn cronjob_client() -> (Client, test_util::Requests) {
+        test_util::fake_client(|req| match req.uri().path() {
+            "/apis/batch/v1/namespaces/default/jobs" => test_util::json_response(test_util::list(
+                "JobList",
+                vec![
+                    job("nightly-1", "nightly", "2024-01-01T00:00:00Z", 1),
+                    job("nightly-2", "nightly", "2024-01-02T00:00:00Z", 1),
+                    job("nightly-3", "nightly", "2024-01-03T00:00:00Z", 0),
+                    job("hourly-1", "hourly", "2024-01-04T00:00:00Z", 1),
+                ],
+            )),
+            "/api/v1/namespaces/default/pods" => {
+                let query = req.uri().query().unwrap_or_default();
+                let job_name = query.rsplit("%3D").next().unwrap_or_default();
+                test_util::json_response(test_util::list(
+                    "PodList",
+                    vec![test_util::pod(&format!("{}-pod", job_name), &["app"])],
+                ))
+            }
+            _ => test_util::not_found(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_get_jobs_for_cronjob() {
+        let (client, _) = cronjob_client();
+
+        let jobs = get_jobs_for_cronjob(&client, "nightly", "default", false)
+            .await
+            .unwrap();
+        let names: Vec<_> = jobs.iter().map(|job| job.name_any()).collect();
+        assert_eq!(names, vec!["nightly-1", "nightly-2"]);
+
+        let jobs = get_jobs_for_cronjob(&client, "nightly", "default", true)
+            .await
+            .unwrap();
+        let names: Vec<_> = jobs.iter().map(|job| job.name_any()).collect();
+        assert_eq!(names, vec!["nightly-3"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_pod_list_for_cronjob() {
+        let (client, _) = cronjob_client();
+
+        let pods = get_pod_list_for_cronjob(&client, "nightly", "default", false)
+            .await
+            .unwrap();
+        assert_eq!(pods, vec!["nightly-1-pod", "nightly-2-pod"]);
+
+        let pods = get_pod_list_for_cronjob(&client, "hourly", "default", true)
+            .await
+            .unwrap();
+        assert_eq!(pods, vec!["hourly-1-pod"]);
+    }
+
+    #[tokio::test]
+    async fn test_follow_pod_logs_annotates_restart() {
+        let pod = |state: k8s_openapi::serde_json::Value, restarts: usize| {
+            let mut pod = test_util::pod("web-0", &["app"]);
+            pod["status"] = json!({
+                "phase": "Running",
+                "containerStatuses": [{
+                    "name": "app",
+                    "image": "busybox",
+                    "imageID": "",
+                    "ready": true,
+                    "restartCount": restarts,
+                    "state": state,
+                    "lastState": if restarts > 0 {
+                        json!({ "terminated": { "exitCode": 137 } })
+                    } else {
+                        json!({})
+                    }
+                }]
+            });
+            pod
+        };
+        let running = move |restarts| pod(json!({ "running": {} }), restarts);
+        // The instance that crashed is counted once the next one is created.
+        let crash_looping = move |restarts| {
+            let mut pod = pod(
+                json!({ "waiting": { "reason": "CrashLoopBackOff" } }),
+                restarts,
+            );
+            pod["status"]["containerStatuses"][0]["lastState"] =
+                json!({ "terminated": { "exitCode": 137 } });
+            pod
+        };
+
+        let log_requests = Arc::new(AtomicUsize::new(0));
+        let counter = log_requests.clone();
+        let (client, _) = test_util::fake_client(move |req| {
+            let watching = req.uri().query().unwrap_or_default().contains("watch=true");
+            let crashes = counter.load(Ordering::SeqCst);
+            match req.uri().path() {
+                "/api/v1/namespaces/default/pods" if watching => {
+                    let event = json!({ "type": "MODIFIED", "object": running(crashes) });
+                    test_util::open_response(&format!("{}\n", event))
+                }
+                "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                    "PodList",
+                    vec![crash_looping(crashes - 1)],
+                )),
+                "/api/v1/namespaces/default/pods/web-0" => match crashes {
+                    0 => test_util::json_response(running(0)),
+                    _ => test_util::json_response(crash_looping(crashes - 1)),
+                },
+                "/api/v1/namespaces/default/pods/web-0/log" => {
+                    match counter.fetch_add(1, Ordering::SeqCst) {
+                        0 => test_util::text_response("crashing\n"),
+                        1 => test_util::text_response("crashing again\n"),
+                        _ => test_util::open_response("recovered\n"),
+                    }
+                }
+                _ => test_util::not_found(),
+            }
+        });
+
+        let options = StreamOptions {
+            follow: true,
+            annotate_restarts: true,
+            ..StreamOptions::default()
+        };
+        let (tx, mut rx) = mpsc::channel(100);
+        let stream = follow_pod_logs(&client, "web-0", "default", &options, None, &tx);
+
+        let mut lines = vec![];
+        tokio::select! {
+            result = stream => panic!("stream ended: {:?}", result),
+            _ = tokio::time::timeout(Duration::from_secs(5), async {
+                while lines.len() < 5 {
+                    lines.push(rx.recv().await.unwrap());
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }) => {}
+        }
+
+        let text: Vec<&str> = lines.iter().map(|line| line.line.as_str()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "crashing",
+                "--- web-0/app restarted (count 1, exit code 137) ---",
+                "crashing again",
+                "--- web-0/app restarted (count 2, exit code 137) ---",
+                "recovered",
+            ]
+        );
+        let restarts: Vec<bool> = lines.iter().map(|line| line.restart).collect();
+        assert_eq!(restarts, vec![false, true, false, true, false]);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(log_requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_follow_pod_logs_waits_for_crash_looping_container() {
+        let pod = |state: k8s_openapi::serde_json::Value, restarts: usize| {
+            let mut pod = test_util::pod("web-0", &["app"]);
+            pod["status"] = json!({
+                "phase": "Running",
+                "containerStatuses": [{
+                    "name": "app",
+                    "image": "busybox",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": restarts,
+                    "state": state,
+                    "lastState": { "terminated": { "exitCode": 1 } }
+                }]
+            });
+            pod
+        };
+        let running = move |restarts| pod(json!({ "running": {} }), restarts);
+        let crash_looping = pod(json!({ "waiting": { "reason": "CrashLoopBackOff" } }), 0);
+
+        let log_requests = Arc::new(AtomicUsize::new(0));
+        let counter = log_requests.clone();
+        let (client, _) = test_util::fake_client(move |req| {
+            let watching = req.uri().query().unwrap_or_default().contains("watch=true");
+            match req.uri().path() {
+                "/api/v1/namespaces/default/pods" if watching => {
+                    let event = json!({ "type": "MODIFIED", "object": running(1) });
+                    test_util::open_response(&format!("{}\n", event))
+                }
+                "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                    "PodList",
+                    vec![crash_looping.clone()],
+                )),
+                "/api/v1/namespaces/default/pods/web-0" => match counter.load(Ordering::SeqCst) {
+                    0 => test_util::json_response(running(0)),
+                    _ => test_util::json_response(crash_looping.clone()),
+                },
+                "/api/v1/namespaces/default/pods/web-0/log" => {
+                    match counter.fetch_add(1, Ordering::SeqCst) {
+                        0 => test_util::text_response("crashing\n"),
+                        _ => test_util::open_response("recovered\n"),
+                    }
+                }
+                _ => test_util::not_found(),
+            }
+        });
+
+        let options = StreamOptions {
+            follow: true,
+            ..StreamOptions::default()
+        };
+        let (tx, mut rx) = mpsc::channel(100);
+        let stream = follow_pod_logs(&client, "web-0", "default", &options, None, &tx);
+
+        let mut lines = vec![];
+        tokio::select! {
+            result = stream => panic!("stream ended: {:?}", result),
+            _ = async {
+                while lines.len() < 2 {
+                    lines.push(rx.recv().await.unwrap().line);
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            } => {}
+        }
+
+        assert_eq!(lines, vec!["crashing", "recovered"]);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(log_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_follow_pod_logs_resumes_dropped_stream() {
+        let queries = Arc::new(std::sync::Mutex::new(vec![]));
+        let seen = queries.clone();
+        let (client, _) = test_util::fake_client(move |req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/web-0" => {
+                let mut pod = test_util::pod("web-0", &["app"]);
+                pod["status"] = json!({
+                    "containerStatuses": [{
+                        "name": "app",
+                        "image": "busybox",
+                        "imageID": "",
+                        "ready": true,
+                        "restartCount": 0,
+                        "state": { "running": {} }
+                    }]
+                });
+                test_util::json_response(pod)
+            }
+            "/api/v1/namespaces/default/pods/web-0/log" => {
+                let mut queries = seen.lock().unwrap();
+                queries.push(req.uri().query().unwrap_or_default().to_string());
+                match queries.len() {
+                    1 => test_util::text_response(
+                        "2024-01-01T00:00:01.5Z one\n2024-01-01T00:00:02.25Z two\n",
+                    ),
+                    _ => test_util::open_response(
+                        "2024-01-01T00:00:02.25Z two\n2024-01-01T00:00:03Z three\n",
+                    ),
+                }
+            }
+            _ => test_util::not_found(),
+        });
+
+        let options = StreamOptions {
+            follow: true,
+            ..StreamOptions::default()
+        };
+        let (tx, mut rx) = mpsc::channel(100);
+        let stream = follow_pod_logs(&client, "web-0", "default", &options, None, &tx);
+
+        let mut lines = vec![];
+        tokio::select! {
+            result = stream => panic!("stream ended: {:?}", result),
+            _ = tokio::time::timeout(Duration::from_secs(5), async {
+                while lines.len() < 3 {
+                    lines.push(rx.recv().await.unwrap());
+                }
+            }) => {}
+        }
+
+        let texts: Vec<_> = lines.iter().map(|line| line.line.as_str()).collect();
+        assert_eq!(texts, vec!["one", "two", "three"]);
+        assert!(lines.iter().all(|line| line.timestamp.is_none()));
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 2);
+        assert!(!queries[0].contains("sinceTime"));
+        assert!(queries[1].contains("sinceTime=2024-01-01T00%3A00%3A02Z"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let (client, _) = test_util::fake_client(move |req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/web-0" => {
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => test_util::unavailable(),
+                    _ => test_util::json_response(test_util::pod("web-0", &["app"])),
+                }
+            }
+            _ => {
+                counter.fetch_add(1, Ordering::SeqCst);
+                test_util::not_found()
+            }
+        });
+        let pods: Api<Pod> = Api::namespaced(client, "default");
+
+        let pod = retry(1, || pods.get("web-0")).await.unwrap();
+        assert_eq!(pod.name_any(), "web-0");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Errors that will not go away are not retried.
+        requests.store(0, Ordering::SeqCst);
+        assert!(retry(3, || pods.get("web-1")).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        requests.store(0, Ordering::SeqCst);
+        let error = retry(0, || pods.get("web-0")).await.unwrap_err();
+        assert!(is_transient(&error));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_follow_pod_logs_waits_for_pending_pod() {
+        let pod = |phase: &str| {
+            let mut pod = test_util::pod("web-0", &["app"]);
+            pod["status"] = json!({ "phase": phase });
+            pod
+        };
+        let fake_client = |starts: bool| {
+            test_util::fake_client(move |req| {
+                let watching = req.uri().query().unwrap_or_default().contains("watch=true");
+                match req.uri().path() {
+                    "/api/v1/namespaces/default/pods" if watching && starts => {
+                        let event = json!({ "type": "MODIFIED", "object": pod("Running") });
+                        test_util::open_response(&format!("{}\n", event))
+                    }
+                    "/api/v1/namespaces/default/pods" if watching => test_util::open_response(""),
+                    "/api/v1/namespaces/default/pods" => {
+                        test_util::json_response(test_util::list("PodList", vec![pod("Pending")]))
+                    }
+                    "/api/v1/namespaces/default/pods/web-0" => {
+                        test_util::json_response(pod("Pending"))
+                    }
+                    "/api/v1/namespaces/default/pods/web-0/log" => {
+                        test_util::text_response("hello\n")
+                    }
+                    _ => test_util::not_found(),
+                }
+            })
+        };
+        let options = StreamOptions {
+            wait_timeout: Some(Duration::from_secs(1)),
+            ..StreamOptions::default()
+        };
+
+        let (client, requests) = fake_client(true);
+        let (tx, mut rx) = mpsc::channel(100);
+        follow_pod_logs(&client, "web-0", "default", &options, None, &tx)
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap().line, "hello");
+        assert!(requests
+            .all()
+            .iter()
+            .any(|request| request.contains("fieldSelector=metadata.name%3Dweb-0")));
+
+        let (client, requests) = fake_client(false);
+        let error = follow_pod_logs(&client, "web-0", "default", &options, None, &tx)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Container app of pod web-0 did not start within 1s"
+        );
+        assert_eq!(
+            requests.count("/api/v1/namespaces/default/pods/web-0/log"),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_follow_pod_logs_streams_init_containers_first() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/web-0" => {
+                let mut pod = test_util::pod("web-0", &["app"]);
+                pod["spec"]["initContainers"] = json!([{ "name": "migrate", "image": "busybox" }]);
+                test_util::json_response(pod)
+            }
+            "/api/v1/namespaces/default/pods/web-0/log" => {
+                match req.uri().query().unwrap_or_default() {
+                    query if query.contains("container=migrate") => {
+                        test_util::text_response("migrating\n")
+                    }
+                    _ => test_util::text_response("serving\n"),
+                }
+            }
+            _ => test_util::not_found(),
+        });
+
+        let options = StreamOptions {
+            init_containers: true,
+            ..StreamOptions::default()
+        };
+        let (tx, mut rx) = mpsc::channel(100);
+        follow_pod_logs(&client, "web-0", "default", &options, None, &tx)
+            .await
+            .unwrap();
+
+        let first = rx.recv().await.unwrap();
+        assert_eq!(first.container.as_deref(), Some("migrate"));
+        assert_eq!(first.line, "migrating");
+        let second = rx.recv().await.unwrap();
+        assert_eq!(second.container.as_deref(), Some("app"));
+        assert_eq!(second.line, "serving");
+    }
+
+    #[tokio::test]
+    async fn test_get_ephemeral_containers() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/web-0" => {
+                let mut pod = test_util::pod("web-0", &["app"]);
+                pod["status"] = json!({
+                    "ephemeralContainerStatuses": [
+                        {
+                            "name": "debugger-1",
+                            "image": "busybox",
+                            "imageID": "",
+                            "ready": false,
+                            "restartCount": 0,
+                            "state": { "running": {} }
+                        },
+                        {
+                            "name": "debugger-2",
+                            "image": "busybox",
+                            "imageID": "",
+                            "ready": false,
+                            "restartCount": 0,
+                            "state": { "waiting": { "reason": "PodInitializing" } }
+                        }
+                    ]
+                });
+                test_util::json_response(pod)
+            }
+            _ => test_util::not_found(),
+        });
+
+        let containers = get_ephemeral_containers(&client, "web-0", "default")
+            .await
+            .unwrap();
+        assert_eq!(containers, vec!["debugger-1"]);
+
+        let containers = get_ephemeral_containers(&client, "deleted", "default")
+            .await
+            .unwrap();
+        assert!(containers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_pod_list_for_service() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/services/web" => test_util::json_response(json!({
+                "metadata": { "name": "web" },
+                "spec": { "selector": { "app": "web" } }
+            })),
+            "/api/v1/namespaces/default/services/external" => test_util::json_response(json!({
+                "metadata": { "name": "external" },
+                "spec": { "type": "ExternalName", "externalName": "example.com" }
+            })),
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![test_util::pod("web-1", &["app"])],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let pods = get_pod_list_for_service(&client, "web", "default")
+            .await
+            .unwrap();
+        assert_eq!(pods, vec!["web-1"]);
+        assert!(requests.all()[1].contains("labelSelector=app%3Dweb"));
+
+        assert!(get_pod_list_for_service(&client, "external", "default")
+            .await
+            .is_err());
+    }
+
+    fn owned_by(
+        mut object: k8s_openapi::serde_json::Value,
+        owner: (&str, &str, &str, &str),
+    ) -> k8s_openapi::serde_json::Value {
+        let (api_version, kind, name, uid) = owner;
+        object["metadata"]["ownerReferences"] = json!([{
+            "apiVersion": api_version, "kind": kind, "name": name, "uid": uid
+        }]);
+        object
+    }
+
+    #[tokio::test]
+    async fn test_get_pod_list_for_owner() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/argoproj.io/v1alpha1" => test_util::json_response(json!({
+                "kind": "APIResourceList",
+                "groupVersion": "argoproj.io/v1alpha1",
+                "resources": [{
+                    "name": "rollouts", "singularName": "rollout", "namespaced": true,
+                    "kind": "Rollout", "verbs": ["get", "list"]
+                }]
+            })),
+            "/apis/apps/v1" => test_util::json_response(json!({
+                "kind": "APIResourceList",
+                "groupVersion": "apps/v1",
+                "resources": [{
+                    "name": "replicasets", "singularName": "replicaset", "namespaced": true,
+                    "kind": "ReplicaSet", "verbs": ["get", "list"]
+                }]
+            })),
+            "/apis/argoproj.io/v1alpha1/namespaces/default/rollouts/web" => {
+                test_util::json_response(json!({
+                    "apiVersion": "argoproj.io/v1alpha1",
+                    "kind": "Rollout",
+                    "metadata": { "name": "web", "uid": "rollout-web" }
+                }))
+            }
+            "/apis/apps/v1/namespaces/default/replicasets/web-7d9f" => {
+                test_util::json_response(owned_by(
+                    json!({ "metadata": { "name": "web-7d9f" } }),
+                    ("argoproj.io/v1alpha1", "Rollout", "web", "rollout-web"),
+                ))
+            }
+            "/apis/apps/v1/namespaces/default/replicasets/db-5c4b" => {
+                test_util::json_response(owned_by(
+                    json!({ "metadata": { "name": "db-5c4b" } }),
+                    ("apps/v1", "Deployment", "db", "deployment-db"),
+                ))
+            }
+            "/api/v1/namespaces/default/pods" => {
+                let web_rs = ("apps/v1", "ReplicaSet", "web-7d9f", "rs-web");
+                test_util::json_response(test_util::list(
+                    "PodList",
+                    vec![
+                        owned_by(test_util::pod("web-7d9f-a", &["app"]), web_rs),
+                        owned_by(test_util::pod("web-7d9f-b", &["app"]), web_rs),
+                        owned_by(
+                            test_util::pod("db-5c4b-a", &["app"]),
+                            ("apps/v1", "ReplicaSet", "db-5c4b", "rs-db"),
+                        ),
+                        test_util::pod("standalone", &["app"]),
+                    ],
+                ))
+            }
+            _ => test_util::not_found(),
+        });
+
+        let gvk = GroupVersionKind::gvk("argoproj.io", "v1alpha1", "Rollout");
+        let pods = get_pod_list_for_owner(&client, &gvk, "web", Some("default"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            pods,
+            vec![
+                ("default".to_string(), "web-7d9f-a".to_string()),
+                ("default".to_string(), "web-7d9f-b".to_string()),
+            ]
+        );
+        // Owners shared by several pods are only looked up once.
+        assert_eq!(
+            requests.count("/apis/apps/v1/namespaces/default/replicasets/web-7d9f"),
+            1
+        );
+
+        let missing = get_pod_list_for_owner(&client, &gvk, "api", Some("default"))
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn test_pod_status() {
+        let mut pod = test_util::pod("web-0", &["app", "istio-proxy"]);
+        pod["metadata"]["creationTimestamp"] = json!("2024-01-01T00:00:00Z");
+        pod["status"] = json!({
+            "phase": "Running",
+            "containerStatuses": [
+                {
+                    "name": "app", "image": "busybox", "imageID": "", "ready": false,
+                    "restartCount": 4,
+                    "state": { "waiting": { "reason": "CrashLoopBackOff" } }
+                },
+                {
+                    "name": "istio-proxy", "image": "busybox", "imageID": "", "ready": true,
+                    "restartCount": 1,
+                    "state": { "running": {} }
+                }
+            ]
+        });
+        let status = PodStatus::of(&k8s_openapi::serde_json::from_value(pod.clone()).unwrap());
+
+        assert_eq!(status.phase, "CrashLoopBackOff");
+        assert_eq!(status.restarts, 5);
+        assert_eq!(
+            status.created,
+            Some("2024-01-01T00:00:00Z".parse().unwrap())
+        );
+
+        pod["metadata"]["deletionTimestamp"] = json!("2024-01-01T01:00:00Z");
+        let status = PodStatus::of(&k8s_openapi::serde_json::from_value(pod).unwrap());
+        assert_eq!(status.phase, "Terminating");
+    }
+
+    #[test]
+    fn test_select_containers() {
+        let pod: Pod = k8s_openapi::serde_json::from_value(test_util::pod(
+            "web-0",
+            &["app", "istio-proxy", "log-shipper"],
+        ))
+        .unwrap();
+        let select = |container: Option<&str>| {
+            let options = StreamOptions {
+                container: container.map(String::from),
+                ..StreamOptions::default()
+            };
+            select_containers(&pod, &options)
+        };
+
+        assert_eq!(
+            select(None).unwrap(),
+            vec!["app", "istio-proxy", "log-shipper"]
+        );
+        assert_eq!(select(Some("istio-proxy")).unwrap(), vec!["istio-proxy"]);
+        assert_eq!(select(Some("log-.*")).unwrap(), vec!["log-shipper"]);
+        assert!(select(Some("sidecar")).is_err());
+
+        let options = StreamOptions {
+            first_container: true,
+            ..StreamOptions::default()
+        };
+        assert_eq!(select_containers(&pod, &options).unwrap(), vec!["app"]);
+    }
 }
@@ -0,0 +1,88 @@
+//! klog tails the logs of many Kubernetes pods at once, following them as they are
+//! replaced. This library is the engine behind the `klog` command line tool, for tools that
+//! want to embed it:
+//!
+//! - [`Klog::builder`] configures what to log and how, like the command line options do,
+//!   and [`Klog::run`] streams it from every context to stdout and the sinks like the tool.
+//! - [`k8s::client`] connects to a cluster, and the rest of [`k8s`] finds pods and streams
+//!   their logs.
+//! - [`PodManager`] discovers the pods of workloads, streams all of them and picks up new
+//!   ones while following. [`PodManager::events`] yields their lines as [`LogEvent`]s.
+//! - [`Hook`]s change or drop lines before they are filtered and output, see
+//!   [`KlogBuilder::hook`].
+//! - [`Writer`] renders the lines like the command line tool, while [`sinks`] forward them
+//!   elsewhere.
+//!
+//! For example, to print the messages of JSON and logfmt lines of a deployment:
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//! use klog::Klog;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let klog = Klog::builder()
+//!     .namespace("shop")
+//!     .deployment("web")
+//!     .follow(true)
+//!     .build();
+//! let mut events = std::pin::pin!(klog.connect().await?.events());
+//! while let Some(event) = events.next().await {
+//!     let event = event?;
+//!     if let Some(message) = event.parsed.as_ref().and_then(|fields| fields.get("msg")) {
+//!         println!("{}: {}", event.pod, message);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod archive;
+pub mod diagnostics;
+pub mod error;
+pub mod files;
+pub mod hooks;
+pub mod k8s;
+pub mod metrics;
+pub mod output;
+pub mod pod_manager;
+pub mod session;
+pub mod sinks;
+#[cfg(test)]
+mod test_util;
+pub mod traits;
+pub mod upload;
+pub mod util;
+
+pub use error::KlogError;
+pub use hooks::{Hook, Hooks};
+pub use output::{LogEvent, LogLine, RenderOptions, Writer};
+pub use pod_manager::{PodManager, PodTarget};
+pub use session::{Klog, KlogBuilder};
+
+/// A workload whose pods to log, by name.
+#[derive(Clone, Copy, Debug)]
+pub enum ResourceType<'a> {
+    Deployment(&'a str),
+    StatefulSet(&'a str),
+    DaemonSet(&'a str),
+    Job(&'a str),
+    CronJob(&'a str),
+    Service(&'a str),
+    /// A `group/version/Kind/name` spec resolved through owner references.
+    Owner(&'a str),
+}
+
+impl ResourceType<'_> {
+    /// Identifies the resource as `kind/name`, e.g. `deployment/web`.
+    pub fn key(&self) -> String {
+        match self {
+            ResourceType::Deployment(name) => format!("deployment/{}", name),
+            ResourceType::StatefulSet(name) => format!("statefulset/{}", name),
+            ResourceType::DaemonSet(name) => format!("daemonset/{}", name),
+            ResourceType::Job(name) => format!("job/{}", name),
+            ResourceType::CronJob(name) => format!("cronjob/{}", name),
+            ResourceType::Service(name) => format!("service/{}", name),
+            ResourceType::Owner(spec) => spec.to_string(),
+        }
+    }
+}
@@ -1,21 +1,42 @@
-pub mod k8s;
-pub mod traits;
-pub mod util;
-
-use clap::{ArgAction, Parser};
-use k8s_openapi::api::{
-    apps::v1::{DaemonSet, Deployment, StatefulSet},
-    batch::v1::{CronJob, Job},
+mod config;
+mod picker;
+mod tui;
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser};
+use k8s_openapi::chrono::{DateTime, TimeDelta, Utc};
+use k8s_openapi::serde_json;
+use klog::hooks::Hooks;
+use klog::output::{FilterMode, OutputMode, RelativeTo, RenderOptions};
+use klog::pod_manager::PodTarget;
+use klog::{
+    archive, diagnostics, error, k8s, metrics, output, sinks, upload, util, Klog, KlogBuilder,
+    ResourceType,
 };
-use kube::Client;
-use tokio::task;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Namespace to use
-    #[arg(short, long)]
-    namespace: String,
+    /// Pods and workloads to log as kind/name, e.g. deploy/api sts/db pod/debug-123
+    #[arg(value_name = "KIND/NAME")]
+    targets: Vec<String>,
+
+    /// Namespaces to use, defaulting to the namespace of the current context
+    #[arg(short, long, value_delimiter = ' ', num_args = 1..)]
+    namespace: Vec<String>,
+
+    /// Look for pods and resources in all namespaces
+    #[arg(short = 'A', long, action = ArgAction::SetTrue)]
+    all_namespaces: bool,
 
     /// Deployment to log
     #[arg(short, long, value_delimiter = ' ', num_args = 1..)]
@@ -37,34 +58,491 @@ struct Args {
     #[arg(long, value_delimiter = ' ', num_args = 1..)]
     cronjobs: Vec<String>,
 
-    /// Pods to log
+    /// Services whose backing pods to log
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    services: Vec<String>,
+
+    /// Workloads of any kind to log as group/version/Kind/name, e.g. apps/v1/Deployment/web
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    resource: Vec<String>,
+
+    /// Pods to log, either by name or as regex, e.g. 'api-.*'
     #[arg(short, long, value_delimiter = ' ', num_args = 1..)]
     pods: Vec<String>,
 
+    /// Label selector of pods to log, e.g. app=web,tier!=db
+    #[arg(short = 'l', long)]
+    selector: Option<String>,
+
+    /// Node whose pods to log
+    #[arg(long)]
+    node: Option<String>,
+
+    /// Skip pods whose names match this regex, e.g. 'canary|debug'
+    #[arg(long)]
+    exclude_pods: Option<regex::Regex>,
+
+    /// Container to log, either by name or as regex, instead of all containers of each pod
+    #[arg(short, long)]
+    container: Option<String>,
+
+    /// Log only the first container of each pod instead of all of them
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "container")]
+    first_container: bool,
+
+    /// Prefix lines with pod/container even for pods with a single container. Every
+    /// container is logged by default now, so this is kept for compatibility
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["container", "first_container"], hide = true)]
+    all_containers: bool,
+
+    /// Also log init containers, waiting for each to finish before the main containers
+    #[arg(long, action = ArgAction::SetTrue)]
+    init_containers: bool,
+
+    /// Also log ephemeral debug containers, picking up new ones while following
+    #[arg(long, action = ArgAction::SetTrue)]
+    ephemeral_containers: bool,
+
+    /// Only log lines newer than this duration, e.g. 10m or 1h30m
+    #[arg(long, value_parser = util::parse_duration_seconds, conflicts_with = "since_time")]
+    since: Option<i64>,
+
+    /// Only log lines written after this RFC3339 time, e.g. 2024-01-01T12:00:00Z
+    #[arg(long)]
+    since_time: Option<DateTime<Utc>>,
+
+    /// Prefix each line with the timestamp recorded by Kubernetes
+    #[arg(long, action = ArgAction::SetTrue)]
+    timestamps: bool,
+
+    /// Give up on a pending container that has not started after this duration, e.g. 5m,
+    /// instead of waiting for it indefinitely
+    #[arg(long, value_parser = util::parse_duration_seconds)]
+    wait_timeout: Option<i64>,
+
+    /// Show timestamps in the local time zone instead of as they were written
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "tz")]
+    local_time: bool,
+
+    /// Show timestamps in this time zone instead of as they were written, e.g. Europe/Berlin
+    #[arg(long)]
+    tz: Option<chrono_tz::Tz>,
+
+    /// Show timestamps as offsets like -2m13s from now, or from when klog started
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "now",
+        conflicts_with_all = ["local_time", "tz"]
+    )]
+    relative_time: Option<RelativeTo>,
+
     /// Follow log?
     #[arg(short, long, action = ArgAction::SetTrue)]
     follow: bool,
 
-    /// Filter
+    /// Stop after printing this many lines
+    #[arg(long)]
+    max_lines: Option<usize>,
+
+    /// Stop after this duration, e.g. 30s or 5m
+    #[arg(long, value_parser = util::parse_duration_seconds)]
+    exit_after: Option<i64>,
+
+    /// Fail if no pod logs a line for this duration, e.g. 5m
+    #[arg(long, value_parser = util::parse_duration_seconds)]
+    idle_timeout: Option<i64>,
+
+    /// Only log lines containing this text, which is highlighted, can be repeated
+    #[arg(long)]
+    filter: Vec<String>,
+
+    /// Whether lines must contain any or all of the --filter texts
+    #[arg(long, value_enum, default_value_t = FilterMode::Any)]
+    filter_mode: FilterMode,
+
+    /// Only log lines matching this regex, e.g. 'status=5\d\d'
+    #[arg(long)]
+    filter_regex: Option<regex::Regex>,
+
+    /// Skip lines matching this regex, e.g. '/healthz|/readyz'
+    #[arg(long)]
+    exclude: Option<regex::Regex>,
+
+    /// Match --filter, --filter-regex and --exclude regardless of case
+    #[arg(short = 'i', long, action = ArgAction::SetTrue)]
+    ignore_case: bool,
+
+    /// Skip lines of JSON, logfmt or plain text logs below this level
+    #[arg(long, value_enum)]
+    min_level: Option<util::Level>,
+
+    /// Only log JSON lines whose fields meet this condition, e.g. 'status>=500' or
+    /// 'user.id=42', can be repeated
+    #[arg(long)]
+    json_filter: Vec<util::JsonFilter>,
+
+    /// Also log this many lines of the same pod after each line passing the filters
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    after_context: usize,
+
+    /// Also log this many lines of the same pod before each line passing the filters
+    #[arg(short = 'B', long, value_name = "NUM", default_value_t = 0)]
+    before_context: usize,
+
+    /// Also log this many lines of the same pod around each line passing the filters
+    #[arg(short = 'C', long, value_name = "NUM")]
+    context_lines: Option<usize>,
+
+    /// How to write lines: untouched, prettified, prettified with JSON in full, as JSON
+    /// objects (ndjson) or as logfmt
+    #[arg(short, long, value_enum, default_value_t = OutputMode::Pretty)]
+    output: OutputMode,
+
+    /// Template for the prefix of lines, e.g. '{namespace}/{pod}[{container}]', with the
+    /// fields {context}, {namespace}, {pod}, {container} and {resource}
+    #[arg(long, value_parser = output::parse_prefix, conflicts_with = "no_prefix")]
+    prefix: Option<String>,
+
+    /// Write lines without the name of their pod
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_prefix: bool,
+
+    /// Also write the lines of each pod to <namespace>_<pod>.log in this directory
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Also write the lines of all pods to this file, prefixed with their pod
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the --log-file once it reaches this size, e.g. 100MB
+    #[arg(long, value_parser = util::parse_size, requires = "log_file")]
+    rotate_size: Option<u64>,
+
+    /// How many rotated files of the --log-file to keep, as <log-file>.1 and so on
+    #[arg(long, default_value_t = 5, requires = "rotate_size")]
+    keep: usize,
+
+    /// Gzip rotated files of the --log-file, as <log-file>.1.gz and so on
+    #[arg(long, action = ArgAction::SetTrue, requires = "rotate_size")]
+    compress: bool,
+
+    /// Upload rotated files of the --log-file to this S3 bucket, e.g. s3://bucket/prefix.
+    /// Credentials, region and endpoint are read from the AWS_* environment variables
+    #[arg(long, value_parser = upload::parse_target, requires = "rotate_size")]
+    upload: Option<upload::UploadTarget>,
+
+    /// Size of the parts that rotated files are uploaded in when they are larger
+    #[arg(long, value_parser = util::parse_size, default_value = "8MiB")]
+    upload_part_size: u64,
+
+    /// How often a failed upload request is retried
+    #[arg(long, default_value_t = 3)]
+    upload_retries: usize,
+
+    /// Write lines only to files, e.g. with --output-dir or --log-file, not to the terminal
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_stdout: bool,
+
+    /// Interval in seconds at which to look for new pods of CronJobs and --resource while
+    /// following, or of all targets with --poll
+    #[arg(long, default_value_t = 5)]
+    refresh_interval: u64,
+
+    /// Print a notice on stderr when a streamed pod is deleted
+    #[arg(long, action = ArgAction::SetTrue)]
+    notify_removed: bool,
+
+    /// Stream at most this many pods at once; further pods wait until a stream ends
+    #[arg(long)]
+    max_concurrent: Option<usize>,
+
+    /// How many lines are buffered for the output before the streams are slowed down, and
+    /// for each sink before lines are dropped for it
+    #[arg(long, default_value_t = output::DEFAULT_BUFFER_LINES)]
+    buffer_lines: usize,
+
+    /// How often a Kubernetes API request that failed with 429, a 5xx or a timeout is
+    /// retried before giving up
+    #[arg(long, default_value_t = 3)]
+    api_retries: u32,
+
+    /// Look for new pods every --refresh-interval instead of watching them, e.g. where
+    /// watching pods is not permitted
+    #[arg(long, action = ArgAction::SetTrue)]
+    poll: bool,
+
+    /// Drain logs of terminating pods and pick up their replacements immediately
+    #[arg(long, action = ArgAction::SetTrue)]
+    reconnect_on_rollout: bool,
+
+    /// Wait for workloads that do not exist yet to be created, instead of failing
+    #[arg(long, action = ArgAction::SetTrue)]
+    wait_for_resources: bool,
+
+    /// Print the namespace's workloads and their pods as JSON and exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    list_resources: bool,
+
+    /// Print the pods and containers that would be logged, with what they were found
+    /// through, and exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    dry_run: bool,
+
+    /// Show the lines in an interactive terminal UI, with a list of the streamed pods
+    #[arg(long, action = ArgAction::SetTrue)]
+    tui: bool,
+
+    /// Show these fields of JSON and logfmt lines instead of their level, timestamp and
+    /// message, e.g. ts,level,msg,request_id or nested ones like user.id
+    #[arg(long, value_delimiter = ',')]
+    fields: Vec<String>,
+
+    /// Cut lines longer than this many characters, marking the cut with …
+    #[arg(long, value_name = "NUM")]
+    max_line_length: Option<usize>,
+
+    /// Remove escape sequences like colors that containers wrote from their lines
+    #[arg(long, action = ArgAction::SetTrue)]
+    strip_ansi: bool,
+
+    /// Join stack traces into single lines, taking lines starting with whitespace, 'at '
+    /// or 'Caused by' as continuations of the line before
+    #[arg(long, action = ArgAction::SetTrue)]
+    join_multiline: bool,
+
+    /// Print lines a container repeats in a row once, followed by (xN)
+    #[arg(long, action = ArgAction::SetTrue)]
+    dedup: bool,
+
+    /// Print identical lines from replicas of the same resource only once
+    #[arg(long, action = ArgAction::SetTrue)]
+    merge_identical_pods: bool,
+
+    /// Print lines of all pods in the order of their timestamps, holding them back briefly
+    #[arg(long, action = ArgAction::SetTrue)]
+    sort_by_time: bool,
+
+    /// Only log the most recent Job of each CronJob
+    #[arg(long, action = ArgAction::SetTrue)]
+    latest_only: bool,
+
+    /// Salt mixed into the pod colors to reshuffle them
     #[arg(long, default_value = "")]
-    filter: String,
+    color_salt: String,
+
+    /// The colors to show pods in, colorblind picks colors that are easy to tell apart
+    /// with color vision deficiencies like deuteranopia
+    #[arg(long, value_enum, default_value_t = util::Palette::Default)]
+    palette: util::Palette,
+
+    /// Write no colors, which is also the default if NO_COLOR is set or the output is not
+    /// a terminal
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_color: bool,
+
+    /// Color lines by their level: errors red, warnings yellow and debug lines dimmed
+    #[arg(long, action = ArgAction::SetTrue)]
+    color_levels: bool,
+
+    /// Print a marker line with the exit code when a followed container restarts
+    #[arg(long, action = ArgAction::SetTrue)]
+    annotate_restarts: bool,
+
+    /// Show levels of JSON logs as one of trace, debug, info, warn, error or fatal
+    #[arg(long, action = ArgAction::SetTrue)]
+    json_level_normalize: bool,
+
+    /// Kubeconfig contexts of the clusters to log, streaming all of them at once
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    context: Vec<String>,
+
+    /// Path to the kubeconfig file to use instead of the default one
+    #[arg(long)]
+    kubeconfig: Option<PathBuf>,
+
+    /// Connect with the service account of the pod klog runs in
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["kubeconfig", "context"])]
+    in_cluster: bool,
+
+    /// User to impersonate, e.g. to check what logs an RBAC subject may read
+    #[arg(long = "as", value_name = "USER")]
+    impersonate: Option<String>,
+
+    /// Groups to impersonate, e.g. system:serviceaccounts
+    #[arg(long = "as-group", value_name = "GROUP", value_delimiter = ' ', num_args = 1..)]
+    impersonate_groups: Vec<String>,
+
+    /// Profile from the config file whose options to use, e.g. ~/.config/klog/config.yaml
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Also index the lines into Elasticsearch or OpenSearch at this URL, e.g.
+    /// https://localhost:9200
+    #[arg(long)]
+    elasticsearch_url: Option<String>,
+
+    /// The Elasticsearch index to write to, with strftime fields filled in from the time of
+    /// each line
+    #[arg(long, value_parser = sinks::elasticsearch::parse_index, default_value = "klog-%Y.%m.%d")]
+    elasticsearch_index: String,
+
+    /// The user to log in to Elasticsearch with, using --elasticsearch-password
+    #[arg(long, requires = "elasticsearch_url")]
+    elasticsearch_user: Option<String>,
+
+    /// The password of the --elasticsearch-user
+    #[arg(long, requires = "elasticsearch_user")]
+    elasticsearch_password: Option<String>,
+
+    /// The API key to log in to Elasticsearch with
+    #[arg(
+        long,
+        requires = "elasticsearch_url",
+        conflicts_with = "elasticsearch_user"
+    )]
+    elasticsearch_api_key: Option<String>,
+
+    /// Interval in seconds at which lines are indexed into Elasticsearch
+    #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+    elasticsearch_flush_interval: u64,
+
+    /// Also send the lines as RFC5424 syslog messages to this address, e.g.
+    /// udp://host:514, tcp://host:514 or tls://host:6514
+    #[arg(long, value_parser = sinks::syslog::parse_address)]
+    syslog: Option<sinks::syslog::SyslogAddress>,
+
+    /// Also forward the lines to this fluentd or fluent-bit forward input, e.g.
+    /// localhost:24224
+    #[arg(long, value_parser = sinks::fluentd::parse_address)]
+    fluentd: Option<String>,
+
+    /// Prefix of the tags lines are forwarded to --fluentd with, followed by their
+    /// namespace and pod
+    #[arg(long, default_value = "klog")]
+    fluentd_tag_prefix: String,
+
+    /// Also send the lines as GELF messages to this Graylog input, e.g.
+    /// udp://graylog:12201 or tcp://graylog:12201
+    #[arg(long, value_parser = sinks::gelf::parse_address)]
+    gelf: Option<sinks::gelf::GelfAddress>,
+
+    /// Also export the lines as OpenTelemetry log records to this OTLP/HTTP endpoint, e.g.
+    /// http://localhost:4318
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// A header to send to the --otlp-endpoint, e.g. 'Authorization=Bearer abc', can be
+    /// repeated
+    #[arg(long, value_parser = sinks::parse_header, requires = "otlp_endpoint")]
+    otlp_header: Vec<(String, String)>,
+
+    /// Also post the lines in batches as JSON arrays of events to this URL
+    #[arg(long)]
+    webhook_url: Option<String>,
+
+    /// A header to send to the --webhook-url, e.g. 'Authorization=Bearer abc', can be
+    /// repeated
+    #[arg(long, value_parser = sinks::parse_header, requires = "webhook_url")]
+    webhook_header: Vec<(String, String)>,
+
+    /// Maximum number of lines posted to the --webhook-url at once
+    #[arg(long, default_value_t = 100)]
+    webhook_batch_size: usize,
+
+    /// Interval in seconds at which lines are posted to the --webhook-url
+    #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+    webhook_flush_interval: u64,
+
+    /// How often a failed post to the --webhook-url is retried before its lines are dropped
+    #[arg(long, default_value_t = 3)]
+    webhook_retries: u32,
+
+    /// Send an alert to the --alert-webhook for lines matching this regex, e.g.
+    /// 'panic|OOMKilled'
+    #[arg(long, requires = "alert_webhook")]
+    alert_pattern: Option<regex::Regex>,
+
+    /// Slack or Discord incoming webhook URL to send alerts for the --alert-pattern to
+    #[arg(long, requires = "alert_pattern")]
+    alert_webhook: Option<String>,
+
+    /// Number of preceding lines of the same pod to include in an alert
+    #[arg(long, default_value_t = 5)]
+    alert_context: usize,
+
+    /// Minimum interval in seconds between alerts for the same pod
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    alert_interval: u64,
+
+    /// Serve Prometheus metrics like lines streamed per pod and active streams on this
+    /// address, e.g. :9090
+    #[arg(long, value_parser = metrics::parse_addr)]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Print a summary of the streams to stderr at this interval in seconds, telling apart
+    /// streams that are stalled or reconnecting from ones that are streaming
+    #[arg(long, value_name = "SECONDS")]
+    status_interval: Option<u64>,
+
+    /// Also print what klog does, like API requests, discovery and reconnects, to stderr;
+    /// repeat for more detail. RUST_LOG takes precedence, e.g. RUST_LOG=klog=debug
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Print only errors to stderr, without notices about pods
+    #[arg(short, long, action = ArgAction::SetTrue)]
+    quiet: bool,
+
+    /// Also serve the lines as NDJSON to every client connecting to this address, e.g.
+    /// tcp://0.0.0.0:5000 or unix:///tmp/klog.sock
+    #[arg(long, value_parser = sinks::server::parse_address)]
+    serve: Option<sinks::server::ServeAddress>,
+
+    /// Also store the lines in this SQLite database, to search them later with `klog query`
+    #[arg(long)]
+    archive: Option<PathBuf>,
+
+    /// Render logs captured as NDJSON from a file instead of a cluster
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
-enum ResourceType<'a> {
-    Deployment(&'a str),
-    StatefulSet(&'a str),
-    DaemonSet(&'a str),
-    Job(&'a str),
-    CronJob(&'a str),
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Search the lines stored with --archive
+    Query(QueryArgs),
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
+    /// SQLite database written with --archive
+    #[arg(long, default_value = "klog.db")]
+    archive: PathBuf,
 
-    let client = Client::try_default().await?;
+    /// Only show lines newer than a relative duration like 30s, 10m or 1h30m
+    #[arg(long, value_parser = util::parse_duration_seconds)]
+    since: Option<i64>,
 
-    let mut pod_list = args.pods;
+    /// Only show lines of this namespace
+    #[arg(short, long)]
+    namespace: Option<String>,
+
+    /// Only show lines of pods whose names contain this
+    #[arg(long)]
+    pod: Option<String>,
 
+    /// Only show lines containing this
+    #[arg(long)]
+    grep: Option<String>,
+}
+/// Collects the workloads named on the command line.
+fn resources(args: &Args) -> Vec<ResourceType<'_>> {
     let mut resources = Vec::new();
 
     resources.extend(
@@ -79,72 +557,436 @@ async fn main() -> anyhow::Result<()> {
     );
     resources.extend(args.daemonsets.iter().map(|ds| ResourceType::DaemonSet(ds)));
     resources.extend(args.jobs.iter().map(|job| ResourceType::Job(job)));
+    resources.extend(
+        args.cronjobs
+            .iter()
+            .map(|cronjob| ResourceType::CronJob(cronjob)),
+    );
+    resources.extend(
+        args.services
+            .iter()
+            .map(|service| ResourceType::Service(service)),
+    );
+    resources.extend(args.resource.iter().map(|spec| ResourceType::Owner(spec)));
+    resources
+}
 
-    for resource in resources {
-        match resource {
-            ResourceType::Deployment(deploy) => {
-                pod_list.append(
-                    &mut k8s::get_pod_list_for_resource::<Deployment>(
-                        &client,
-                        deploy,
-                        &args.namespace,
-                    )
-                    .await?,
-                );
-            }
-            ResourceType::StatefulSet(statefulset) => {
-                pod_list.append(
-                    &mut k8s::get_pod_list_for_resource::<StatefulSet>(
-                        &client,
-                        statefulset,
-                        &args.namespace,
-                    )
-                    .await?,
-                );
-            }
-            ResourceType::DaemonSet(ds) => {
-                pod_list.append(
-                    &mut k8s::get_pod_list_for_resource::<DaemonSet>(&client, ds, &args.namespace)
-                        .await?,
-                );
-            }
-            ResourceType::Job(job) => {
-                pod_list.append(
-                    &mut k8s::get_pod_list_for_resource::<Job>(&client, job, &args.namespace)
-                        .await?,
-                );
+/// Builds the session from the command line.
+fn session<'a>(args: &'a Args, render_options: &RenderOptions) -> Klog<'a> {
+    let builder = Klog::builder()
+        .client_options(k8s::ClientOptions {
+            kubeconfig: args.kubeconfig.clone(),
+            context: None,
+            in_cluster: args.in_cluster,
+            impersonate: args.impersonate.clone(),
+            impersonate_groups: args.impersonate_groups.clone(),
+        })
+        .all_namespaces(args.all_namespaces)
+        .selector(args.selector.clone())
+        .node(args.node.clone())
+        .exclude_pods(args.exclude_pods.clone())
+        .stream_options(k8s::StreamOptions {
+            follow: args.follow,
+            annotate_restarts: args.annotate_restarts,
+            container: args.container.clone(),
+            first_container: args.first_container,
+            tag_containers: args.all_containers,
+            init_containers: args.init_containers,
+            ephemeral_containers: args.ephemeral_containers,
+            since_seconds: args.since,
+            since_time: args.since_time,
+            timestamps: args.timestamps || args.sort_by_time,
+            api_retries: args.api_retries,
+            wait_timeout: args
+                .wait_timeout
+                .map(|seconds| Duration::from_secs(seconds.max(0) as u64)),
+            ..k8s::StreamOptions::default()
+        })
+        .render_options(render_options.clone())
+        .refresh_interval(Duration::from_secs(args.refresh_interval))
+        .watch(!args.poll)
+        .notify_removed(args.notify_removed)
+        .max_concurrent(args.max_concurrent)
+        .buffer_lines(args.buffer_lines)
+        .reconnect_on_rollout(args.reconnect_on_rollout)
+        .wait_for_resources(args.wait_for_resources)
+        .watch_pod_statuses(args.tui)
+        .latest_only(args.latest_only)
+        .sinks(sink_options(args))
+        .metrics_addr(args.metrics_addr)
+        .status_interval(
+            args.status_interval
+                .map(|seconds| Duration::from_secs(seconds.max(1))),
+        );
+    let builder = args
+        .context
+        .iter()
+        .fold(builder, |builder, context| builder.context(context));
+    let builder = args
+        .namespace
+        .iter()
+        .fold(builder, |builder, namespace| builder.namespace(namespace));
+    let builder = resources(args)
+        .into_iter()
+        .fold(builder, KlogBuilder::resource);
+    args.pods
+        .iter()
+        .fold(builder, |builder, pod| builder.pod(pod))
+        .build()
+}
+
+fn has_targets(args: &Args) -> bool {
+    !resources(args).is_empty()
+        || !args.pods.is_empty()
+        || args.selector.is_some()
+        || args.node.is_some()
+}
+
+/// Adds a target given as `kind/name` or `kind/namespace/name` to the arguments, where
+/// the kind may be abbreviated like in kubectl, e.g. `deploy/api` or `sts/db`.
+fn add_target(args: &mut Args, target: &str) -> anyhow::Result<()> {
+    let (kind, name) = target
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("Invalid target {}, expected kind/name", target))?;
+    let name = match name.split_once('/') {
+        Some((namespace, name)) => {
+            if !args.all_namespaces && !args.namespace.iter().any(|ns| ns == namespace) {
+                args.namespace.push(namespace.to_string());
             }
-            ResourceType::CronJob(cronjob) => pod_list.append(
-                &mut k8s::get_pod_list_for_resource::<CronJob>(&client, cronjob, &args.namespace)
-                    .await?,
-            ),
+            name
         }
+        None => name,
+    };
+    let targets = match kind.to_ascii_lowercase().as_str() {
+        "deploy" | "deployment" | "deployments" => &mut args.deployments,
+        "sts" | "statefulset" | "statefulsets" => &mut args.statefulsets,
+        "ds" | "daemonset" | "daemonsets" => &mut args.daemonsets,
+        "job" | "jobs" => &mut args.jobs,
+        "cj" | "cronjob" | "cronjobs" => &mut args.cronjobs,
+        "svc" | "service" | "services" => &mut args.services,
+        "po" | "pod" | "pods" => &mut args.pods,
+        _ => anyhow::bail!("Invalid target {}, unknown kind {}", target, kind),
+    };
+    targets.push(name.to_string());
+    Ok(())
+}
+
+/// Adds the targets given as positional arguments.
+fn add_positional_targets(args: &mut Args) -> anyhow::Result<()> {
+    // `--namespace` takes several values, so targets following it are parsed as
+    // namespaces. Namespaces never contain a slash, so they can be told apart.
+    let (mut targets, namespaces): (Vec<_>, Vec<_>) = std::mem::take(&mut args.namespace)
+        .into_iter()
+        .partition(|namespace| namespace.contains('/'));
+    args.namespace = namespaces;
+    targets.append(&mut args.targets);
+    for target in targets {
+        add_target(args, &target)?;
+    }
+    Ok(())
+}
+
+/// The command line interface, shown as `kubectl klog` when invoked as a kubectl plugin
+/// through a binary or symlink called `kubectl-klog`.
+fn command(argv0: Option<&OsString>) -> clap::Command {
+    let is_plugin = argv0
+        .map(Path::new)
+        .and_then(Path::file_stem)
+        .is_some_and(|name| name == "kubectl-klog");
+    if is_plugin {
+        Args::command().bin_name("kubectl klog")
+    } else {
+        Args::command()
     }
+}
+
+/// Parses the command line, filling in the options of the selected profile that were not
+/// given on the command line.
+fn parse_args(argv: Vec<OsString>) -> anyhow::Result<Args> {
+    let command = command(argv.first());
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .get_matches_from(argv.clone());
+    let Some(profile) = matches.get_one::<String>("profile") else {
+        return Ok(Args::from_arg_matches(&command.get_matches_from(argv))?);
+    };
+    let path = config::path()
+        .ok_or_else(|| anyhow::anyhow!("No config file found for profile {}", profile))?;
+    let config = config::load(&path)?;
 
-    let namespace = args.namespace;
-    let follow = args.follow;
-    let filter = args.filter;
+    let mut full_argv: Vec<OsString> = argv.iter().take(1).cloned().collect();
+    for (option, values) in config.profile_args(profile)? {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(option.as_str()))
+            .ok_or_else(|| anyhow::anyhow!("Unknown option {} in profile {}", option, profile))?;
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if values.is_empty() {
+            full_argv.push(format!("--{}", option).into());
+        }
+        for value in values {
+            full_argv.push(format!("--{}={}", option, value).into());
+        }
+    }
+    full_argv.extend(argv.into_iter().skip(1));
+    Ok(Args::from_arg_matches(
+        &command.get_matches_from(full_argv),
+    )?)
+}
 
-    let mut handles = Vec::new();
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let e = error::classify(e);
+            diagnostics::error(format_args!("{:#}", e));
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
 
-    for pod in pod_list {
-        let client = client.clone();
-        let namespace = namespace.clone();
-        let filter = filter.clone();
+async fn run() -> anyhow::Result<()> {
+    let mut args = parse_args(std::env::args_os().collect())?;
+    add_positional_targets(&mut args)?;
+    colored::control::set_override(util::use_color(
+        args.no_color,
+        std::env::var_os("NO_COLOR"),
+        std::io::stdout().is_terminal(),
+    ));
+    diagnostics::init(args.verbose, args.quiet);
 
-        let handle = task::spawn(async move {
-            k8s::stream_single_pod_logs(&client, &pod, &namespace, &follow, &filter).await?;
-            Ok::<(), anyhow::Error>(())
+    let render_options = RenderOptions {
+        filter: args.filter.clone(),
+        filter_mode: args.filter_mode,
+        filter_regex: args.filter_regex.clone(),
+        exclude: args.exclude.clone(),
+        ignore_case: args.ignore_case,
+        min_level: args.min_level,
+        json_filter: args.json_filter.clone(),
+        before_context: args.context_lines.unwrap_or(args.before_context),
+        after_context: args.context_lines.unwrap_or(args.after_context),
+        output: args.output,
+        time_zone: match args.tz {
+            Some(tz) => Some(util::TimeZone::Named(tz)),
+            None => args.local_time.then_some(util::TimeZone::Local),
+        },
+        relative_time: args.relative_time,
+        merge_identical_pods: args.merge_identical_pods,
+        sort_by_time: args.sort_by_time,
+        hide_timestamps: args.sort_by_time && !args.timestamps,
+        color_salt: args.color_salt.clone(),
+        palette: args.palette,
+        color_levels: args.color_levels,
+        dedup: args.dedup,
+        strip_ansi: args.strip_ansi,
+        join_multiline: args.join_multiline,
+        fields: args.fields.clone(),
+        max_line_length: args.max_line_length,
+        json_level_normalize: args.json_level_normalize,
+        show_namespace: args.all_namespaces || args.namespace.len() > 1,
+        prefix: args.prefix.clone(),
+        no_prefix: args.no_prefix,
+        output_dir: args.output_dir.clone(),
+        log_file: args.log_file.clone(),
+        rotate_size: args.rotate_size,
+        keep: args.keep,
+        compress: args.compress,
+        no_stdout: args.no_stdout,
+        segments: None,
+        sinks: vec![],
+        max_lines: args.max_lines,
+        idle_timeout: args
+            .idle_timeout
+            .map(|seconds| Duration::from_secs(seconds.max(0) as u64)),
+        shutdown: CancellationToken::new(),
+        hooks: Hooks::default(),
+    };
+    if let Some(seconds) = args.exit_after {
+        let shutdown = render_options.shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(seconds.max(0) as u64)).await;
+            shutdown.cancel();
         });
+    }
+
+    stream(args, render_options).await
+}
+
+/// The sinks requested by `args`.
+fn sink_options(args: &Args) -> sinks::SinkOptions {
+    sinks::SinkOptions {
+        elasticsearch: args.elasticsearch_url.clone().map(|url| {
+            sinks::elasticsearch::ElasticsearchOptions {
+                url,
+                index: args.elasticsearch_index.clone(),
+                username: args.elasticsearch_user.clone(),
+                password: args.elasticsearch_password.clone(),
+                api_key: args.elasticsearch_api_key.clone(),
+                flush_interval: Duration::from_secs(args.elasticsearch_flush_interval),
+            }
+        }),
+        fluentd: args
+            .fluentd
+            .clone()
+            .map(|address| sinks::fluentd::FluentdOptions {
+                address,
+                tag_prefix: args.fluentd_tag_prefix.clone(),
+            }),
+        gelf: args.gelf.clone(),
+        otlp: args
+            .otlp_endpoint
+            .clone()
+            .map(|endpoint| sinks::otlp::OtlpOptions {
+                endpoint,
+                headers: args.otlp_header.clone(),
+            }),
+        webhook: args
+            .webhook_url
+            .clone()
+            .map(|url| sinks::webhook::WebhookOptions {
+                url,
+                headers: args.webhook_header.clone(),
+                batch_size: args.webhook_batch_size.max(1),
+                flush_interval: Duration::from_secs(args.webhook_flush_interval),
+                retries: args.webhook_retries,
+            }),
+        alert: match (&args.alert_pattern, &args.alert_webhook) {
+            (Some(pattern), Some(webhook)) => Some(sinks::alert::AlertOptions {
+                pattern: pattern.clone(),
+                webhook: webhook.clone(),
+                context: args.alert_context,
+                interval: Duration::from_secs(args.alert_interval),
+            }),
+            _ => None,
+        },
+        syslog: args.syslog.clone(),
+        serve: args.serve.clone(),
+        archive: args.archive.clone(),
+        upload: args.upload.clone().map(|target| upload::UploadOptions {
+            target,
+            part_size: args.upload_part_size as usize,
+            retries: args.upload_retries,
+        }),
+        buffer_lines: args.buffer_lines,
+    }
+}
 
-        handles.push(handle);
+/// Streams the lines of all contexts into the TUI instead of writing them, until it is
+/// quit or a stream fails.
+async fn run_tui(klog: &Klog<'_>, buffer_lines: usize) -> anyhow::Result<()> {
+    let (lines_tx, lines_rx) = mpsc::channel(buffer_lines.max(1));
+    let shutdown = klog.shutdown();
+    let runs = async {
+        let result = klog.run_into(lines_tx).await;
+        if result.is_err() {
+            // Close the TUI so that the error can be reported.
+            shutdown.cancel();
+        }
+        result
+    };
+    let (shown, runs) = tokio::join!(tui::run(lines_rx, klog.render_options().clone()), runs);
+    runs?;
+    shown
+}
+
+/// Prints the pods `--dry-run` found, one per line like
+/// `shop/web-1 [app, proxy] deployment/web`.
+fn print_dry_run(context: Option<&str>, streams: &[(PodTarget, Vec<String>)]) {
+    if streams.is_empty() {
+        diagnostics::notice("No pods found for the targets");
     }
+    for (target, containers) in streams {
+        let context = context.map(|context| format!("{}: ", context));
+        println!(
+            "{}{}/{} [{}] {}",
+            context.unwrap_or_default(),
+            target.namespace,
+            target.name,
+            containers.join(", "),
+            target.resource.as_deref().unwrap_or("pod")
+        );
+    }
+}
+
+/// Cancels `shutdown` on Ctrl-C, so that the streams end and the lines read so far are
+/// written out before klog exits. A second Ctrl-C exits right away.
+fn shutdown_on_ctrl_c(shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        diagnostics::notice(format_args!(
+            "Shutting down, press Ctrl-C again to exit immediately"
+        ));
+        shutdown.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+}
 
-    for handle in handles {
-        handle.await??;
+/// Streams the logs of the targets, or of a capture with `--replay`.
+async fn stream(mut args: Args, render_options: RenderOptions) -> anyhow::Result<()> {
+    if let Some(Command::Query(query)) = &args.command {
+        if !query.archive.exists() {
+            anyhow::bail!("Archive {} not found", query.archive.display());
+        }
+        let connection = archive::open(&query.archive)?;
+        let lines = archive::query(
+            &connection,
+            &archive::Query {
+                since: query
+                    .since
+                    .map(|seconds| Utc::now() - TimeDelta::seconds(seconds)),
+                namespace: query.namespace.clone(),
+                pod: query.pod.clone(),
+                grep: query.grep.clone(),
+            },
+        )?;
+        return session(&args, &render_options).write_lines(lines).await;
+    }
+    if let Some(path) = &args.replay {
+        let reader = BufReader::new(File::open(path)?);
+        return session(&args, &render_options).replay(reader).await;
     }
 
-    Ok(())
+    if !has_targets(&args) && !args.list_resources && std::io::stdin().is_terminal() {
+        let resources = session(&args, &render_options)
+            .connect()
+            .await?
+            .list_resources()
+            .await?;
+        let candidates = picker::candidates(&resources);
+        for target in picker::pick(&candidates, std::io::stdin().lock(), std::io::stderr())? {
+            add_target(&mut args, &target)?;
+        }
+        if !has_targets(&args) {
+            return Ok(());
+        }
+    }
+
+    let klog = session(&args, &render_options);
+    if args.list_resources {
+        let resources = klog.list_resources().await?;
+        println!("{}", serde_json::to_string_pretty(&resources)?);
+        return Ok(());
+    }
+    if args.dry_run {
+        for (context, streams) in klog.dry_run().await? {
+            print_dry_run(context.as_deref(), &streams);
+        }
+        return Ok(());
+    }
+    if args.tui {
+        return run_tui(&klog, args.buffer_lines).await;
+    }
+
+    shutdown_on_ctrl_c(klog.shutdown());
+    klog.run().await
 }
 
 #[cfg(test)]
@@ -154,15 +996,115 @@ mod tests {
     #[tokio::test]
     async fn test_resource_processing() -> Result<(), anyhow::Error> {
         let args = Args {
+            targets: vec![],
             deployments: vec!["deploy1".into()],
             statefulsets: vec!["statefulset1".into()],
             daemonsets: vec!["daemonset1".into()],
             jobs: vec!["job1".into()],
             cronjobs: vec!["job2".into()],
+            services: vec![],
+            resource: vec![],
             pods: vec!["pod1".into()],
-            namespace: "test-namespace".into(),
+            selector: None,
+            node: None,
+            exclude_pods: None,
+            container: None,
+            first_container: false,
+            all_containers: false,
+            init_containers: false,
+            ephemeral_containers: false,
+            since: None,
+            since_time: None,
+            timestamps: false,
+            wait_timeout: None,
+            local_time: false,
+            tz: None,
+            relative_time: None,
+            namespace: vec!["test-namespace".into()],
+            all_namespaces: false,
             follow: true,
-            filter: "".into(),
+            filter: vec![],
+            filter_mode: FilterMode::Any,
+            filter_regex: None,
+            exclude: None,
+            ignore_case: false,
+            min_level: None,
+            json_filter: vec![],
+            after_context: 0,
+            before_context: 0,
+            context_lines: None,
+            output: OutputMode::Pretty,
+            prefix: None,
+            no_prefix: false,
+            output_dir: None,
+            log_file: None,
+            rotate_size: None,
+            upload: None,
+            upload_part_size: 8 << 20,
+            upload_retries: 3,
+            keep: 5,
+            compress: false,
+            no_stdout: false,
+            elasticsearch_url: None,
+            elasticsearch_index: "klog-%Y.%m.%d".into(),
+            elasticsearch_user: None,
+            elasticsearch_password: None,
+            elasticsearch_api_key: None,
+            elasticsearch_flush_interval: 5,
+            syslog: None,
+            fluentd: None,
+            fluentd_tag_prefix: "klog".to_string(),
+            gelf: None,
+            otlp_endpoint: None,
+            otlp_header: vec![],
+            webhook_url: None,
+            webhook_header: vec![],
+            webhook_batch_size: 100,
+            webhook_flush_interval: 5,
+            webhook_retries: 3,
+            alert_pattern: None,
+            alert_webhook: None,
+            alert_context: 5,
+            alert_interval: 60,
+            metrics_addr: None,
+            status_interval: None,
+            verbose: 0,
+            quiet: false,
+            refresh_interval: 5,
+            notify_removed: false,
+            max_concurrent: None,
+            buffer_lines: output::DEFAULT_BUFFER_LINES,
+            api_retries: 3,
+            poll: false,
+            reconnect_on_rollout: false,
+            wait_for_resources: false,
+            list_resources: false,
+            dry_run: false,
+            tui: false,
+            merge_identical_pods: false,
+            sort_by_time: false,
+            latest_only: false,
+            color_salt: "".into(),
+            palette: util::Palette::Default,
+            no_color: false,
+            color_levels: false,
+            dedup: false,
+            strip_ansi: false,
+            join_multiline: false,
+            fields: vec![],
+            max_line_length: None,
+            annotate_restarts: false,
+            json_level_normalize: false,
+            context: vec![],
+            kubeconfig: None,
+            in_cluster: false,
+            impersonate: None,
+            impersonate_groups: vec![],
+            profile: None,
+            serve: None,
+            archive: None,
+            replay: None,
+            command: None,
         };
 
         let resources: Vec<_> = args
@@ -191,4 +1133,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_add_target() {
+        let mut args = Args::parse_from(["klog", "-n", "default"]);
+
+        add_target(&mut args, "deployment/web").unwrap();
+        add_target(&mut args, "cronjob/shop/nightly").unwrap();
+        add_target(&mut args, "po/web-0").unwrap();
+
+        assert_eq!(args.deployments, vec!["web"]);
+        assert_eq!(args.cronjobs, vec!["nightly"]);
+        assert_eq!(args.pods, vec!["web-0"]);
+        assert_eq!(args.namespace, vec!["default", "shop"]);
+        assert!(add_target(&mut args, "configmap/settings").is_err());
+        assert!(add_target(&mut args, "web").is_err());
+    }
+
+    #[test]
+    fn test_add_positional_targets() {
+        let mut args = Args::parse_from([
+            "klog",
+            "deploy/api",
+            "-n",
+            "shop",
+            "sts/db",
+            "pod/debug-123",
+        ]);
+
+        add_positional_targets(&mut args).unwrap();
+
+        assert_eq!(args.namespace, vec!["shop"]);
+        assert_eq!(args.deployments, vec!["api"]);
+        assert_eq!(args.statefulsets, vec!["db"]);
+        assert_eq!(args.pods, vec!["debug-123"]);
+    }
+
+    #[test]
+    fn test_parse_args_without_profile() {
+        let args = parse_args(vec!["klog".into(), "-n".into(), "shop".into()]).unwrap();
+
+        assert_eq!(args.namespace, vec!["shop"]);
+        assert!(args.profile.is_none());
+    }
+
+    #[test]
+    fn test_command_as_kubectl_plugin() {
+        let usage = |argv0: &str| {
+            command(Some(&OsString::from(argv0)))
+                .render_usage()
+                .to_string()
+        };
+
+        assert!(usage("/usr/local/bin/kubectl-klog").contains("kubectl klog"));
+        assert!(!usage("klog").contains("kubectl"));
+    }
 }
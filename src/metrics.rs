@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use k8s_openapi::chrono::TimeDelta;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::diagnostics;
+use crate::k8s::PodStatus;
+use crate::util;
+
+/// The counters of this process, served by `serve`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Counters describing the streams of a long-running klog.
+#[derive(Default)]
+pub struct Metrics {
+    /// Lines received, keyed by namespace and pod.
+    lines: Mutex<BTreeMap<(String, String), u64>>,
+    bytes: AtomicU64,
+    active_streams: AtomicI64,
+    reconnects: AtomicU64,
+    filter_matches: AtomicU64,
+    dropped_lines: AtomicU64,
+    /// The health of each container's stream, keyed by namespace, pod and container.
+    streams: Mutex<BTreeMap<(String, String, String), StreamHealth>>,
+    /// The status of the pods, keyed by namespace and pod, while they are watched.
+    pods: Mutex<BTreeMap<(String, String), PodStatus>>,
+}
+
+/// What the stream of a container is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamState {
+    /// Waiting for the container to start.
+    Waiting,
+    Streaming,
+    /// Waiting to reopen a stream that ended while the container kept running, or that
+    /// was restarted.
+    Reconnecting,
+    Ended,
+}
+
+struct StreamHealth {
+    state: StreamState,
+    /// When the stream was opened or last received a line.
+    last_activity: Instant,
+    /// Whether `last_activity` is from a line rather than opening the stream.
+    any_lines: bool,
+    reconnects: u64,
+}
+
+/// Counts a log stream as active until dropped.
+pub struct ActiveStream<'a> {
+    metrics: &'a Metrics,
+    key: (String, String, String),
+}
+
+impl Drop for ActiveStream<'_> {
+    fn drop(&mut self) {
+        self.metrics.active_streams.fetch_sub(1, Ordering::Relaxed);
+        if let Some(health) = self.metrics.streams.lock().unwrap().get_mut(&self.key) {
+            health.state = StreamState::Ended;
+        }
+    }
+}
+
+fn key(namespace: &str, pod: &str, container: &str) -> (String, String, String) {
+    (
+        namespace.to_string(),
+        pod.to_string(),
+        container.to_string(),
+    )
+}
+
+impl Metrics {
+    pub fn line_received(&self, namespace: &str, pod: &str, container: &str, line: &str) {
+        if let Some(health) = self
+            .streams
+            .lock()
+            .unwrap()
+            .get_mut(&key(namespace, pod, container))
+        {
+            health.last_activity = Instant::now();
+            health.any_lines = true;
+        }
+        *self
+            .lines
+            .lock()
+            .unwrap()
+            .entry((namespace.to_string(), pod.to_string()))
+            .or_default() += 1;
+        self.bytes
+            .fetch_add(line.len() as u64 + 1, Ordering::Relaxed);
+    }
+
+    pub fn stream_started(&self, namespace: &str, pod: &str, container: &str) -> ActiveStream<'_> {
+        self.active_streams.fetch_add(1, Ordering::Relaxed);
+        self.set_state(namespace, pod, container, StreamState::Streaming);
+        ActiveStream {
+            metrics: self,
+            key: key(namespace, pod, container),
+        }
+    }
+
+    pub fn reconnected(&self, namespace: &str, pod: &str, container: &str) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.set_state(namespace, pod, container, StreamState::Reconnecting);
+        if let Some(health) = self
+            .streams
+            .lock()
+            .unwrap()
+            .get_mut(&key(namespace, pod, container))
+        {
+            health.reconnects += 1;
+        }
+    }
+
+    /// Records what the stream of a container is doing, for `status`.
+    pub fn set_state(&self, namespace: &str, pod: &str, container: &str, state: StreamState) {
+        let mut streams = self.streams.lock().unwrap();
+        let health = streams
+            .entry(key(namespace, pod, container))
+            .or_insert_with(|| StreamHealth {
+                state,
+                last_activity: Instant::now(),
+                any_lines: false,
+                reconnects: 0,
+            });
+        if state == StreamState::Streaming && health.state != StreamState::Streaming {
+            health.last_activity = Instant::now();
+            health.any_lines = false;
+        }
+        health.state = state;
+    }
+
+    pub fn filter_matched(&self) {
+        self.filter_matches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn line_dropped(&self) {
+        self.dropped_lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The state of each container's stream, keyed by namespace, pod and container.
+    pub fn stream_states(&self) -> Vec<((String, String, String), StreamState)> {
+        self.streams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, health)| (key.clone(), health.state))
+            .collect()
+    }
+
+    /// Records the status of a pod, or that it was deleted.
+    pub fn set_pod_status(&self, namespace: &str, pod: &str, status: Option<PodStatus>) {
+        let mut pods = self.pods.lock().unwrap();
+        let key = (namespace.to_string(), pod.to_string());
+        match status {
+            Some(status) => pods.insert(key, status),
+            None => pods.remove(&key),
+        };
+    }
+
+    /// The status of the watched pods, keyed by namespace and pod.
+    pub fn pod_statuses(&self) -> BTreeMap<(String, String), PodStatus> {
+        self.pods.lock().unwrap().clone()
+    }
+
+    /// Summarizes the streams for `--status-interval`: how many are streaming, how many
+    /// are stalled because they received nothing for `stall_after`, and the streams that
+    /// are stalled or reconnecting.
+    pub fn status(&self, stall_after: Duration) -> String {
+        let streams = self.streams.lock().unwrap();
+        let mut counts = BTreeMap::new();
+        let mut details = String::new();
+        for ((namespace, pod, container), health) in streams.iter() {
+            let idle = health.last_activity.elapsed();
+            let state = match health.state {
+                StreamState::Streaming if idle >= stall_after => "stalled",
+                StreamState::Streaming => "streaming",
+                StreamState::Waiting => "waiting",
+                StreamState::Reconnecting => "reconnecting",
+                StreamState::Ended => "ended",
+            };
+            *counts.entry(state).or_insert(0) += 1;
+            if matches!(state, "stalled" | "reconnecting") {
+                let since = TimeDelta::from_std(idle).unwrap_or_default();
+                let _ = write!(
+                    details,
+                    "\n  {}/{} [{}] {}, {} {} ago, {} reconnects",
+                    namespace,
+                    pod,
+                    container,
+                    state,
+                    if health.any_lines {
+                        "last line"
+                    } else {
+                        "opened"
+                    },
+                    util::format_offset(since).trim_start_matches('+'),
+                    health.reconnects
+                );
+            }
+        }
+        let summary = ["streaming", "stalled", "reconnecting", "waiting", "ended"]
+            .iter()
+            .map(|state| format!("{} {}", counts.get(state).unwrap_or(&0), state))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "Streams: {}, {} reconnects{}",
+            summary,
+            self.reconnects.load(Ordering::Relaxed),
+            details
+        )
+    }
+
+    /// Renders the counters in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        header(
+            &mut text,
+            "klog_lines_total",
+            "counter",
+            "Lines received per pod.",
+        );
+        for ((namespace, pod), count) in self.lines.lock().unwrap().iter() {
+            let _ = writeln!(
+                text,
+                "klog_lines_total{{namespace=\"{}\",pod=\"{}\"}} {}",
+                escape(namespace),
+                escape(pod),
+                count
+            );
+        }
+        let values = [
+            (
+                "klog_bytes_total",
+                "counter",
+                "Bytes of log lines received.",
+                self.bytes.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "klog_active_streams",
+                "gauge",
+                "Container log streams currently open.",
+                self.active_streams.load(Ordering::Relaxed),
+            ),
+            (
+                "klog_reconnects_total",
+                "counter",
+                "Log streams reopened, e.g. after a container restart.",
+                self.reconnects.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "klog_filter_matches_total",
+                "counter",
+                "Lines that passed the filters.",
+                self.filter_matches.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "klog_dropped_lines_total",
+                "counter",
+                "Lines dropped for sinks that could not keep up.",
+                self.dropped_lines.load(Ordering::Relaxed) as i64,
+            ),
+        ];
+        for (name, kind, help, value) in values {
+            header(&mut text, name, kind, help);
+            let _ = writeln!(text, "{} {}", name, value);
+        }
+        text
+    }
+}
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// Parses an address to serve metrics on, where a missing host like in `:9090` means all
+/// interfaces.
+pub fn parse_addr(addr: &str) -> Result<SocketAddr, String> {
+    let addr = match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => addr.to_string(),
+    };
+    addr.parse()
+        .map_err(|_| format!("Invalid address {}, expected e.g. :9090", addr))
+}
+
+/// Prints `Metrics::status` to stderr every `interval` until the process exits, counting
+/// streams as stalled when they received nothing for an interval.
+pub async fn report_status(interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        diagnostics::notice(METRICS.status(interval));
+    }
+}
+
+/// Serves the metrics over HTTP on every path until the process exits.
+pub async fn serve(listener: TcpListener) -> anyhow::Result<()> {
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            // The request is not needed, but is read so clients see a complete exchange.
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let body = METRICS.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.line_received("shop", "web-1", "app", "hello");
+        metrics.line_received("shop", "web-1", "app", "world");
+        metrics.line_received("shop", "web-\"2", "app", "!");
+        let stream = metrics.stream_started("shop", "web-1", "app");
+        let _other = metrics.stream_started("shop", "web-2", "app");
+        drop(stream);
+        metrics.reconnected("shop", "web-1", "app");
+
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE klog_lines_total counter\n"));
+        assert!(text.contains("klog_lines_total{namespace=\"shop\",pod=\"web-1\"} 2\n"));
+        assert!(text.contains("klog_lines_total{namespace=\"shop\",pod=\"web-\\\"2\"} 1\n"));
+        assert!(text.contains("\nklog_bytes_total 14\n"));
+        assert!(text.contains("\nklog_active_streams 1\n"));
+        assert!(text.contains("\nklog_reconnects_total 1\n"));
+        assert!(text.contains("\nklog_filter_matches_total 0\n"));
+    }
+
+    #[test]
+    fn test_status() {
+        let metrics = Metrics::default();
+        let _web_1 = metrics.stream_started("shop", "web-1", "app");
+        let web_2 = metrics.stream_started("shop", "web-2", "app");
+        metrics.set_state("shop", "web-3", "app", StreamState::Waiting);
+        drop(web_2);
+        metrics.reconnected("shop", "web-2", "app");
+
+        assert_eq!(
+            metrics.status(Duration::from_secs(60)),
+            "Streams: 1 streaming, 0 stalled, 1 reconnecting, 1 waiting, 0 ended, 1 reconnects\n  shop/web-2 [app] reconnecting, opened 0s ago, 1 reconnects"
+        );
+        let status = metrics.status(Duration::ZERO);
+        assert!(status.starts_with("Streams: 0 streaming, 1 stalled, 1 reconnecting"));
+        assert!(status.contains("\n  shop/web-1 [app] stalled, opened 0s ago, 0 reconnects"));
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(
+            parse_addr(":9090").unwrap(),
+            "0.0.0.0:9090".parse().unwrap()
+        );
+        assert_eq!(
+            parse_addr("127.0.0.1:9090").unwrap(),
+            "127.0.0.1:9090".parse().unwrap()
+        );
+        assert!(parse_addr("localhost").is_err());
+    }
+}
@@ -0,0 +1,1790 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use colored::Colorize;
+use itertools::Itertools;
+use k8s_openapi::chrono::{DateTime, Utc};
+use k8s_openapi::serde_json::{self, Value};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::diagnostics;
+use crate::error::KlogError;
+use crate::files::{PodFiles, RotatingFile, Segment};
+use crate::hooks::Hooks;
+use crate::metrics::METRICS;
+use crate::util;
+
+/// How long identical lines from replicas are held back to be merged.
+pub const MERGE_WINDOW: Duration = Duration::from_millis(500);
+/// How long repeats of a line are counted before it is written with `--dedup`.
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(1);
+/// How long a line waits for continuation lines with `--join-multiline`.
+pub const JOIN_WINDOW: Duration = Duration::from_millis(200);
+/// How long lines are held back to be written in timestamp order with `--sort-by-time`.
+pub const SORT_WINDOW: Duration = Duration::from_millis(500);
+/// How many lines are buffered for the writer before the streams are slowed down, and for
+/// each sink before lines are dropped for it.
+pub const DEFAULT_BUFFER_LINES: usize = 10_000;
+
+/// A single line of log output from a pod.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LogLine {
+    /// The kubeconfig context of the cluster the pod runs in, when streaming several.
+    #[serde(default)]
+    pub context: Option<String>,
+    #[serde(default)]
+    pub namespace: String,
+    pub pod: String,
+    /// The container the line came from, set when streaming all containers of a pod.
+    #[serde(default)]
+    pub container: Option<String>,
+    /// The workload the pod was discovered through, e.g. `deployment/web`.
+    #[serde(default)]
+    pub resource: Option<String>,
+    /// The RFC3339 timestamp the API server recorded for the line, if requested.
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    pub line: String,
+    /// Marks a line klog adds when a container restarted, rather than one it wrote.
+    #[serde(default)]
+    pub restart: bool,
+}
+
+/// A line with what klog knows about it, as yielded by `PodManager::events`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEvent {
+    /// The kubeconfig context of the cluster the pod runs in, when streaming several.
+    pub context: Option<String>,
+    pub namespace: String,
+    pub pod: String,
+    /// The container the line came from, set when streaming all containers of a pod.
+    pub container: Option<String>,
+    /// The workload the pod was discovered through, e.g. `deployment/web`.
+    pub resource: Option<String>,
+    /// When the API server recorded the line, if timestamps were requested.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The line as the container wrote it.
+    pub raw: String,
+    /// The fields of JSON and logfmt lines.
+    pub parsed: Option<Value>,
+    /// Marks a line klog adds when a container restarted, rather than one it wrote.
+    pub restart: bool,
+}
+
+impl From<LogLine> for LogEvent {
+    fn from(line: LogLine) -> Self {
+        LogEvent {
+            timestamp: line
+                .timestamp
+                .as_deref()
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+                .map(|timestamp| timestamp.with_timezone(&Utc)),
+            parsed: util::maybe_parse_structured(&line.line),
+            context: line.context,
+            namespace: line.namespace,
+            pod: line.pod,
+            container: line.container,
+            resource: line.resource,
+            raw: line.line,
+            restart: line.restart,
+        }
+    }
+}
+
+/// How several `--filter` texts are combined.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FilterMode {
+    /// Lines must contain at least one of the texts.
+    #[default]
+    Any,
+    /// Lines must contain all of the texts.
+    All,
+}
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// The lines exactly as the containers wrote them.
+    Raw,
+    /// The lines prefixed with their pod, with JSON lines summarized.
+    #[default]
+    Pretty,
+    /// Like pretty, but with JSON lines written in full, indented and highlighted.
+    JsonPretty,
+    /// One JSON object per line (NDJSON) with the pod's metadata, which can be replayed.
+    #[value(alias = "ndjson")]
+    Json,
+    /// The pod's metadata and the line as logfmt, with JSON fields flattened.
+    Logfmt,
+}
+
+/// What `--relative-time` measures offsets of timestamps from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RelativeTo {
+    /// The time the line is written.
+    Now,
+    /// The time klog started.
+    Start,
+}
+
+/// Settings that control how log lines are rendered.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Only lines containing these texts are written, any or all of them depending on
+    /// `filter_mode`.
+    pub filter: Vec<String>,
+    pub filter_mode: FilterMode,
+    /// Only lines matching this regex are written.
+    pub filter_regex: Option<Regex>,
+    /// Lines matching this regex are dropped.
+    pub exclude: Option<Regex>,
+    /// Match `filter`, `filter_regex` and `exclude` regardless of case.
+    pub ignore_case: bool,
+    /// Lines with a lower level are dropped. Lines without a recognizable level are kept.
+    pub min_level: Option<util::Level>,
+    /// Conditions on fields that JSON lines must all meet. Other lines are dropped.
+    pub json_filter: Vec<util::JsonFilter>,
+    /// How many lines of the same pod to show before a line passing the filters.
+    pub before_context: usize,
+    /// How many lines of the same pod to show after a line passing the filters.
+    pub after_context: usize,
+    pub output: OutputMode,
+    /// Show timestamps of pretty output, both in lines and from Kubernetes, in this zone.
+    pub time_zone: Option<util::TimeZone>,
+    /// Show timestamps of pretty output as offsets like `-2m13s`, taking precedence over
+    /// `time_zone`.
+    pub relative_time: Option<RelativeTo>,
+    pub merge_identical_pods: bool,
+    /// Hold lines back for `SORT_WINDOW` and write them in the order of their timestamps
+    /// across pods, instead of the order they arrive in.
+    pub sort_by_time: bool,
+    /// Remove the timestamps of lines after sorting them, when they were only requested
+    /// for `sort_by_time`.
+    pub hide_timestamps: bool,
+    pub color_salt: String,
+    pub palette: util::Palette,
+    /// Color the lines themselves by their level, e.g. errors red.
+    pub color_levels: bool,
+    /// Write lines a container repeats in a row once, with the number of repeats.
+    pub dedup: bool,
+    /// Remove escape sequences like colors that containers wrote from the lines.
+    pub strip_ansi: bool,
+    /// Append continuation lines of stack traces to the line before them, so they are
+    /// filtered and written as one.
+    pub join_multiline: bool,
+    /// Show these fields of JSON and logfmt lines in pretty output, instead of their level,
+    /// timestamp and message.
+    pub fields: Vec<String>,
+    /// Cut lines of pretty output longer than this many characters.
+    pub max_line_length: Option<usize>,
+    pub json_level_normalize: bool,
+    /// Prefix lines with `namespace/pod` instead of just the pod name.
+    pub show_namespace: bool,
+    /// A template like `{namespace}/{pod}[{container}]` for the prefix of lines, replacing
+    /// the pod name. Fields that are unknown for a line are left empty.
+    pub prefix: Option<String>,
+    /// Write lines of pretty output without a prefix.
+    pub no_prefix: bool,
+    /// Also write the lines of each pod to its own file in this directory.
+    pub output_dir: Option<PathBuf>,
+    /// Also write the lines of all pods to this file, prefixed with their pod.
+    pub log_file: Option<PathBuf>,
+    /// Rotate `log_file` once it reaches this many bytes.
+    pub rotate_size: Option<u64>,
+    /// How many rotated files of `log_file` to keep.
+    pub keep: usize,
+    /// Gzip rotated files of `log_file`.
+    pub compress: bool,
+    /// Write lines only to files, not to the output.
+    pub no_stdout: bool,
+    /// Send rotated files of `log_file` here, e.g. to upload them.
+    pub segments: Option<mpsc::Sender<Segment>>,
+    /// Also forward the lines to these sinks, see `crate::sinks`. Lines for a sink whose
+    /// buffer is full are dropped, so that a slow sink does not hold up the others.
+    pub sinks: Vec<mpsc::Sender<LogLine>>,
+    /// Write at most this many lines, then cancel `shutdown`.
+    pub max_lines: Option<usize>,
+    /// Cancel `shutdown` and fail with `KlogError::Idle` if no line arrives for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Cancelled to end the streams, see `PodManager::shutdown`.
+    pub shutdown: CancellationToken,
+    /// Transform or drop lines before they are filtered and output.
+    pub hooks: Hooks,
+}
+
+impl RenderOptions {
+    /// Prepares the filters to match regardless of case if `ignore_case` is set.
+    pub fn fold_case(&mut self) {
+        if self.ignore_case {
+            for filter in &mut self.filter {
+                *filter = filter.to_lowercase();
+            }
+            self.filter_regex = self.filter_regex.as_ref().map(util::case_insensitive);
+            self.exclude = self.exclude.as_ref().map(util::case_insensitive);
+        }
+    }
+
+    /// Whether a line passes the filters, which must be prepared with `fold_case` first.
+    pub fn matches_filters(&self, line: &str) -> bool {
+        let filters = &self.filter;
+        let text = if self.ignore_case {
+            Cow::Owned(line.to_lowercase())
+        } else {
+            Cow::Borrowed(line)
+        };
+        let matches_filter = match self.filter_mode {
+            FilterMode::Any => filters.iter().any(|filter| text.contains(filter.as_str())),
+            FilterMode::All => filters.iter().all(|filter| text.contains(filter.as_str())),
+        };
+        if !filters.is_empty() && !matches_filter {
+            return false;
+        }
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(line) {
+                return false;
+            }
+        }
+        if !self.json_filter.is_empty() {
+            let matches_json = util::maybe_parse_json(line)
+                .is_some_and(|json| self.json_filter.iter().all(|filter| filter.matches(&json)));
+            if !matches_json {
+                return false;
+            }
+        }
+        if let Some(min_level) = self.min_level {
+            if util::detect_level(line).is_some_and(|level| level < min_level) {
+                return false;
+            }
+        }
+        self.filter_regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(line))
+    }
+}
+
+/// Lines around filter matches that are kept for a single pod.
+#[derive(Default)]
+struct PodContext {
+    /// The most recent lines that did not pass the filters.
+    before: VecDeque<LogLine>,
+    /// How many more lines to show after the last match.
+    after: usize,
+}
+
+struct PendingLine {
+    line: LogLine,
+    pods: Vec<String>,
+    /// How often the line was repeated in a row, see `RepeatedLine`.
+    repeats: usize,
+    deadline: Instant,
+}
+
+/// A line that is held back to append the continuation lines following it.
+struct JoinedLine {
+    line: LogLine,
+    deadline: Instant,
+}
+
+/// A line that is held back to be written in timestamp order.
+struct SortedLine {
+    line: LogLine,
+    deadline: Instant,
+}
+
+/// A line that is held back to count how often its pod repeats it in a row.
+struct RepeatedLine {
+    line: LogLine,
+    repeats: usize,
+    deadline: Instant,
+}
+
+/// Writes the log lines of all pods to a single output.
+pub struct Writer<W: Write> {
+    out: W,
+    options: RenderOptions,
+    colors: HashMap<String, util::Color>,
+    pending: VecDeque<PendingLine>,
+    /// Lines held back with `sort_by_time`, keyed by their timestamp and arrival.
+    sorted: BTreeMap<(DateTime<Utc>, u64), SortedLine>,
+    /// How many lines were held back for sorting, to keep lines with the same timestamp
+    /// in the order they arrived.
+    arrivals: u64,
+    /// Matches the parts of lines found by the filters, to be highlighted.
+    highlight: Option<Regex>,
+    /// Context lines of each pod, keyed by namespace and pod.
+    context: HashMap<(String, String), PodContext>,
+    /// When the writer was created, for timestamps relative to the start.
+    started: DateTime<Utc>,
+    /// The last line of each container, keyed by namespace, pod and container, while
+    /// its repeats are counted.
+    repeated: HashMap<(String, String, Option<String>), RepeatedLine>,
+    /// The last line of each container while continuation lines are appended to it.
+    joined: HashMap<(String, String, Option<String>), JoinedLine>,
+    /// The file of each pod with `output_dir`.
+    files: Option<PodFiles>,
+    log_file: Option<RotatingFile>,
+    /// Lines dropped for sinks that could not keep up.
+    dropped: u64,
+    /// How many lines were written, for `max_lines`.
+    written: usize,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W, mut options: RenderOptions) -> Self {
+        options.fold_case();
+        let highlight = highlight_regex(&options);
+        let files = options.output_dir.clone().map(PodFiles::new);
+        let log_file = options.log_file.clone().map(|path| {
+            RotatingFile::new(path, options.rotate_size, options.keep)
+                .compress(options.compress)
+                .segments(options.segments.clone())
+        });
+        Writer {
+            out,
+            options,
+            colors: HashMap::new(),
+            pending: VecDeque::new(),
+            sorted: BTreeMap::new(),
+            arrivals: 0,
+            highlight,
+            context: HashMap::new(),
+            started: Utc::now(),
+            repeated: HashMap::new(),
+            joined: HashMap::new(),
+            files,
+            log_file,
+            dropped: 0,
+            written: 0,
+        }
+    }
+
+    /// Writes lines until all senders are dropped, then returns the output.
+    pub async fn run(mut self, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<W> {
+        let mut idle_deadline = self
+            .options
+            .idle_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut idle = None;
+        loop {
+            let deadline = self
+                .pending
+                .front()
+                .map(|pending| pending.deadline)
+                .into_iter()
+                .chain(self.repeated.values().map(|repeated| repeated.deadline))
+                .chain(self.joined.values().map(|joined| joined.deadline))
+                .chain(self.sorted.values().map(|sorted| sorted.deadline))
+                .min();
+            tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => {
+                        if let (Some(deadline), Some(timeout)) =
+                            (&mut idle_deadline, self.options.idle_timeout)
+                        {
+                            *deadline = Instant::now() + timeout;
+                        }
+                        self.sort(line)?
+                    }
+                    None => break,
+                },
+                // The streams end once cancelled, and what they sent so far is still written.
+                _ = tokio::time::sleep_until(idle_deadline.unwrap_or_else(Instant::now)), if idle_deadline.is_some() => {
+                    idle = self.options.idle_timeout;
+                    idle_deadline = None;
+                    self.options.shutdown.cancel();
+                }
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    self.flush_until(Instant::now())?;
+                }
+            }
+        }
+        self.flush_until(Instant::now() + SORT_WINDOW + MERGE_WINDOW + DEDUP_WINDOW + JOIN_WINDOW)?;
+        if let Some(files) = &mut self.files {
+            files.close()?;
+        }
+        if let Some(log_file) = &mut self.log_file {
+            log_file.close()?;
+        }
+        self.out.flush()?;
+        if self.dropped > 0 {
+            diagnostics::notice(format_args!(
+                "Dropped {} lines that sinks could not keep up with",
+                self.dropped
+            ));
+        }
+        if let Some(timeout) = idle {
+            return Err(KlogError::Idle(timeout).into());
+        }
+        Ok(self.out)
+    }
+
+    /// Holds a line back to write it in timestamp order with `--sort-by-time`. Lines without
+    /// a timestamp are sorted as if they were written when they arrived.
+    fn sort(&mut self, line: LogLine) -> std::io::Result<()> {
+        if !self.options.sort_by_time {
+            return self.push(line);
+        }
+        let time = line
+            .timestamp
+            .as_deref()
+            .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+            .map_or_else(Utc::now, |time| time.with_timezone(&Utc));
+        self.arrivals += 1;
+        let sorted = SortedLine {
+            line,
+            deadline: Instant::now() + SORT_WINDOW,
+        };
+        self.sorted.insert((time, self.arrivals), sorted);
+        Ok(())
+    }
+
+    /// Takes a line, appending it to the previous line of its container if it continues a
+    /// stack trace and `--join-multiline` is given.
+    fn push(&mut self, mut line: LogLine) -> std::io::Result<()> {
+        if self.options.strip_ansi {
+            if let Cow::Owned(stripped) = util::strip_ansi(&line.line) {
+                line.line = stripped;
+            }
+        }
+        if !self.options.join_multiline {
+            return self.filter(line);
+        }
+
+        let key = (
+            line.namespace.clone(),
+            line.pod.clone(),
+            line.container.clone(),
+        );
+        if util::is_continuation(&line.line) {
+            if let Some(joined) = self.joined.get_mut(&key) {
+                joined.line.line.push('\n');
+                joined.line.line.push_str(&line.line);
+                joined.deadline = Instant::now() + JOIN_WINDOW;
+                return Ok(());
+            }
+        }
+        let joined = JoinedLine {
+            line,
+            deadline: Instant::now() + JOIN_WINDOW,
+        };
+        match self.joined.insert(key, joined) {
+            Some(previous) => self.filter(previous.line),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a line if it passes the filters, along with the context lines around it.
+    fn filter(&mut self, line: LogLine) -> std::io::Result<()> {
+        let Some(line) = self.options.hooks.apply_line(line) else {
+            return Ok(());
+        };
+        let (before, after) = (self.options.before_context, self.options.after_context);
+        let matches = self.options.matches_filters(&line.line);
+        if matches {
+            METRICS.filter_matched();
+        }
+        if before == 0 && after == 0 {
+            return if matches { self.emit(line) } else { Ok(()) };
+        }
+
+        let key = (line.namespace.clone(), line.pod.clone());
+        let context = self.context.entry(key).or_default();
+        if matches {
+            let lines: Vec<LogLine> = context.before.drain(..).chain([line]).collect();
+            context.after = after;
+            for line in lines {
+                self.emit(line)?;
+            }
+        } else if context.after > 0 {
+            context.after -= 1;
+            self.emit(line)?;
+        } else if before > 0 {
+            if context.before.len() == before {
+                context.before.pop_front();
+            }
+            context.before.push_back(line);
+        }
+        Ok(())
+    }
+
+    /// Writes a line that passed the filters, collapsing repeats of the same container if
+    /// requested.
+    fn emit(&mut self, line: LogLine) -> std::io::Result<()> {
+        if !self.options.dedup {
+            return self.emit_merged(line, 1);
+        }
+
+        let key = (
+            line.namespace.clone(),
+            line.pod.clone(),
+            line.container.clone(),
+        );
+        if let Some(repeated) = self.repeated.get_mut(&key) {
+            if repeated.line.line == line.line {
+                repeated.repeats += 1;
+                return Ok(());
+            }
+        }
+        let repeated = RepeatedLine {
+            line,
+            repeats: 1,
+            deadline: Instant::now() + DEDUP_WINDOW,
+        };
+        match self.repeated.insert(key, repeated) {
+            Some(previous) => self.emit_merged(previous.line, previous.repeats),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a line, merging it with identical lines of other replicas if requested.
+    fn emit_merged(&mut self, line: LogLine, repeats: usize) -> std::io::Result<()> {
+        if !self.options.merge_identical_pods || line.resource.is_none() {
+            return self.write(&line, 1, repeats);
+        }
+
+        // Only lines from other replicas are merged, repeats from the same pod are kept, and
+        // workloads of the same name in other namespaces or clusters are not replicas.
+        let merged = self.pending.iter_mut().find(|pending| {
+            pending.line.resource == line.resource
+                && pending.line.namespace == line.namespace
+                && pending.line.context == line.context
+                && pending.line.line == line.line
+                && pending.repeats == repeats
+                && !pending.pods.contains(&line.pod)
+        });
+        match merged {
+            Some(pending) => pending.pods.push(line.pod),
+            None => self.pending.push_back(PendingLine {
+                pods: vec![line.pod.clone()],
+                line,
+                repeats,
+                deadline: Instant::now() + MERGE_WINDOW,
+            }),
+        }
+        Ok(())
+    }
+
+    fn flush_until(&mut self, now: Instant) -> std::io::Result<()> {
+        // The earliest line is written once it has waited long enough, and later lines that
+        // are due wait for it so that the order holds.
+        while self
+            .sorted
+            .first_key_value()
+            .is_some_and(|(_, sorted)| sorted.deadline <= now)
+        {
+            let (_, mut sorted) = self.sorted.pop_first().unwrap();
+            if self.options.hide_timestamps {
+                sorted.line.timestamp = None;
+            }
+            self.push(sorted.line)?;
+        }
+        let due: Vec<_> = self
+            .joined
+            .iter()
+            .filter(|(_, joined)| joined.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in due {
+            let joined = self.joined.remove(&key).unwrap();
+            self.filter(joined.line)?;
+        }
+        let due: Vec<_> = self
+            .repeated
+            .iter()
+            .filter(|(_, repeated)| repeated.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in due {
+            let repeated = self.repeated.remove(&key).unwrap();
+            self.emit_merged(repeated.line, repeated.repeats)?;
+        }
+        while self
+            .pending
+            .front()
+            .is_some_and(|pending| pending.deadline <= now)
+        {
+            let pending = self.pending.pop_front().unwrap();
+            self.write(&pending.line, pending.pods.len(), pending.repeats)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, line: &LogLine, replicas: usize, repeats: usize) -> std::io::Result<()> {
+        if let Some(max_lines) = self.options.max_lines {
+            // Lines that were on their way when the limit was reached are discarded.
+            if self.written == max_lines {
+                return Ok(());
+            }
+            self.written += 1;
+            if self.written == max_lines {
+                self.options.shutdown.cancel();
+            }
+        }
+        if let Some(files) = &mut self.files {
+            files.write(line)?;
+        }
+        if let Some(log_file) = &mut self.log_file {
+            log_file.write(&to_plain(line))?;
+        }
+        for sink in &self.options.sinks {
+            // A sink that stopped reports its error when it is awaited.
+            if let Err(TrySendError::Full(_)) = sink.try_send(line.clone()) {
+                if self.dropped == 0 {
+                    diagnostics::notice("A sink cannot keep up, dropping lines for it");
+                }
+                self.dropped += 1;
+                METRICS.line_dropped();
+            }
+        }
+        if self.options.no_stdout {
+            return Ok(());
+        }
+        match self.options.output {
+            OutputMode::Raw => writeln!(self.out, "{}", line.line),
+            OutputMode::Pretty | OutputMode::JsonPretty => {
+                self.write_pretty(line, replicas, repeats)
+            }
+            OutputMode::Json => writeln!(self.out, "{}", to_json(line, replicas, repeats)),
+            OutputMode::Logfmt => writeln!(self.out, "{}", to_logfmt(line, replicas, repeats)),
+        }
+    }
+
+    /// Shows the timestamps in `text` relative to a reference time or in another time zone,
+    /// if requested.
+    fn convert_timestamps<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if let Some(relative_to) = self.options.relative_time {
+            let reference = match relative_to {
+                RelativeTo::Now => Utc::now(),
+                RelativeTo::Start => self.started,
+            };
+            return util::replace_timestamps(text, |timestamp| {
+                util::format_offset(timestamp.signed_duration_since(reference))
+            });
+        }
+        match self.options.time_zone {
+            Some(time_zone) => {
+                util::replace_timestamps(text, |timestamp| time_zone.convert(timestamp))
+            }
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    fn write_pretty(
+        &mut self,
+        line: &LogLine,
+        replicas: usize,
+        repeats: usize,
+    ) -> std::io::Result<()> {
+        let full_json = match self.options.output {
+            OutputMode::JsonPretty => util::maybe_parse_json(&line.line),
+            _ => None,
+        };
+        let mut body = match full_json {
+            Some(json) => util::highlight_json(&json),
+            None => util::maybe_parse_structured(&line.line)
+                .and_then(|json| match self.options.fields.as_slice() {
+                    [] => util::get_pretty_json(&json, self.options.json_level_normalize),
+                    fields => util::select_fields(&json, fields),
+                })
+                .unwrap_or_else(|| line.line.clone()),
+        };
+        body = self.convert_timestamps(&body).into_owned();
+        if let Some(max_line_length) = self.options.max_line_length {
+            body = util::truncate(&body, max_line_length).into_owned();
+        }
+        if let Some(highlight) = &self.highlight {
+            body = highlight
+                .replace_all(&body, |captures: &Captures| {
+                    captures[0].red().bold().to_string()
+                })
+                .into_owned();
+        }
+        if self.options.color_levels {
+            body = match util::detect_level(&line.line) {
+                Some(util::Level::Error | util::Level::Fatal) => body.red().to_string(),
+                Some(util::Level::Warn) => body.yellow().to_string(),
+                Some(util::Level::Debug | util::Level::Trace) => body.dimmed().to_string(),
+                _ => body,
+            };
+        }
+        if line.restart {
+            body = body.yellow().bold().to_string();
+        }
+        if let Some(timestamp) = &line.timestamp {
+            let timestamp = self.convert_timestamps(timestamp);
+            body = format!("{} {}", timestamp.dimmed(), body);
+        }
+        if let Some(prefix) = self.prefix(line) {
+            let color = self.colors.entry(line.pod.clone()).or_insert_with(|| {
+                self.options.palette.color_for_pod(
+                    &line.pod,
+                    line.resource.as_deref(),
+                    &self.options.color_salt,
+                )
+            });
+            body = format!("{} {}", prefix.truecolor(color.r, color.g, color.b), body);
+        }
+        if repeats > 1 {
+            body = format!("{} (x{})", body, repeats);
+        }
+        if replicas > 1 {
+            writeln!(self.out, "{} (x{} replicas)", body, replicas)
+        } else {
+            writeln!(self.out, "{}", body)
+        }
+    }
+
+    /// Tells where a line came from, using the `--prefix` template if given.
+    fn prefix(&self, line: &LogLine) -> Option<String> {
+        if self.options.no_prefix {
+            return None;
+        }
+        if let Some(template) = &self.options.prefix {
+            return Some(
+                PREFIX_FIELD
+                    .replace_all(template, |captures: &Captures| {
+                        prefix_field(line, &captures[1]).unwrap_or_default()
+                    })
+                    .into_owned(),
+            );
+        }
+        let mut pod_name = if self.options.show_namespace {
+            format!("{}/{}", line.namespace, line.pod)
+        } else {
+            line.pod.clone()
+        };
+        if let Some(container) = &line.container {
+            pod_name = format!("{}/{}", pod_name, container);
+        }
+        if let Some(context) = &line.context {
+            pod_name = format!("{}/{}", context, pod_name);
+        }
+        Some(pod_name)
+    }
+}
+
+static PREFIX_FIELD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(\w+)\}").unwrap());
+
+/// The fields `--prefix` templates can refer to, e.g. `{pod}`.
+const PREFIX_FIELDS: [&str; 5] = ["context", "namespace", "pod", "container", "resource"];
+
+fn prefix_field(line: &LogLine, field: &str) -> Option<String> {
+    match field {
+        "context" => line.context.clone(),
+        "namespace" => Some(line.namespace.clone()),
+        "pod" => Some(line.pod.clone()),
+        "container" => line.container.clone(),
+        "resource" => line.resource.clone(),
+        _ => None,
+    }
+}
+
+/// Checks that a `--prefix` template only refers to known fields.
+pub fn parse_prefix(template: &str) -> Result<String, String> {
+    for captures in PREFIX_FIELD.captures_iter(template) {
+        if !PREFIX_FIELDS.contains(&&captures[1]) {
+            return Err(format!(
+                "Unknown field {{{}}}, expected one of {}",
+                &captures[1],
+                PREFIX_FIELDS
+                    .iter()
+                    .map(|field| format!("{{{}}}", field))
+                    .join(", ")
+            ));
+        }
+    }
+    Ok(template.to_string())
+}
+
+/// Renders a line without colors after its timestamp and `context/namespace/pod/container`,
+/// for files with the lines of several pods.
+fn to_plain(line: &LogLine) -> String {
+    let origin = line
+        .context
+        .iter()
+        .chain([&line.namespace, &line.pod])
+        .chain(&line.container)
+        .join("/");
+    match &line.timestamp {
+        Some(timestamp) => format!("{} {} {}", timestamp, origin, line.line),
+        None => format!("{} {}", origin, line.line),
+    }
+}
+
+/// Where a line came from, leaving out what is unknown.
+fn metadata(line: &LogLine, replicas: usize, repeats: usize) -> Vec<(String, String)> {
+    [
+        ("context", line.context.clone()),
+        ("namespace", Some(line.namespace.clone())),
+        ("pod", Some(line.pod.clone())),
+        ("container", line.container.clone()),
+        ("resource", line.resource.clone()),
+        ("timestamp", line.timestamp.clone()),
+        ("replicas", (replicas > 1).then(|| replicas.to_string())),
+        ("repeats", (repeats > 1).then(|| repeats.to_string())),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key.to_string(), value?)))
+    .collect()
+}
+
+/// Renders a line as a JSON object with its metadata, its level and the fields of JSON
+/// or logfmt lines as `parsed`. It keeps the fields of `LogLine`, so the output can be
+/// replayed. `container`, `timestamp` and `parsed` are always present, if only as `null`.
+pub fn to_json(line: &LogLine, replicas: usize, repeats: usize) -> serde_json::Value {
+    let mut object: serde_json::Map<String, serde_json::Value> = metadata(line, replicas, repeats)
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+    for key in ["container", "timestamp"] {
+        object.entry(key).or_insert(serde_json::Value::Null);
+    }
+    if let Some(level) = util::detect_level(&line.line) {
+        object.insert("level".to_string(), level.as_str().into());
+    }
+    object.insert(
+        "parsed".to_string(),
+        util::maybe_parse_structured(&line.line).unwrap_or_default(),
+    );
+    object.insert("line".to_string(), line.line.clone().into());
+    object.into()
+}
+
+/// Renders a line as logfmt after its metadata. The fields of JSON or logfmt lines are
+/// kept, other lines are written as `msg`.
+fn to_logfmt(line: &LogLine, replicas: usize, repeats: usize) -> String {
+    let mut pairs = metadata(line, replicas, repeats);
+    match util::maybe_parse_structured(&line.line) {
+        Some(serde_json::Value::Object(fields)) => {
+            pairs.extend(fields.into_iter().map(|(key, value)| match value {
+                serde_json::Value::String(s) => (key, s),
+                value => (key, value.to_string()),
+            }))
+        }
+        _ => pairs.push(("msg".to_string(), line.line.clone())),
+    }
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, util::logfmt_value(value)))
+        .join(" ")
+}
+
+/// Combines `--filter` and `--filter-regex` into one regex finding what they matched.
+pub fn highlight_regex(options: &RenderOptions) -> Option<Regex> {
+    let patterns: Vec<String> = options
+        .filter
+        .iter()
+        .map(|filter| regex::escape(filter))
+        .chain(
+            options
+                .filter_regex
+                .iter()
+                .map(|regex| regex.as_str().to_string()),
+        )
+        .collect();
+    if patterns.is_empty() {
+        return None;
+    }
+    RegexBuilder::new(&patterns.join("|"))
+        .case_insensitive(options.ignore_case)
+        .build()
+        .ok()
+}
+
+/// Renders log lines captured as NDJSON without connecting to a cluster.
+///
+/// Each line must be an object with at least `pod` and `line` fields.
+pub async fn replay<R: BufRead + Send + 'static, W: Write>(
+    reader: R,
+    writer: Writer<W>,
+) -> anyhow::Result<W> {
+    let (tx, rx) = mpsc::channel(DEFAULT_BUFFER_LINES);
+    // Lines are read on their own thread, so that a capture which is still being written,
+    // like the output of `--serve`, is rendered as it arrives.
+    let reading = tokio::task::spawn_blocking(move || {
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let log_line: LogLine = serde_json::from_str(&line)
+                .map_err(|e| anyhow::anyhow!("Invalid log line {}: {}", index + 1, e))?;
+            tx.blocking_send(log_line)?;
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+    let out = writer.run(rx).await?;
+    reading.await??;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn log_line(pod: &str, line: &str) -> LogLine {
+        LogLine {
+            namespace: "default".to_string(),
+            resource: Some("deployment/web".to_string()),
+            ..test_util::log_line(pod, line)
+        }
+    }
+
+    /// Removes colors, which other tests may force on at any time.
+    fn plain(out: Vec<u8>) -> String {
+        let ansi = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        ansi.replace_all(&String::from_utf8(out).unwrap(), "")
+            .into_owned()
+    }
+
+    async fn render(lines: Vec<LogLine>, merge_identical_pods: bool) -> String {
+        let (tx, rx) = mpsc::channel(100);
+        for line in lines {
+            tx.try_send(line).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            merge_identical_pods,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_merge_identical_pods() {
+        let output = render(
+            vec![
+                log_line("web-1", "starting server"),
+                log_line("web-2", "starting server"),
+                log_line("web-3", "starting server"),
+            ],
+            true,
+        )
+        .await;
+
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.contains("starting server (x3 replicas)"));
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_namespaces_apart() {
+        let in_namespace = |namespace: &str, pod: &str| LogLine {
+            namespace: namespace.to_string(),
+            ..log_line(pod, "starting server")
+        };
+        let output = render(
+            vec![
+                in_namespace("staging", "web-1"),
+                in_namespace("prod", "web-1"),
+                in_namespace("prod", "web-2"),
+            ],
+            true,
+        )
+        .await;
+
+        assert_eq!(output.lines().count(), 2);
+        assert!(output.contains("starting server (x2 replicas)"));
+        let context = LogLine {
+            context: Some("eu".to_string()),
+            ..log_line("web-1", "starting server")
+        };
+        let output = render(vec![log_line("web-1", "starting server"), context], true).await;
+        assert_eq!(output.lines().count(), 2);
+        assert!(!output.contains("replicas"));
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_repeats_from_same_pod() {
+        let output = render(
+            vec![
+                log_line("web-1", "retrying"),
+                log_line("web-1", "retrying"),
+                log_line("web-2", "ready"),
+            ],
+            true,
+        )
+        .await;
+
+        assert_eq!(output.lines().count(), 3);
+        assert!(!output.contains("replicas"));
+    }
+
+    #[tokio::test]
+    async fn test_no_merge_by_default() {
+        let output = render(
+            vec![log_line("web-1", "starting"), log_line("web-2", "starting")],
+            false,
+        )
+        .await;
+
+        assert_eq!(output.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sort_by_time() {
+        let (tx, rx) = mpsc::channel(100);
+        for (pod, second, line) in [
+            ("web-1", 2, "second"),
+            ("web-2", 1, "first"),
+            ("web-1", 3, "third"),
+        ] {
+            tx.try_send(LogLine {
+                timestamp: Some(format!("2024-01-01T00:00:0{}Z", second)),
+                ..log_line(pod, line)
+            })
+            .unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            sort_by_time: true,
+            hide_timestamps: true,
+            no_prefix: true,
+            ..RenderOptions::default()
+        };
+        let out = plain(Writer::new(Vec::new(), options).run(rx).await.unwrap());
+
+        assert_eq!(out, "first\nsecond\nthird\n");
+    }
+
+    #[tokio::test]
+    async fn test_max_lines() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in ["one", "two", "three"] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            max_lines: Some(2),
+            no_prefix: true,
+            ..RenderOptions::default()
+        };
+        let shutdown = options.shutdown.clone();
+        let out = plain(Writer::new(Vec::new(), options).run(rx).await.unwrap());
+
+        assert_eq!(out, "one\ntwo\n");
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("web-1", "started")).unwrap();
+        let options = RenderOptions {
+            idle_timeout: Some(Duration::from_millis(50)),
+            ..RenderOptions::default()
+        };
+        let shutdown = options.shutdown.clone();
+        let run = tokio::spawn(Writer::new(Vec::new(), options).run(rx));
+
+        shutdown.cancelled().await;
+        drop(tx);
+        let error = run.await.unwrap().unwrap_err();
+        assert_eq!(crate::error::exit_code(&error), 8);
+    }
+
+    #[tokio::test]
+    async fn test_slow_sink_drops_lines() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in ["one", "two", "three"] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let (sink_tx, mut sink_rx) = mpsc::channel(1);
+        let options = RenderOptions {
+            sinks: vec![sink_tx],
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        // The output gets every line, the sink only what fit in its buffer.
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 3);
+        assert_eq!(sink_rx.recv().await.unwrap().line, "one");
+        assert!(sink_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_are_prettified() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line(
+            "web-1",
+            r#"{"level": "W", "msg": "slow request"}"#,
+        ))
+        .unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            json_level_normalize: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with(" [warn] slow request\n"));
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        colored::control::set_override(true);
+        let capture = r#"{"pod": "web-1", "namespace": "default", "line": "GET /health 200"}
+{"pod": "web-1", "namespace": "default", "line": "{\"level\": \"E\", \"msg\": \"GET /orders 500\"}"}
+
+{"pod": "web-2", "resource": "deployment/web", "line": "GET /orders 200"}
+"#;
+        let options = RenderOptions {
+            filter: vec!["/orders".to_string()],
+            json_level_normalize: true,
+            color_salt: "salt".to_string(),
+            ..RenderOptions::default()
+        };
+
+        let out = replay(capture.as_bytes(), Writer::new(Vec::new(), options))
+            .await
+            .unwrap();
+
+        let prefix = |pod: &str, resource: Option<&str>| {
+            let color = util::color_for_pod(pod, resource, "salt");
+            pod.truecolor(color.r, color.g, color.b).to_string()
+        };
+        let orders = "/orders".red().bold();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{} [error] GET {} 500\n{} GET {} 200\n",
+                prefix("web-1", None),
+                orders,
+                prefix("web-2", Some("deployment/web")),
+                orders
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_invalid_lines() {
+        let writer = Writer::new(Vec::new(), RenderOptions::default());
+        let result = replay("not json\n".as_bytes(), writer).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_show_namespace() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("web-1", "hello")).unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            show_namespace: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert!(String::from_utf8(out).unwrap().contains("default/web-1"));
+    }
+
+    #[tokio::test]
+    async fn test_container_prefix() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(LogLine {
+            container: Some("istio-proxy".to_string()),
+            ..log_line("web-1", "hello")
+        })
+        .unwrap();
+        drop(tx);
+        let out = Writer::new(Vec::new(), RenderOptions::default())
+            .run(rx)
+            .await
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("web-1/istio-proxy"));
+        assert!(out.ends_with(" hello\n"));
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_precedes_json_body() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(LogLine {
+            timestamp: Some("2024-01-01T12:00:00.000000001Z".to_string()),
+            ..log_line("web-1", r#"{"level": "info", "msg": "ready"}"#)
+        })
+        .unwrap();
+        drop(tx);
+        let out = Writer::new(Vec::new(), RenderOptions::default())
+            .run(rx)
+            .await
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("2024-01-01T12:00:00.000000001Z"));
+        assert!(out.ends_with(" [info] ready\n"));
+    }
+
+    #[tokio::test]
+    async fn test_context_prefix() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(LogLine {
+            context: Some("eu-west".to_string()),
+            ..log_line("web-1", "hello")
+        })
+        .unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            show_namespace: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("eu-west/default/web-1"));
+    }
+
+    #[tokio::test]
+    async fn test_filter_regex() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in ["GET /orders 200", "GET /orders 503", "POST /orders 500"] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            filter: vec!["GET".to_string()],
+            filter_regex: Some(Regex::new(r" 5\d\d$").unwrap()),
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let out = plain(out);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with(" GET /orders 503\n"));
+    }
+
+    #[tokio::test]
+    async fn test_exclude() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in ["GET /healthz 200", "GET /orders 200", "GET /readyz 200"] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            exclude: Some(Regex::new("/healthz|/readyz").unwrap()),
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with(" GET /orders 200\n"));
+    }
+
+    async fn render_filtered(filter: &[&str], filter_mode: FilterMode) -> String {
+        let (tx, rx) = mpsc::channel(100);
+        for line in ["order 1 failed", "order 2 shipped", "payment failed"] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            filter: filter.iter().map(|filter| filter.to_string()).collect(),
+            filter_mode,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+        plain(out)
+    }
+
+    #[tokio::test]
+    async fn test_filter_modes() {
+        let any = render_filtered(&["shipped", "payment"], FilterMode::Any).await;
+        assert_eq!(any.lines().count(), 2);
+        assert!(!any.contains("order 1 failed"));
+
+        let all = render_filtered(&["order", "failed"], FilterMode::All).await;
+        assert_eq!(all.lines().count(), 1);
+        assert!(all.ends_with(" order 1 failed\n"));
+    }
+
+    #[tokio::test]
+    async fn test_ignore_case() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in [
+            "Level=ERROR db down",
+            "level=error cache down",
+            "level=info ok",
+        ] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            filter: vec!["Error".to_string()],
+            exclude: Some(Regex::new("CACHE").unwrap()),
+            ignore_case: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let out = plain(out);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with(" Level=ERROR db down\n"));
+    }
+
+    #[tokio::test]
+    async fn test_min_level() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in [
+            r#"{"level": "debug", "msg": "cache miss"}"#,
+            "level=warn msg=slow",
+            "INFO started",
+            "plain line",
+        ] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            min_level: Some(util::Level::Warn),
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("[warn] slow"));
+        assert!(out.contains("plain line"));
+    }
+
+    #[tokio::test]
+    async fn test_restart_marker() {
+        colored::control::set_override(true);
+        let marker = "--- web-1/app restarted ---";
+        let render = |output| async move {
+            let (tx, rx) = mpsc::channel(100);
+            tx.try_send(LogLine {
+                restart: true,
+                ..log_line("web-1", marker)
+            })
+            .unwrap();
+            drop(tx);
+            let options = RenderOptions {
+                output,
+                ..RenderOptions::default()
+            };
+            let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let pretty = render(OutputMode::Pretty).await;
+        assert!(pretty.ends_with(&format!(" {}\n", marker.yellow().bold())));
+        assert_eq!(render(OutputMode::Raw).await, format!("{}\n", marker));
+    }
+
+    #[tokio::test]
+    async fn test_highlight_filter_matches() {
+        colored::control::set_override(true);
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("web-1", "order 7 failed")).unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            filter: vec!["Failed".to_string()],
+            ignore_case: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let highlighted = "failed".red().bold().to_string();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with(&format!(" order 7 {}\n", highlighted)));
+    }
+
+    #[tokio::test]
+    async fn test_context_lines() {
+        let (tx, rx) = mpsc::channel(100);
+        for (pod, line) in [
+            ("web-1", "one"),
+            ("web-1", "two"),
+            ("web-2", "other pod"),
+            ("web-1", "three"),
+            ("web-1", "boom"),
+            ("web-1", "four"),
+            ("web-1", "five"),
+            ("web-1", "six"),
+        ] {
+            tx.try_send(log_line(pod, line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            filter: vec!["boom".to_string()],
+            before_context: 2,
+            after_context: 1,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let bodies: Vec<String> = plain(out)
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1.to_string())
+            .collect();
+        assert_eq!(bodies, vec!["two", "three", "boom", "four"]);
+    }
+
+    #[tokio::test]
+    async fn test_json_filter() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in [
+            r#"{"msg": "ok", "status": 200}"#,
+            r#"{"msg": "failed", "status": 503, "user": {"id": 42}}"#,
+            r#"{"msg": "failed", "status": 500, "user": {"id": 7}}"#,
+            "status=503 plain text",
+        ] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            json_filter: vec![
+                "status>=500".parse().unwrap(),
+                "user.id=42".parse().unwrap(),
+            ],
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let out = plain(out);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with(" failed\n"));
+    }
+
+    async fn render_output(line: &str, output: OutputMode) -> String {
+        let (tx, rx) = mpsc::channel(100);
+        let mut line = log_line("web-1", line);
+        line.container = Some("app".to_string());
+        tx.try_send(line).unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            output,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_output_raw() {
+        let line = r#"{"level": "info", "msg": "started"}"#;
+
+        assert_eq!(
+            render_output(line, OutputMode::Raw).await,
+            format!("{}\n", line)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_json() {
+        let out = render_output(r#"{"level": "WARNING", "msg": "slow"}"#, OutputMode::Json).await;
+
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["pod"], "web-1");
+        assert_eq!(json["container"], "app");
+        assert_eq!(json["level"], "warn");
+        assert_eq!(json["parsed"]["msg"], "slow");
+        assert!(json["timestamp"].is_null());
+        assert!(json.get("context").is_none());
+
+        let replayed: LogLine = serde_json::from_str(&out).unwrap();
+        assert_eq!(replayed.line, r#"{"level": "WARNING", "msg": "slow"}"#);
+
+        let out = render_output("plain text", OutputMode::Json).await;
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert!(json["parsed"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_output_logfmt() {
+        let plain = render_output("order 7 failed", OutputMode::Logfmt).await;
+        assert_eq!(
+            plain,
+            "namespace=default pod=web-1 container=app resource=deployment/web \
+             msg=\"order 7 failed\"\n"
+        );
+
+        let json = render_output(r#"{"msg": "slow", "took": 1.5}"#, OutputMode::Logfmt).await;
+        assert!(json.ends_with(" msg=slow took=1.5\n"));
+    }
+
+    #[tokio::test]
+    async fn test_time_zone() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(LogLine {
+            timestamp: Some("2024-07-01T12:00:00.000000001Z".to_string()),
+            ..log_line(
+                "web-1",
+                r#"{"ts": "2024-07-01T11:59:59Z", "msg": "started"}"#,
+            )
+        })
+        .unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            time_zone: Some(util::TimeZone::Named(chrono_tz::America::New_York)),
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert!(plain(out).ends_with(
+            " 2024-07-01T08:00:00.000000001-04:00 2024-07-01T07:59:59-04:00: started\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relative_time() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(LogLine {
+            timestamp: Some("2024-01-01T12:02:13Z".to_string()),
+            ..log_line("web-1", "ts=2024-01-01T11:59:50Z msg=started")
+        })
+        .unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            relative_time: Some(RelativeTo::Start),
+            ..RenderOptions::default()
+        };
+        let mut writer = Writer::new(Vec::new(), options);
+        writer.started = "2024-01-01T12:00:00Z".parse().unwrap();
+        let out = writer.run(rx).await.unwrap();
+
+        assert!(plain(out).ends_with(" +2m13s -10s: started\n"));
+    }
+
+    #[tokio::test]
+    async fn test_prefix() {
+        let render = |prefix: Option<&str>, no_prefix: bool| {
+            let (tx, rx) = mpsc::channel(100);
+            tx.try_send(log_line("web-1", "started")).unwrap();
+            drop(tx);
+            let options = RenderOptions {
+                prefix: prefix.map(|prefix| parse_prefix(prefix).unwrap()),
+                no_prefix,
+                ..RenderOptions::default()
+            };
+            async move { plain(Writer::new(Vec::new(), options).run(rx).await.unwrap()) }
+        };
+
+        assert_eq!(
+            render(Some("{namespace}/{pod}[{container}]"), false).await,
+            "default/web-1[] started\n"
+        );
+        assert_eq!(
+            render(Some("{resource}:"), false).await,
+            "deployment/web: started\n"
+        );
+        assert_eq!(render(None, true).await, "started\n");
+        assert!(parse_prefix("{node}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_color_levels() {
+        colored::control::set_override(true);
+        let (tx, rx) = mpsc::channel(100);
+        for line in ["ERROR db down", "level=warn msg=slow", "INFO started"] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            color_levels: true,
+            no_prefix: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{}\n{}\nINFO started\n",
+                "ERROR db down".red(),
+                "[warn] slow".yellow()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let (tx, rx) = mpsc::channel(100);
+        for (pod, line) in [
+            ("web-1", "probe failed"),
+            ("web-1", "probe failed"),
+            ("web-2", "probe failed"),
+            ("web-1", "probe failed"),
+            ("web-1", "recovered"),
+            ("web-1", "probe failed"),
+        ] {
+            tx.try_send(log_line(pod, line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            dedup: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        let mut lines: Vec<String> = plain(out).lines().map(str::to_string).collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "web-1 probe failed",
+                "web-1 probe failed (x3)",
+                "web-1 recovered",
+                "web-2 probe failed",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_line_length() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("web-1", "short")).unwrap();
+        tx.try_send(log_line("web-1", &"x".repeat(100))).unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            max_line_length: Some(10),
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert_eq!(plain(out), "web-1 short\nweb-1 xxxxxxxxx…\n");
+    }
+
+    #[tokio::test]
+    async fn test_join_multiline() {
+        let (tx, rx) = mpsc::channel(100);
+        for (pod, line) in [
+            ("web-1", "starting"),
+            (
+                "web-1",
+                "Exception in thread \"main\" java.lang.IllegalStateException: boom",
+            ),
+            ("web-2", "other pod"),
+            ("web-1", "\tat com.example.Main.run(Main.java:12)"),
+            ("web-1", "Caused by: java.io.IOException: disk full"),
+            ("web-1", "at com.example.Disk.write(Disk.java:7)"),
+            ("web-1", "done"),
+        ] {
+            tx.try_send(log_line(pod, line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            filter: vec!["IOException".to_string()],
+            join_multiline: true,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert_eq!(
+            plain(out),
+            "web-1 Exception in thread \"main\" java.lang.IllegalStateException: boom\n\
+             \tat com.example.Main.run(Main.java:12)\n\
+             Caused by: java.io.IOException: disk full\n\
+             at com.example.Disk.write(Disk.java:7)\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_json_pretty() {
+        let out = plain(
+            render_output(
+                r#"{"msg": "slow", "took": [1.5, null]}"#,
+                OutputMode::JsonPretty,
+            )
+            .await
+            .into_bytes(),
+        );
+
+        assert_eq!(
+            out,
+            "web-1/app {\n  \"msg\": \"slow\",\n  \"took\": [\n    1.5,\n    null\n  ]\n}\n"
+        );
+        assert_eq!(
+            render_output("plain text", OutputMode::JsonPretty).await,
+            render_output("plain text", OutputMode::Pretty).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fields() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in [
+            r#"{"msg": "order shipped", "level": "info", "req": {"id": "a1"}, "took": 3}"#,
+            "level=warn msg=slow",
+            "plain text",
+        ] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let options = RenderOptions {
+            fields: vec!["req.id".to_string(), "msg".to_string()],
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert_eq!(
+            plain(out),
+            "web-1 req.id=a1 msg=\"order shipped\"\nweb-1 msg=slow\nweb-1 plain text\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strip_ansi() {
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("web-1", "\x1b[1;31mERROR\x1b[0m db down"))
+            .unwrap();
+        drop(tx);
+        let options = RenderOptions {
+            strip_ansi: true,
+            output: OutputMode::Raw,
+            ..RenderOptions::default()
+        };
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "ERROR db down\n");
+    }
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let (tx, rx) = mpsc::channel(100);
+        for line in [
+            "login user=alice password=hunter2",
+            "GET /healthz",
+            "logout",
+        ] {
+            tx.try_send(log_line("web-1", line)).unwrap();
+        }
+        drop(tx);
+        let mut options = RenderOptions {
+            // Filters see the lines as the hooks left them.
+            exclude: Some(Regex::new("hunter2").unwrap()),
+            output: OutputMode::Raw,
+            ..RenderOptions::default()
+        };
+        let password = Regex::new("password=\\S+").unwrap();
+        options.hooks.push(move |mut event: LogEvent| {
+            event.raw = password
+                .replace_all(&event.raw, "password=***")
+                .into_owned();
+            Some(event)
+        });
+        options
+            .hooks
+            .push(|event: LogEvent| (!event.raw.contains("/healthz")).then_some(event));
+        let out = Writer::new(Vec::new(), options).run(rx).await.unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "login user=alice password=***\nlogout\n"
+        );
+    }
+}
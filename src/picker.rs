@@ -0,0 +1,166 @@
+use std::io::{self, BufRead, Write};
+
+use k8s_openapi::serde_json::Value;
+
+/// How many matching candidates are listed at once.
+const MAX_SHOWN: usize = 20;
+
+/// The kinds listed by `PodManager::list_resources`, with the singular used in targets.
+const KINDS: [(&str, &str); 5] = [
+    ("deployments", "deployment"),
+    ("statefulsets", "statefulset"),
+    ("daemonsets", "daemonset"),
+    ("jobs", "job"),
+    ("cronjobs", "cronjob"),
+];
+
+/// Turns the output of `PodManager::list_resources` into `kind/name` targets, where the
+/// name may be prefixed with its namespace, e.g. `deployment/shop/web`.
+pub fn candidates(resources: &Value) -> Vec<String> {
+    KINDS
+        .iter()
+        .flat_map(|(plural, kind)| {
+            resources
+                .get(plural)
+                .and_then(Value::as_object)
+                .into_iter()
+                .flat_map(|names| names.keys())
+                .map(move |name| format!("{}/{}", kind, name))
+        })
+        .collect()
+}
+
+/// Scores how well `query` matches `candidate` when its characters appear in order,
+/// ignoring case. Consecutive and early matches score higher.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + candidate[position..].iter().position(|&x| x == c)?;
+        score += match previous {
+            Some(previous) if found == previous + 1 => 10,
+            _ => 1,
+        };
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score * 100 - previous.unwrap_or(0) as i64)
+}
+
+/// Lists the candidates matching `query`, best first.
+pub fn matches<'c>(query: &str, candidates: &'c [String]) -> Vec<&'c String> {
+    let mut scored: Vec<_> = candidates
+        .iter()
+        .filter_map(|candidate| Some((fuzzy_score(query, candidate)?, candidate)))
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+    scored.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Lets the user narrow down `candidates` by typing a query, then select some of them by
+/// their numbers. Returns no targets if the input ends first.
+pub fn pick<R: BufRead, W: Write>(
+    candidates: &[String],
+    mut input: R,
+    mut out: W,
+) -> io::Result<Vec<String>> {
+    let mut query = String::new();
+    loop {
+        let shown = matches(&query, candidates);
+        for (index, candidate) in shown.iter().take(MAX_SHOWN).enumerate() {
+            writeln!(out, "{:>3} {}", index + 1, candidate)?;
+        }
+        if shown.len() > MAX_SHOWN {
+            writeln!(out, "    ... {} more", shown.len() - MAX_SHOWN)?;
+        }
+        write!(out, "Type to filter, or numbers to select: ")?;
+        out.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(vec![]);
+        }
+        let selected: Option<Vec<String>> = line
+            .split_whitespace()
+            .map(|number| {
+                let index = number.parse::<usize>().ok()?.checked_sub(1)?;
+                shown
+                    .iter()
+                    .take(MAX_SHOWN)
+                    .nth(index)
+                    .map(|c| c.to_string())
+            })
+            .collect();
+        match selected {
+            Some(selected) if !selected.is_empty() => return Ok(selected),
+            _ => query = line.trim().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::serde_json::json;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_candidates() {
+        let resources = json!({
+            "deployments": { "web": ["web-1"], "api": [] },
+            "statefulsets": {},
+            "cronjobs": { "shop/nightly": [] }
+        });
+
+        assert_eq!(
+            candidates(&resources),
+            vec!["deployment/api", "deployment/web", "cronjob/shop/nightly"]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("dpweb", "deployment/web").is_some());
+        assert!(fuzzy_score("WEB", "deployment/web").is_some());
+        assert!(fuzzy_score("bew", "deployment/web").is_none());
+        assert!(
+            fuzzy_score("web", "deployment/web").unwrap()
+                > fuzzy_score("web", "deployment/worker-backend").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pick_filters_then_selects() {
+        let candidates = names(&["deployment/web", "deployment/api", "job/migrate"]);
+        let mut out = Vec::new();
+
+        let picked = pick(&candidates, "api\n1\n".as_bytes(), &mut out).unwrap();
+
+        assert_eq!(picked, vec!["deployment/api"]);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  3 job/migrate"));
+    }
+
+    #[test]
+    fn test_pick_selects_several() {
+        let candidates = names(&["deployment/web", "deployment/api"]);
+
+        let picked = pick(&candidates, "2 1\n".as_bytes(), io::sink()).unwrap();
+
+        assert_eq!(picked, vec!["deployment/api", "deployment/web"]);
+    }
+
+    #[test]
+    fn test_pick_without_selection() {
+        let candidates = names(&["deployment/web"]);
+
+        assert!(pick(&candidates, "3\n".as_bytes(), io::sink())
+            .unwrap()
+            .is_empty());
+    }
+}
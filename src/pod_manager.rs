@@ -0,0 +1,1963 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::stream::{self, Stream};
+use futures_util::StreamExt;
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, StatefulSet},
+    batch::v1::{CronJob, Job},
+    core::v1::{Pod, Service},
+};
+use k8s_openapi::serde::Deserialize;
+use k8s_openapi::serde_json::{json, Value};
+use k8s_openapi::{Metadata, NamespaceResourceScope, Resource};
+use kube::api::ObjectMeta;
+use kube::Client;
+use regex::Regex;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::diagnostics;
+use crate::error::KlogError;
+use crate::k8s;
+use crate::metrics::METRICS;
+use crate::output::{LogEvent, LogLine, RenderOptions, Writer, DEFAULT_BUFFER_LINES};
+use crate::traits::HasSpec;
+use crate::util;
+use crate::ResourceType;
+
+/// Events sent from pod log streams back to the manager.
+#[derive(Debug, PartialEq)]
+pub enum PodEvent {
+    /// The log stream of a deleted or terminating pod reached EOF.
+    Terminated { namespace: String, pod: String },
+    /// A watched pod started or changed, see `k8s::watch_pods`.
+    Started(PodTarget),
+    /// A watched pod was deleted.
+    Deleted { namespace: String, pod: String },
+}
+
+/// A pod to stream logs from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PodTarget {
+    pub namespace: String,
+    pub name: String,
+    /// Key of the resource the pod was discovered through, e.g. `deployment/web`.
+    pub resource: Option<String>,
+}
+
+/// Pods to watch for, see `k8s::watch_pods`.
+#[derive(Clone, Debug)]
+struct WatchTarget {
+    namespace: Option<String>,
+    labels: String,
+    fields: String,
+    /// Only pods whose names match this.
+    name: Option<Regex>,
+    resource: Option<String>,
+}
+
+pub struct PodManager<'a> {
+    client: Client,
+    namespaces: Vec<String>,
+    all_namespaces: bool,
+    resources: Vec<ResourceType<'a>>,
+    pods: Vec<String>,
+    selector: Option<String>,
+    node: Option<String>,
+    exclude_pods: Option<Regex>,
+    options: k8s::StreamOptions,
+    refresh_interval: Duration,
+    reconnect_on_rollout: bool,
+    latest_only: bool,
+    watch: bool,
+    notify_removed: bool,
+    wait_for_resources: bool,
+    watch_pod_statuses: bool,
+    render_options: RenderOptions,
+    /// A permit for each pod that may be streamed at once.
+    slots: Arc<Semaphore>,
+    active_pods: HashMap<(String, String), JoinHandle<anyhow::Result<()>>>,
+    /// Streams of ephemeral containers, keyed by namespace, pod and container.
+    active_containers: HashMap<(String, String, String), JoinHandle<anyhow::Result<()>>>,
+    events_tx: mpsc::UnboundedSender<PodEvent>,
+    events_rx: Option<mpsc::UnboundedReceiver<PodEvent>>,
+    lines_tx: mpsc::Sender<LogLine>,
+    lines_rx: Option<mpsc::Receiver<LogLine>>,
+}
+
+impl<'a> PodManager<'a> {
+    pub fn new(
+        client: Client,
+        namespaces: Vec<String>,
+        resources: Vec<ResourceType<'a>>,
+        pods: Vec<String>,
+        options: k8s::StreamOptions,
+    ) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (lines_tx, lines_rx) = mpsc::channel(DEFAULT_BUFFER_LINES);
+        PodManager {
+            client,
+            namespaces,
+            all_namespaces: false,
+            resources,
+            pods,
+            selector: None,
+            node: None,
+            exclude_pods: None,
+            options,
+            refresh_interval: Duration::from_secs(5),
+            reconnect_on_rollout: false,
+            latest_only: false,
+            watch: false,
+            notify_removed: false,
+            wait_for_resources: false,
+            watch_pod_statuses: false,
+            render_options: RenderOptions::default(),
+            slots: Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+            active_pods: HashMap::new(),
+            active_containers: HashMap::new(),
+            events_tx,
+            events_rx: Some(events_rx),
+            lines_tx,
+            lines_rx: Some(lines_rx),
+        }
+    }
+
+    /// Looks up pods and resources by name in all namespaces instead of just one.
+    pub fn all_namespaces(mut self, all_namespaces: bool) -> Self {
+        self.all_namespaces = all_namespaces;
+        self
+    }
+
+    /// Also streams all pods matching this label selector.
+    pub fn selector(mut self, selector: Option<String>) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Also streams all pods scheduled on this node, restricted to those matching the
+    /// label selector if one is set.
+    pub fn node(mut self, node: Option<String>) -> Self {
+        self.node = node;
+        self
+    }
+
+    /// Skips discovered pods whose names match this regex, whatever they were found through.
+    pub fn exclude_pods(mut self, exclude_pods: Option<Regex>) -> Self {
+        self.exclude_pods = exclude_pods;
+        self
+    }
+
+    pub fn refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    pub fn reconnect_on_rollout(mut self, reconnect_on_rollout: bool) -> Self {
+        self.reconnect_on_rollout = reconnect_on_rollout;
+        self
+    }
+
+    /// Only streams the most recent Job spawned by each CronJob.
+    pub fn latest_only(mut self, latest_only: bool) -> Self {
+        self.latest_only = latest_only;
+        self
+    }
+
+    /// Watches the pods of the targets while following, so that new pods are streamed as
+    /// soon as they start. Only targets without a label selector, like CronJobs, are still
+    /// looked for every `refresh_interval`.
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Reports on stderr when a streamed pod is deleted.
+    pub fn notify_removed(mut self, notify_removed: bool) -> Self {
+        self.notify_removed = notify_removed;
+        self
+    }
+
+    /// Waits for workloads that do not exist yet to be created before streaming, instead of
+    /// failing.
+    pub fn wait_for_resources(mut self, wait_for_resources: bool) -> Self {
+        self.wait_for_resources = wait_for_resources;
+        self
+    }
+
+    /// Keeps the status of the pods in the namespaces up to date in `METRICS`, e.g. for the
+    /// TUI.
+    pub fn watch_pod_statuses(mut self, watch_pod_statuses: bool) -> Self {
+        self.watch_pod_statuses = watch_pod_statuses;
+        self
+    }
+
+    /// Streams at most this many pods at once, so that a selector matching hundreds of pods
+    /// does not open as many connections. Further pods wait until a stream ends.
+    pub fn max_concurrent(mut self, max_concurrent: Option<usize>) -> Self {
+        let permits = max_concurrent.map_or(Semaphore::MAX_PERMITS, |max| max.max(1));
+        self.slots = Arc::new(Semaphore::new(permits));
+        self
+    }
+
+    /// Ends the streams once `shutdown` is cancelled, writing out what they read so far.
+    pub fn shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.options.shutdown = shutdown;
+        self
+    }
+
+    pub fn render_options(mut self, render_options: RenderOptions) -> Self {
+        self.render_options = render_options;
+        self
+    }
+
+    /// How many lines are buffered for the writer before the streams are slowed down, so
+    /// that pods logging faster than the output drains do not grow memory without bound.
+    pub fn buffer_lines(mut self, buffer_lines: usize) -> Self {
+        let (lines_tx, lines_rx) = mpsc::channel(buffer_lines.max(1));
+        self.lines_tx = lines_tx;
+        self.lines_rx = Some(lines_rx);
+        self
+    }
+
+    /// Sends log lines to `output` instead of writing them itself, so the lines of several
+    /// managers can be written together.
+    pub fn output(mut self, output: mpsc::Sender<LogLine>) -> Self {
+        self.lines_tx = output;
+        self.lines_rx = None;
+        self
+    }
+
+    /// Fails with the permissions the user lacks to stream the targets, instead of failing
+    /// each stream on its own. Clusters that do not answer the access reviews are not
+    /// checked.
+    async fn check_permissions(&self) -> anyhow::Result<()> {
+        let namespaces = match self.all_namespaces {
+            true => vec![None],
+            false => self.namespaces.iter().map(|n| Some(n.as_str())).collect(),
+        };
+        // Pods given by name are fetched one by one, anything else is found by listing,
+        // including pods given by a regex.
+        let lists = self.all_namespaces
+            || self.watch
+            || !self.resources.is_empty()
+            || self.selector.is_some()
+            || self.node.is_some()
+            || self
+                .pods
+                .iter()
+                .any(|pod| util::pod_name_regex(pod).ok().flatten().is_some());
+        // Without `watch pods`, watching for new pods or their statuses retries forever.
+        let watches = self.watch || self.watch_pod_statuses;
+        let mut missing = vec![];
+        for namespace in namespaces {
+            for (verb, resource) in [("list", "pods"), ("watch", "pods"), ("get", "pods/log")] {
+                if (verb == "list" && !lists) || (verb == "watch" && !watches) {
+                    continue;
+                }
+                if let Ok(false) = k8s::can_i(&self.client, verb, resource, namespace).await {
+                    missing.push(match namespace {
+                        Some(namespace) => {
+                            format!("{} {} in namespace {}", verb, resource, namespace)
+                        }
+                        None => format!("{} {} in all namespaces", verb, resource),
+                    });
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(KlogError::Forbidden(format!("missing {}", missing.join(", "))).into())
+    }
+
+    /// Namespaces to look for `name` in.
+    ///
+    /// With several namespaces, or all of them, only those containing a `T` called `name`
+    /// are returned, so the same name can be followed wherever it exists.
+    async fn namespaces_for<T>(&self, name: &str) -> anyhow::Result<Vec<String>>
+    where
+        T: Resource<Scope = NamespaceResourceScope>
+            + Clone
+            + for<'de> Deserialize<'de>
+            + Debug
+            + Metadata<Ty = ObjectMeta>,
+    {
+        let namespaces = if self.all_namespaces {
+            k8s::find_namespaces::<T>(&self.client, name).await?
+        } else if self.namespaces.len() == 1 {
+            return Ok(self.namespaces.clone());
+        } else {
+            let mut namespaces = vec![];
+            for namespace in &self.namespaces {
+                if k8s::resource_exists::<T>(&self.client, name, namespace).await? {
+                    namespaces.push(namespace.clone());
+                }
+            }
+            namespaces
+        };
+        if namespaces.is_empty() {
+            return Err(KlogError::ResourceNotFound {
+                kind: T::KIND.to_string(),
+                name: name.to_string(),
+                namespace: None,
+            }
+            .into());
+        }
+        Ok(namespaces)
+    }
+
+    /// Waits until all workloads to stream exist. Returns false if shut down first.
+    async fn await_resources(&self) -> anyhow::Result<bool> {
+        for resource in &self.resources {
+            let created = match resource {
+                ResourceType::Deployment(name) => self.await_resource::<Deployment>(name).await?,
+                ResourceType::StatefulSet(name) => self.await_resource::<StatefulSet>(name).await?,
+                ResourceType::DaemonSet(name) => self.await_resource::<DaemonSet>(name).await?,
+                ResourceType::Job(name) => self.await_resource::<Job>(name).await?,
+                ResourceType::CronJob(name) => self.await_resource::<CronJob>(name).await?,
+                ResourceType::Service(name) => self.await_resource::<Service>(name).await?,
+                // Owners can be of any kind, so they are not waited for.
+                ResourceType::Owner(_) => true,
+            };
+            if !created {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Waits until the `T` called `name` exists in one of the namespaces.
+    async fn await_resource<T>(&self, name: &str) -> anyhow::Result<bool>
+    where
+        T: Resource<Scope = NamespaceResourceScope>
+            + Clone
+            + for<'de> Deserialize<'de>
+            + Debug
+            + Send
+            + 'static
+            + Metadata<Ty = ObjectMeta>,
+    {
+        let namespaces: &[String] = match self.all_namespaces {
+            true => {
+                if !k8s::find_namespaces::<T>(&self.client, name)
+                    .await?
+                    .is_empty()
+                {
+                    return Ok(true);
+                }
+                &[]
+            }
+            false => {
+                for namespace in &self.namespaces {
+                    if k8s::resource_exists::<T>(&self.client, name, namespace).await? {
+                        return Ok(true);
+                    }
+                }
+                &self.namespaces
+            }
+        };
+        diagnostics::notice(format_args!(
+            "Waiting for {} {} to be created",
+            T::KIND,
+            name
+        ));
+        k8s::wait_for_resource::<T>(&self.client, name, namespaces, &self.options.shutdown).await
+    }
+
+    /// Lists the workloads of type `T` with their pods, keyed as `namespace/name` unless
+    /// only a single namespace is used.
+    async fn pod_lists_for<T>(&self) -> anyhow::Result<BTreeMap<String, Vec<String>>>
+    where
+        T: Resource<Scope = NamespaceResourceScope>
+            + Clone
+            + for<'de> Deserialize<'de>
+            + Debug
+            + Metadata<Ty = ObjectMeta>
+            + HasSpec,
+    {
+        if self.all_namespaces {
+            return k8s::get_pod_lists_for_all_resources::<T>(&self.client, None).await;
+        }
+        let mut pod_lists = BTreeMap::new();
+        for namespace in &self.namespaces {
+            for (name, pods) in
+                k8s::get_pod_lists_for_all_resources::<T>(&self.client, Some(namespace)).await?
+            {
+                let key = match self.namespaces.len() {
+                    1 => name,
+                    _ => format!("{}/{}", namespace, name),
+                };
+                pod_lists.insert(key, pods);
+            }
+        }
+        Ok(pod_lists)
+    }
+
+    async fn pods_for_resource<T>(&self, name: &str) -> anyhow::Result<Vec<(String, String)>>
+    where
+        T: Resource<Scope = NamespaceResourceScope>
+            + Clone
+            + for<'de> Deserialize<'de>
+            + Debug
+            + Metadata<Ty = ObjectMeta>
+            + HasSpec,
+    {
+        let retries = self.options.api_retries;
+        let mut pods = vec![];
+        for namespace in k8s::retry(retries, || self.namespaces_for::<T>(name)).await? {
+            let found = k8s::retry(retries, || {
+                k8s::get_pod_list_for_resource::<T>(&self.client, name, &namespace)
+            })
+            .await;
+            let found = match found {
+                Err(e) if k8s::is_not_found(&e) => {
+                    return Err(self.not_found::<T>(e, name, &namespace).await)
+                }
+                found => found?,
+            };
+            pods.extend(found.into_iter().map(|pod| (namespace.clone(), pod)));
+        }
+        Ok(pods)
+    }
+
+    /// Tells which of a namespace or the `T` called `name` in it is missing when looking up
+    /// the latter failed with `error`.
+    async fn not_found<T: Resource>(
+        &self,
+        error: anyhow::Error,
+        name: &str,
+        namespace: &str,
+    ) -> anyhow::Error {
+        match k8s::namespace_exists(&self.client, namespace).await {
+            Ok(false) => KlogError::NamespaceNotFound(namespace.to_string()).into(),
+            Ok(true) => KlogError::ResourceNotFound {
+                kind: T::KIND.to_string(),
+                name: name.to_string(),
+                namespace: Some(namespace.to_string()),
+            }
+            .into(),
+            // Reading namespaces may not be permitted, so the original error is kept.
+            Err(_) => error,
+        }
+    }
+
+    /// Lists the pods that the object `spec` names owns, directly or through other
+    /// controllers such as ReplicaSets.
+    async fn pods_for_owner(&self, spec: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let (gvk, name) = util::parse_resource_spec(spec)?;
+        let namespaces: Vec<Option<&str>> = if self.all_namespaces {
+            vec![None]
+        } else {
+            self.namespaces.iter().map(|ns| Some(ns.as_str())).collect()
+        };
+        let mut pods = None;
+        for namespace in namespaces {
+            if let Some(found) =
+                k8s::get_pod_list_for_owner(&self.client, &gvk, &name, namespace).await?
+            {
+                pods.get_or_insert_with(Vec::new).extend(found);
+            }
+        }
+        pods.ok_or_else(|| {
+            KlogError::ResourceNotFound {
+                kind: gvk.kind,
+                name,
+                namespace: None,
+            }
+            .into()
+        })
+    }
+
+    /// Lists the pods matching the label and field selectors in the namespaces in use.
+    async fn pods_by_selector(
+        &self,
+        labels: &str,
+        fields: &str,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        if self.all_namespaces {
+            return k8s::get_pods_by_selector(&self.client, None, labels, fields).await;
+        }
+        let mut pods = vec![];
+        for namespace in &self.namespaces {
+            pods.extend(
+                k8s::get_pods_by_selector(&self.client, Some(namespace), labels, fields).await?,
+            );
+        }
+        Ok(pods)
+    }
+
+    async fn all_pods(&self) -> anyhow::Result<Vec<(String, String)>> {
+        self.pods_by_selector("", "").await
+    }
+
+    /// The namespaces in use, or `None` for all of them.
+    fn scopes(&self) -> Vec<Option<String>> {
+        if self.all_namespaces {
+            return vec![None];
+        }
+        self.namespaces.iter().cloned().map(Some).collect()
+    }
+
+    /// The label and field selector of `--selector` and `--node`, with the key of their
+    /// pods.
+    fn selectors(&self) -> Option<(String, String, String)> {
+        let labels = self.selector.clone().unwrap_or_default();
+        let fields = self
+            .node
+            .as_ref()
+            .map(|node| format!("spec.nodeName={}", node))
+            .unwrap_or_default();
+        let key = match (&self.selector, &self.node) {
+            (None, None) => return None,
+            (Some(selector), None) => format!("selector/{}", selector),
+            (None, Some(node)) => format!("node/{}", node),
+            (selector, node) => format!(
+                "node/{}/selector/{}",
+                node.as_deref().unwrap_or_default(),
+                selector.as_deref().unwrap_or_default()
+            ),
+        };
+        Some((labels, fields, key))
+    }
+
+    /// Resolves the requested pods and resources to the pods to stream.
+    pub async fn discover_pods(&self) -> anyhow::Result<Vec<PodTarget>> {
+        let mut pod_list = vec![];
+
+        for pod in &self.pods {
+            if let Some(regex) = util::pod_name_regex(pod)? {
+                pod_list.extend(
+                    self.all_pods()
+                        .await?
+                        .into_iter()
+                        .filter(|(_, name)| regex.is_match(name))
+                        .map(|(namespace, name)| PodTarget {
+                            namespace,
+                            name,
+                            resource: None,
+                        }),
+                );
+                continue;
+            }
+            for namespace in self.namespaces_for::<Pod>(pod).await? {
+                pod_list.push(PodTarget {
+                    namespace,
+                    name: pod.clone(),
+                    resource: None,
+                });
+            }
+        }
+
+        if let Some((labels, fields, key)) = self.selectors() {
+            let pods = self.pods_by_selector(&labels, &fields).await?;
+            pod_list.extend(pods.into_iter().map(|(namespace, name)| PodTarget {
+                namespace,
+                name,
+                resource: Some(key.clone()),
+            }));
+        }
+
+        for resource in &self.resources {
+            let pods = match resource {
+                ResourceType::Deployment(deploy) => {
+                    self.pods_for_resource::<Deployment>(deploy).await?
+                }
+                ResourceType::StatefulSet(statefulset) => {
+                    self.pods_for_resource::<StatefulSet>(statefulset).await?
+                }
+                ResourceType::DaemonSet(ds) => self.pods_for_resource::<DaemonSet>(ds).await?,
+                ResourceType::Job(job) => self.pods_for_resource::<Job>(job).await?,
+                ResourceType::CronJob(cronjob) => {
+                    let mut pods = vec![];
+                    for namespace in self.namespaces_for::<CronJob>(cronjob).await? {
+                        for pod in k8s::get_pod_list_for_cronjob(
+                            &self.client,
+                            cronjob,
+                            &namespace,
+                            self.latest_only,
+                        )
+                        .await?
+                        {
+                            pods.push((namespace.clone(), pod));
+                        }
+                    }
+                    pods
+                }
+                ResourceType::Service(service) => {
+                    let mut pods = vec![];
+                    for namespace in self.namespaces_for::<Service>(service).await? {
+                        for pod in
+                            k8s::get_pod_list_for_service(&self.client, service, &namespace).await?
+                        {
+                            pods.push((namespace.clone(), pod));
+                        }
+                    }
+                    pods
+                }
+                ResourceType::Owner(spec) => self.pods_for_owner(spec).await?,
+            };
+            pod_list.extend(pods.into_iter().map(|(namespace, name)| PodTarget {
+                namespace,
+                name,
+                resource: Some(resource.key()),
+            }));
+        }
+
+        Ok(pod_list)
+    }
+
+    /// The label selectors of the pods of the workloads of type `T` called `name`, with
+    /// their namespaces.
+    async fn resource_selectors<T>(&self, name: &str) -> anyhow::Result<Vec<(String, String)>>
+    where
+        T: Resource<Scope = NamespaceResourceScope>
+            + Clone
+            + for<'de> Deserialize<'de>
+            + Debug
+            + Metadata<Ty = ObjectMeta>
+            + HasSpec,
+    {
+        let mut selectors = vec![];
+        for namespace in self.namespaces_for::<T>(name).await? {
+            let labels =
+                k8s::get_selector_for_resource::<T>(&self.client, name, &namespace).await?;
+            selectors.push((namespace, labels));
+        }
+        Ok(selectors)
+    }
+
+    /// Resolves the requested pods and resources to selectors of their pods, which are
+    /// watched instead of repeating discovery. Also returns whether some targets have no
+    /// selector, so discovery still needs to be repeated for them.
+    async fn watch_targets(&self) -> anyhow::Result<(Vec<WatchTarget>, bool)> {
+        let mut targets = vec![];
+        let mut unwatched = false;
+        let watch_target =
+            |namespace: Option<String>, labels: String, resource: Option<String>| WatchTarget {
+                namespace,
+                labels,
+                fields: String::new(),
+                name: None,
+                resource,
+            };
+
+        for pod in &self.pods {
+            for namespace in self.scopes() {
+                let mut target = watch_target(namespace, String::new(), None);
+                match util::pod_name_regex(pod)? {
+                    Some(regex) => target.name = Some(regex),
+                    None => target.fields = format!("metadata.name={}", pod),
+                }
+                targets.push(target);
+            }
+        }
+
+        if let Some((labels, fields, key)) = self.selectors() {
+            for namespace in self.scopes() {
+                let mut target = watch_target(namespace, labels.clone(), Some(key.clone()));
+                target.fields = fields.clone();
+                targets.push(target);
+            }
+        }
+
+        for resource in &self.resources {
+            let selectors = match resource {
+                ResourceType::Deployment(name) => {
+                    self.resource_selectors::<Deployment>(name).await?
+                }
+                ResourceType::StatefulSet(name) => {
+                    self.resource_selectors::<StatefulSet>(name).await?
+                }
+                ResourceType::DaemonSet(name) => self.resource_selectors::<DaemonSet>(name).await?,
+                ResourceType::Job(name) => self.resource_selectors::<Job>(name).await?,
+                ResourceType::Service(service) => {
+                    let mut selectors = vec![];
+                    for namespace in self.namespaces_for::<Service>(service).await? {
+                        let labels =
+                            k8s::get_selector_for_service(&self.client, service, &namespace)
+                                .await?;
+                        selectors.push((namespace, labels));
+                    }
+                    selectors
+                }
+                ResourceType::CronJob(_) | ResourceType::Owner(_) => {
+                    unwatched = true;
+                    continue;
+                }
+            };
+            targets.extend(selectors.into_iter().map(|(namespace, labels)| {
+                watch_target(Some(namespace), labels, Some(resource.key()))
+            }));
+        }
+
+        Ok((targets, unwatched))
+    }
+
+    /// Watches the pods of a target, sending an event for each pod that starts or is
+    /// deleted until the manager stops.
+    fn spawn_watch(&self, target: WatchTarget) {
+        let client = self.client.clone();
+        let events = self.events_tx.clone();
+        task::spawn(async move {
+            let changes = k8s::watch_pods(
+                &client,
+                target.namespace.as_deref(),
+                &target.labels,
+                &target.fields,
+            );
+            let mut changes = std::pin::pin!(changes);
+            while let Some(change) = changes.next().await {
+                let event = match change {
+                    Ok(k8s::PodChange::Started { namespace, pod }) => {
+                        if target
+                            .name
+                            .as_ref()
+                            .is_some_and(|name| !name.is_match(&pod))
+                        {
+                            continue;
+                        }
+                        PodEvent::Started(PodTarget {
+                            namespace,
+                            name: pod,
+                            resource: target.resource.clone(),
+                        })
+                    }
+                    Ok(k8s::PodChange::Deleted { namespace, pod }) => {
+                        PodEvent::Deleted { namespace, pod }
+                    }
+                    Err(e) => {
+                        diagnostics::error(format_args!("Could not watch pods: {}", e));
+                        continue;
+                    }
+                };
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Watches the status of the pods in a namespace, or in all of them, until shut down.
+    fn spawn_status_watch(&self, namespace: Option<String>) {
+        let client = self.client.clone();
+        let shutdown = self.options.shutdown.clone();
+        task::spawn(async move {
+            let statuses = k8s::watch_pod_statuses(&client, namespace.as_deref());
+            let mut statuses = std::pin::pin!(statuses);
+            loop {
+                let change = tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    change = statuses.next() => change,
+                };
+                match change {
+                    Some(Ok((namespace, pod, status))) => {
+                        METRICS.set_pod_status(&namespace, &pod, status)
+                    }
+                    Some(Err(e)) => {
+                        diagnostics::error(format_args!("Could not watch pod status: {}", e))
+                    }
+                    None => return,
+                }
+            }
+        });
+    }
+
+    /// Maps every workload in the namespaces to the pods it currently resolves to,
+    /// grouped by kind, without starting any log streams.
+    pub async fn list_resources(&self) -> anyhow::Result<Value> {
+        Ok(json!({
+            "deployments": self.pod_lists_for::<Deployment>().await?,
+            "statefulsets": self.pod_lists_for::<StatefulSet>().await?,
+            "daemonsets": self.pod_lists_for::<DaemonSet>().await?,
+            "jobs": self.pod_lists_for::<Job>().await?,
+            "cronjobs": self.pod_lists_for::<CronJob>().await?,
+        }))
+    }
+
+    /// Lists the pods that would be streamed with their containers, without streaming them.
+    pub async fn dry_run(&self) -> anyhow::Result<Vec<(PodTarget, Vec<String>)>> {
+        let mut streams = vec![];
+        for target in self.discover_pods().await? {
+            if let Some(exclude) = &self.exclude_pods {
+                if exclude.is_match(&target.name) {
+                    continue;
+                }
+            }
+            let containers = k8s::containers_to_stream(
+                &self.client,
+                &target.name,
+                &target.namespace,
+                &self.options,
+            )
+            .await?;
+            streams.push((target, containers));
+        }
+        Ok(streams)
+    }
+
+    /// Starts a log stream for every discovered pod that is not streamed yet.
+    ///
+    /// With ephemeral containers enabled, pods that are already streamed are checked for
+    /// newly started ephemeral containers as well, since those are added at runtime.
+    pub async fn discover_and_start_new_pods(&mut self) -> anyhow::Result<()> {
+        let targets = self.discover_pods().await?;
+        tracing::debug!(pods = targets.len(), "Discovered pods");
+        let discovered: HashSet<(String, String)> = targets
+            .iter()
+            .map(|target| (target.namespace.clone(), target.name.clone()))
+            .collect();
+        let removed: Vec<(String, String)> = self
+            .active_pods
+            .keys()
+            .filter(|key| !discovered.contains(*key))
+            .cloned()
+            .collect();
+        for (namespace, pod) in removed {
+            self.remove_pod(&namespace, &pod);
+        }
+        for target in targets {
+            self.start_pod(target).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a log stream for the pod unless it is excluded or streamed already.
+    async fn start_pod(&mut self, target: PodTarget) -> anyhow::Result<()> {
+        if let Some(exclude) = &self.exclude_pods {
+            if exclude.is_match(&target.name) {
+                return Ok(());
+            }
+        }
+        if self.options.ephemeral_containers {
+            self.start_ephemeral_containers(&target).await?;
+        }
+        let key = (target.namespace.clone(), target.name.clone());
+        if !self.active_pods.contains_key(&key) {
+            tracing::debug!(
+                namespace = %target.namespace,
+                pod = %target.name,
+                resource = ?target.resource,
+                "Streaming pod"
+            );
+            let handle = self.spawn_stream(target);
+            self.active_pods.insert(key, handle);
+        }
+        Ok(())
+    }
+
+    /// Stops the streams of a deleted pod and forgets them, so that it is streamed again
+    /// if it comes back.
+    fn remove_pod(&mut self, namespace: &str, pod: &str) {
+        let key = (namespace.to_string(), pod.to_string());
+        let Some(handle) = self.active_pods.remove(&key) else {
+            return;
+        };
+        tracing::debug!(namespace, pod, "Stopped streaming removed pod");
+        handle.abort();
+        self.active_containers.retain(|(ns, name, _), handle| {
+            let removed = ns == namespace && name == pod;
+            if removed {
+                handle.abort();
+            }
+            !removed
+        });
+        if self.notify_removed {
+            diagnostics::notice(format_args!("Pod {}/{} was removed", namespace, pod));
+        }
+    }
+
+    /// Starts a log stream for every started ephemeral container of the pod that is not
+    /// streamed yet.
+    async fn start_ephemeral_containers(&mut self, target: &PodTarget) -> anyhow::Result<()> {
+        let containers =
+            k8s::get_ephemeral_containers(&self.client, &target.name, &target.namespace).await?;
+        for container in containers {
+            let key = (
+                target.namespace.clone(),
+                target.name.clone(),
+                container.clone(),
+            );
+            if self.active_containers.contains_key(&key) {
+                continue;
+            }
+            let client = self.client.clone();
+            let PodTarget {
+                namespace,
+                name: pod,
+                resource,
+            } = target.clone();
+            let options = self.options.clone();
+            let output = self.lines_tx.clone();
+            let slots = self.slots.clone();
+            let handle = task::spawn(async move {
+                let Some(_slot) = stream_slot(slots, &options.shutdown).await else {
+                    return Ok(());
+                };
+                let result = k8s::follow_single_container_logs(
+                    &client,
+                    &pod,
+                    &container,
+                    &namespace,
+                    &options,
+                    resource.as_deref(),
+                    &output,
+                )
+                .await;
+                report_error(result, options.follow, &namespace, &pod)
+            });
+            self.active_containers.insert(key, handle);
+        }
+        Ok(())
+    }
+
+    fn spawn_stream(&self, target: PodTarget) -> JoinHandle<anyhow::Result<()>> {
+        let client = self.client.clone();
+        let PodTarget {
+            namespace,
+            name: pod,
+            resource,
+        } = target;
+        let options = self.options.clone();
+        let reconnect_on_rollout = self.reconnect_on_rollout;
+        let events = self.events_tx.clone();
+        let output = self.lines_tx.clone();
+        let slots = self.slots.clone();
+
+        task::spawn(async move {
+            let Some(_slot) = stream_slot(slots, &options.shutdown).await else {
+                return Ok(());
+            };
+            let result = async {
+                k8s::follow_pod_logs(
+                    &client,
+                    &pod,
+                    &namespace,
+                    &options,
+                    resource.as_deref(),
+                    &output,
+                )
+                .await?;
+                // The stream only ends while following once the container is gone, so a
+                // terminating pod has now been drained and its replacement can be looked up.
+                if options.follow
+                    && reconnect_on_rollout
+                    && !options.shutdown.is_cancelled()
+                    && k8s::is_pod_terminating(&client, &pod, &namespace).await?
+                {
+                    let _ = events.send(PodEvent::Terminated {
+                        namespace: namespace.clone(),
+                        pod: pod.clone(),
+                    });
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+            .await;
+            report_error(result, options.follow, &namespace, &pod)
+        })
+    }
+
+    /// Streams logs until all streams end, or until shut down when following.
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        let events_rx = self.events_rx.take().unwrap();
+        let writer = self.lines_rx.take().map(|lines_rx| {
+            task::spawn(Writer::new(std::io::stdout(), self.render_options.clone()).run(lines_rx))
+        });
+
+        self.check_permissions().await?;
+        if self.watch_pod_statuses {
+            for namespace in self.scopes() {
+                self.spawn_status_watch(namespace);
+            }
+        }
+        if !self.wait_for_resources || self.await_resources().await? {
+            self.discover_and_start_new_pods().await?;
+            if self.options.follow {
+                self.follow(events_rx).await?;
+            }
+        }
+
+        // Streams end by themselves unless following, or once shut down.
+        for (_, handle) in self.active_pods.drain() {
+            handle.await??;
+        }
+        for (_, handle) in self.active_containers.drain() {
+            handle.await??;
+        }
+        // Dropping the manager closes the output so the writer can flush and finish.
+        drop(self);
+        if let Some(writer) = writer {
+            writer.await??;
+        }
+        Ok(())
+    }
+
+    /// Streams the lines of the pods as events instead of writing them, until all streams
+    /// end, or until shut down when following. An error that ended the streams is yielded
+    /// after the lines read before it. Only lines passing the hooks and filters of the render
+    /// options are yielded.
+    pub fn events(mut self) -> impl Stream<Item = anyhow::Result<LogEvent>> + 'a {
+        let mut filters = self.render_options.clone();
+        filters.fold_case();
+        let lines = match self.lines_rx.take() {
+            Some(lines) => lines,
+            None => {
+                let (lines_tx, lines_rx) = mpsc::channel(DEFAULT_BUFFER_LINES);
+                self.lines_tx = lines_tx;
+                lines_rx
+            }
+        };
+        let run: Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'a>> = Box::pin(self.run());
+        stream::unfold(
+            (Some(run), lines, None),
+            |(mut run, mut lines, mut error)| async move {
+                loop {
+                    let Some(running) = &mut run else {
+                        // The manager is done, so the lines end once the buffered ones are read.
+                        return match lines.recv().await {
+                            Some(line) => Some((Ok(LogEvent::from(line)), (run, lines, error))),
+                            None => error.take().map(|e| (Err(e), (run, lines, None))),
+                        };
+                    };
+                    tokio::select! {
+                        Some(line) = lines.recv() => {
+                            return Some((Ok(LogEvent::from(line)), (run, lines, error)));
+                        }
+                        result = running => {
+                            run = None;
+                            error = result.err();
+                        }
+                    }
+                }
+            },
+        )
+        .filter_map(move |event| {
+            let event = match event {
+                Ok(event) => filters
+                    .hooks
+                    .apply(event)
+                    .filter(|event| filters.matches_filters(&event.raw))
+                    .map(Ok),
+                Err(e) => Some(Err(e)),
+            };
+            future::ready(event)
+        })
+    }
+
+    /// Picks up new pods until the streams are shut down, by watching the targets, or by
+    /// repeating discovery every `refresh_interval` for targets that cannot be watched.
+    async fn follow(
+        &mut self,
+        mut events_rx: mpsc::UnboundedReceiver<PodEvent>,
+    ) -> anyhow::Result<()> {
+        let shutdown = self.options.shutdown.clone();
+        let polling = match self.watch {
+            true => {
+                let (targets, unwatched) = self.watch_targets().await?;
+                for target in targets {
+                    self.spawn_watch(target);
+                }
+                unwatched
+            }
+            false => true,
+        };
+
+        let mut interval = tokio::time::interval(self.refresh_interval);
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = interval.tick(), if polling => {}
+                Some(event) = events_rx.recv() => match event {
+                    PodEvent::Terminated { namespace, pod } => {
+                        diagnostics::notice(format_args!(
+                            "Pod {}/{} terminated, looking for its replacement",
+                            namespace, pod
+                        ));
+                        self.remove_pod(&namespace, &pod);
+                        interval.reset();
+                        // Watched replacements are started as soon as they start.
+                        if !polling {
+                            continue;
+                        }
+                    }
+                    PodEvent::Started(target) => {
+                        if let Err(e) = self.start_pod(target).await {
+                            diagnostics::error(format_args!("Could not start stream: {}", e));
+                        }
+                        continue;
+                    }
+                    PodEvent::Deleted { namespace, pod } => {
+                        self.remove_pod(&namespace, &pod);
+                        continue;
+                    }
+                }
+            }
+            if let Err(e) = self.discover_and_start_new_pods().await {
+                diagnostics::error(format_args!("Could not discover pods: {}", e));
+            }
+        }
+    }
+}
+
+/// Waits for a free slot to stream a pod in, or returns `None` once the streams are shut
+/// down first.
+async fn stream_slot(
+    slots: Arc<Semaphore>,
+    shutdown: &CancellationToken,
+) -> Option<OwnedSemaphorePermit> {
+    tokio::select! {
+        // The semaphore is never closed.
+        slot = slots.acquire_owned() => slot.ok(),
+        _ = shutdown.cancelled() => None,
+    }
+}
+
+/// Reports the error a stream ended with while following, since nothing awaits the
+/// stream then. Otherwise the error is returned as a `KlogError`, ending klog with it.
+fn report_error(
+    result: anyhow::Result<()>,
+    follow: bool,
+    namespace: &str,
+    pod: &str,
+) -> anyhow::Result<()> {
+    let result = result.map_err(|e| {
+        let error = match k8s::is_not_found(&e) {
+            true => KlogError::ResourceNotFound {
+                kind: Pod::KIND.to_string(),
+                name: pod.to_string(),
+                namespace: Some(namespace.to_string()),
+            },
+            false => KlogError::Stream {
+                namespace: namespace.to_string(),
+                pod: pod.to_string(),
+                source: e,
+            },
+        };
+        anyhow::Error::new(error)
+    });
+    match result {
+        Err(e) if follow => {
+            diagnostics::error(format_args!("{:#}", e));
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_terminating_pod_triggers_discovery() {
+        let pod_lists = AtomicUsize::new(0);
+        let (client, requests) = test_util::fake_client(move |req| match req.uri().path() {
+            "/apis/apps/v1/namespaces/default/deployments/web" => test_util::json_response(json!({
+                "apiVersion": "apps/v1",
+                "kind": "Deployment",
+                "metadata": { "name": "web" },
+                "spec": {
+                    "selector": { "matchLabels": { "app": "web" } },
+                    "template": {}
+                }
+            })),
+            "/api/v1/namespaces/default/pods" => {
+                let name = match pod_lists.fetch_add(1, Ordering::SeqCst) {
+                    0 => "web-old",
+                    _ => "web-new",
+                };
+                test_util::json_response(test_util::list(
+                    "PodList",
+                    vec![test_util::pod(name, &["app"])],
+                ))
+            }
+            "/api/v1/namespaces/default/pods/web-old" => {
+                let mut pod = test_util::pod("web-old", &["app"]);
+                pod["metadata"]["deletionTimestamp"] = json!("2024-01-01T00:00:00Z");
+                test_util::json_response(pod)
+            }
+            "/api/v1/namespaces/default/pods/web-old/log" => {
+                test_util::text_response("shutting down\n")
+            }
+            "/api/v1/namespaces/default/pods/web-new" => {
+                test_util::json_response(test_util::pod("web-new", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-new/log" => {
+                test_util::open_response("starting up\n")
+            }
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![ResourceType::Deployment("web")],
+            vec![],
+            k8s::StreamOptions {
+                follow: true,
+                ..k8s::StreamOptions::default()
+            },
+        )
+        .refresh_interval(Duration::from_secs(3600))
+        .reconnect_on_rollout(true);
+
+        tokio::select! {
+            result = manager.run() => panic!("manager stopped: {:?}", result),
+            _ = tokio::time::timeout(
+                Duration::from_secs(5),
+                requests.wait_for("/api/v1/namespaces/default/pods/web-new/log"),
+            ) => {}
+        }
+
+        assert_eq!(requests.count("/api/v1/namespaces/default/pods?"), 2);
+        assert_eq!(
+            requests.count("/api/v1/namespaces/default/pods/web-new/log"),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deleted_pod_is_removed() {
+        let pod_lists = AtomicUsize::new(0);
+        let (client, _) = test_util::fake_client(move |req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods" => {
+                let pods = match pod_lists.fetch_add(1, Ordering::SeqCst) {
+                    0 => vec![
+                        test_util::pod("web-1", &["app"]),
+                        test_util::pod("web-2", &["app"]),
+                    ],
+                    _ => vec![test_util::pod("web-1", &["app"])],
+                };
+                test_util::json_response(test_util::list("PodList", pods))
+            }
+            "/api/v1/namespaces/default/pods/web-1" => {
+                test_util::json_response(test_util::pod("web-1", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-2" => {
+                test_util::json_response(test_util::pod("web-2", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-1/log"
+            | "/api/v1/namespaces/default/pods/web-2/log" => test_util::open_response("hello\n"),
+            _ => test_util::not_found(),
+        });
+        let mut manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec!["web-.*".to_string()],
+            k8s::StreamOptions {
+                follow: true,
+                ..k8s::StreamOptions::default()
+            },
+        );
+
+        manager.discover_and_start_new_pods().await.unwrap();
+        assert_eq!(manager.active_pods.len(), 2);
+        let web_2 =
+            manager.active_pods[&("default".to_string(), "web-2".to_string())].abort_handle();
+
+        manager.discover_and_start_new_pods().await.unwrap();
+        let active: Vec<_> = manager.active_pods.keys().collect();
+        assert_eq!(active, vec![&("default".to_string(), "web-1".to_string())]);
+        let aborted = tokio::time::timeout(Duration::from_secs(5), async {
+            while !web_2.is_finished() {
+                tokio::task::yield_now().await;
+            }
+        });
+        assert!(aborted.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_queues_pods() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![
+                    test_util::pod("web-1", &["app"]),
+                    test_util::pod("web-2", &["app"]),
+                ],
+            )),
+            "/api/v1/namespaces/default/pods/web-1" => {
+                test_util::json_response(test_util::pod("web-1", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-2" => {
+                test_util::json_response(test_util::pod("web-2", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-1/log"
+            | "/api/v1/namespaces/default/pods/web-2/log" => test_util::open_response("hello\n"),
+            _ => test_util::not_found(),
+        });
+        let mut manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec!["web-.*".to_string()],
+            k8s::StreamOptions {
+                follow: true,
+                ..k8s::StreamOptions::default()
+            },
+        )
+        .max_concurrent(Some(1));
+        let logs = |requests: &test_util::Requests| {
+            requests
+                .all()
+                .into_iter()
+                .filter(|request| request.contains("/log"))
+                .collect::<Vec<_>>()
+        };
+
+        manager.discover_and_start_new_pods().await.unwrap();
+        assert_eq!(manager.active_pods.len(), 2);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while logs(&requests).is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let first = logs(&requests);
+        assert_eq!(first.len(), 1);
+
+        // The queued pod attaches once the streamed one is gone.
+        let streamed = match first[0].contains("web-1") {
+            true => "web-1",
+            false => "web-2",
+        };
+        manager.remove_pod("default", streamed);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while logs(&requests).len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watched_pod_starts_streaming() {
+        let running = |name: &str| {
+            let mut pod = test_util::pod(name, &["app"]);
+            pod["status"] = json!({ "phase": "Running" });
+            pod
+        };
+        let (client, requests) = test_util::fake_client(move |req| {
+            let watching = req.uri().query().unwrap_or_default().contains("watch=true");
+            match req.uri().path() {
+                "/apis/apps/v1/namespaces/default/deployments/web" => {
+                    test_util::json_response(json!({
+                        "apiVersion": "apps/v1",
+                        "kind": "Deployment",
+                        "metadata": { "name": "web" },
+                        "spec": {
+                            "selector": { "matchLabels": { "app": "web" } },
+                            "template": {}
+                        }
+                    }))
+                }
+                "/api/v1/namespaces/default/pods" if watching => {
+                    let event = json!({ "type": "ADDED", "object": running("web-2") });
+                    test_util::open_response(&format!("{}\n", event))
+                }
+                "/api/v1/namespaces/default/pods" => {
+                    test_util::json_response(test_util::list("PodList", vec![running("web-1")]))
+                }
+                "/api/v1/namespaces/default/pods/web-1" => {
+                    test_util::json_response(running("web-1"))
+                }
+                "/api/v1/namespaces/default/pods/web-2" => {
+                    test_util::json_response(running("web-2"))
+                }
+                "/api/v1/namespaces/default/pods/web-1/log"
+                | "/api/v1/namespaces/default/pods/web-2/log" => {
+                    test_util::open_response("hello\n")
+                }
+                _ => test_util::not_found(),
+            }
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![ResourceType::Deployment("web")],
+            vec![],
+            k8s::StreamOptions {
+                follow: true,
+                ..k8s::StreamOptions::default()
+            },
+        )
+        .refresh_interval(Duration::from_secs(3600))
+        .watch(true);
+
+        tokio::select! {
+            result = manager.run() => panic!("manager stopped: {:?}", result),
+            _ = tokio::time::timeout(
+                Duration::from_secs(5),
+                requests.wait_for("/api/v1/namespaces/default/pods/web-2/log"),
+            ) => {}
+        }
+
+        assert!(requests
+            .all()
+            .iter()
+            .any(|request| request.contains("labelSelector=app%3Dweb")
+                && request.contains("watch=true")));
+        assert_eq!(
+            requests.count("/api/v1/namespaces/default/pods/web-2/log"),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_streams() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/web-1" => {
+                test_util::json_response(test_util::pod("web-1", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-1/log" => test_util::open_response("one\ntwo\n"),
+            _ => test_util::not_found(),
+        });
+        let (lines_tx, mut lines_rx) = mpsc::channel(100);
+        let shutdown = CancellationToken::new();
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec!["web-1".to_string()],
+            k8s::StreamOptions {
+                follow: true,
+                ..k8s::StreamOptions::default()
+            },
+        )
+        .refresh_interval(Duration::from_secs(3600))
+        .output(lines_tx)
+        .shutdown(shutdown.clone());
+        let run = task::spawn(manager.run());
+
+        for expected in ["one", "two"] {
+            let line = tokio::time::timeout(Duration::from_secs(5), lines_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(line.line, expected);
+        }
+        shutdown.cancel();
+
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("manager did not stop")
+            .unwrap()
+            .unwrap();
+        // Every stream has ended, so the output is closed.
+        assert!(lines_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default" => test_util::json_response(json!({
+                "apiVersion": "v1",
+                "kind": "Namespace",
+                "metadata": { "name": "default" }
+            })),
+            "/api/v1/namespaces/default/pods/web-1" => {
+                test_util::json_response(test_util::pod("web-1", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-1/log" => {
+                test_util::text_response("{\"level\":\"info\",\"msg\":\"started\"}\nplain\n")
+            }
+            _ => test_util::not_found(),
+        });
+        let manager = |resources, pods| {
+            PodManager::new(
+                client.clone(),
+                vec!["default".to_string()],
+                resources,
+                pods,
+                k8s::StreamOptions::default(),
+            )
+        };
+
+        let events: Vec<_> = manager(vec![], vec!["web-1".to_string()])
+            .events()
+            .collect()
+            .await;
+
+        assert_eq!(events.len(), 2);
+        let event = events[0].as_ref().unwrap();
+        assert_eq!(event.pod, "web-1");
+        assert_eq!(event.raw, r#"{"level":"info","msg":"started"}"#);
+        assert_eq!(event.parsed.as_ref().unwrap()["msg"], "started");
+        let event = events[1].as_ref().unwrap();
+        assert_eq!(event.raw, "plain");
+        assert!(event.parsed.is_none());
+
+        let events: Vec<_> = manager(vec![ResourceType::Deployment("web")], vec![])
+            .events()
+            .collect()
+            .await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].as_ref().unwrap_err().to_string(),
+            "Deployment web not found in namespace default"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_resource_or_namespace() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/shop" => test_util::json_response(json!({
+                "apiVersion": "v1",
+                "kind": "Namespace",
+                "metadata": { "name": "shop" }
+            })),
+            _ => test_util::not_found(),
+        });
+        let discover = |namespace: &str| {
+            PodManager::new(
+                client.clone(),
+                vec![namespace.to_string()],
+                vec![ResourceType::Deployment("web")],
+                vec![],
+                k8s::StreamOptions::default(),
+            )
+        };
+
+        let error = discover("shop").discover_pods().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Deployment web not found in namespace shop"
+        );
+        let error = discover("shoop").discover_pods().await.unwrap_err();
+        assert_eq!(error.to_string(), "Namespace shoop not found");
+    }
+
+    #[tokio::test]
+    async fn test_missing_permissions() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/authorization.k8s.io/v1/selfsubjectaccessreviews" => {
+                test_util::json_response(json!({
+                    "apiVersion": "authorization.k8s.io/v1",
+                    "kind": "SelfSubjectAccessReview",
+                    "spec": {},
+                    "status": { "allowed": false }
+                }))
+            }
+            _ => test_util::not_found(),
+        });
+        let manager = PodManager::new(
+            client,
+            vec!["shop".to_string()],
+            vec![ResourceType::Deployment("web")],
+            vec![],
+            k8s::StreamOptions::default(),
+        );
+
+        let error = manager.run().await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Permission denied: missing list pods in namespace shop, get pods/log in namespace shop"
+        );
+        assert_eq!(crate::error::exit_code(&error), 6);
+        assert_eq!(requests.count("/apis/apps"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_missing_permissions_for_pod_regex() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/authorization.k8s.io/v1/selfsubjectaccessreviews" => {
+                test_util::json_response(json!({
+                    "apiVersion": "authorization.k8s.io/v1",
+                    "kind": "SelfSubjectAccessReview",
+                    "spec": {},
+                    "status": { "allowed": false }
+                }))
+            }
+            _ => test_util::not_found(),
+        });
+        let run = |pod: &str| {
+            PodManager::new(
+                client.clone(),
+                vec!["shop".to_string()],
+                vec![],
+                vec![pod.to_string()],
+                k8s::StreamOptions::default(),
+            )
+            .run()
+        };
+
+        // Pods matching a regex are found by listing, so they need `list pods` too.
+        let error = run("api-.*").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Permission denied: missing list pods in namespace shop, get pods/log in namespace shop"
+        );
+        assert_eq!(requests.count("/api/v1/namespaces/shop/pods"), 0);
+
+        let error = run("api-1").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Permission denied: missing get pods/log in namespace shop"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_watch_permission() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/authorization.k8s.io/v1/selfsubjectaccessreviews" => {
+                test_util::json_response(json!({
+                    "apiVersion": "authorization.k8s.io/v1",
+                    "kind": "SelfSubjectAccessReview",
+                    "spec": {},
+                    "status": { "allowed": false }
+                }))
+            }
+            _ => test_util::not_found(),
+        });
+        let manager = PodManager::new(
+            client,
+            vec!["shop".to_string()],
+            vec![],
+            vec!["api-1".to_string()],
+            k8s::StreamOptions::default(),
+        )
+        .watch_pod_statuses(true);
+
+        let error = manager.run().await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Permission denied: missing watch pods in namespace shop, get pods/log in namespace shop"
+        );
+        assert_eq!(requests.count("/api/v1/namespaces/shop/pods"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![
+                    test_util::pod("web-1", &["app", "proxy"]),
+                    test_util::pod("web-canary", &["app"]),
+                ],
+            )),
+            "/api/v1/namespaces/default/pods/web-1" => {
+                test_util::json_response(test_util::pod("web-1", &["app", "proxy"]))
+            }
+            _ => test_util::not_found(),
+        });
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec![],
+            k8s::StreamOptions::default(),
+        )
+        .selector(Some("app=web".to_string()))
+        .exclude_pods(Some(Regex::new("canary").unwrap()));
+
+        let streams = manager.dry_run().await.unwrap();
+
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].0.name, "web-1");
+        assert_eq!(streams[0].1, vec!["app", "proxy"]);
+        assert_eq!(
+            requests.count("/api/v1/namespaces/default/pods/web-1/log"),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_resources() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/apps/v1/namespaces/default/deployments" => {
+                test_util::json_response(test_util::list(
+                    "DeploymentList",
+                    vec![json!({
+                        "apiVersion": "apps/v1",
+                        "kind": "Deployment",
+                        "metadata": { "name": "web", "namespace": "default" },
+                        "spec": {
+                            "selector": { "matchLabels": { "app": "web" } },
+                            "template": {}
+                        }
+                    })],
+                ))
+            }
+            _ => test_util::not_found(),
+        });
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![ResourceType::Deployment("web")],
+            vec![],
+            k8s::StreamOptions::default(),
+        )
+        .wait_for_resources(true);
+
+        let created = tokio::time::timeout(Duration::from_secs(5), manager.await_resources())
+            .await
+            .expect("deployment was not picked up")
+            .unwrap();
+
+        assert!(created);
+        assert_eq!(
+            requests.count("/apis/apps/v1/namespaces/default/deployments/web"),
+            1
+        );
+        assert!(requests.count("/apis/apps/v1/namespaces/default/deployments?") > 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_resources() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/apps/v1/namespaces/default/deployments" => {
+                test_util::json_response(test_util::list(
+                    "DeploymentList",
+                    vec![json!({
+                        "metadata": { "name": "web" },
+                        "spec": {
+                            "selector": { "matchLabels": { "app": "web" } },
+                            "template": {}
+                        }
+                    })],
+                ))
+            }
+            "/apis/batch/v1/namespaces/default/cronjobs" => {
+                test_util::json_response(test_util::list(
+                    "CronJobList",
+                    vec![json!({
+                        "metadata": { "name": "nightly" },
+                        "spec": { "schedule": "@daily", "jobTemplate": {} }
+                    })],
+                ))
+            }
+            "/apis/apps/v1/namespaces/default/statefulsets"
+            | "/apis/apps/v1/namespaces/default/daemonsets"
+            | "/apis/batch/v1/namespaces/default/jobs" => {
+                test_util::json_response(test_util::list("List", vec![]))
+            }
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![
+                    test_util::pod("web-1", &["app"]),
+                    test_util::pod("web-2", &["app"]),
+                ],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec![],
+            k8s::StreamOptions::default(),
+        );
+        let resources = manager.list_resources().await.unwrap();
+
+        assert_eq!(resources["deployments"]["web"], json!(["web-1", "web-2"]));
+        assert_eq!(resources["cronjobs"]["nightly"], json!([]));
+        assert_eq!(resources["statefulsets"], json!({}));
+        assert!(requests.all().iter().all(|r| !r.contains("/log")));
+    }
+
+    #[tokio::test]
+    async fn test_discover_pods_in_all_namespaces() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/apis/apps/v1/deployments" => test_util::json_response(test_util::list(
+                "DeploymentList",
+                vec![
+                    json!({ "metadata": { "name": "web", "namespace": "staging" } }),
+                    json!({ "metadata": { "name": "web", "namespace": "prod" } }),
+                ],
+            )),
+            "/apis/apps/v1/namespaces/staging/deployments/web"
+            | "/apis/apps/v1/namespaces/prod/deployments/web" => test_util::json_response(json!({
+                "metadata": { "name": "web" },
+                "spec": {
+                    "selector": { "matchLabels": { "app": "web" } },
+                    "template": {}
+                }
+            })),
+            "/api/v1/namespaces/staging/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![test_util::pod("web-staging", &["app"])],
+            )),
+            "/api/v1/namespaces/prod/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![test_util::pod("web-prod", &["app"])],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec![],
+            vec![ResourceType::Deployment("web")],
+            vec![],
+            k8s::StreamOptions::default(),
+        )
+        .all_namespaces(true);
+        let pods = manager.discover_pods().await.unwrap();
+
+        let pods: Vec<_> = pods
+            .iter()
+            .map(|pod| format!("{}/{}", pod.namespace, pod.name))
+            .collect();
+        assert_eq!(pods, vec!["staging/web-staging", "prod/web-prod"]);
+    }
+
+    #[tokio::test]
+    async fn test_discover_pods_in_multiple_namespaces() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/staging/pods/debug" | "/api/v1/namespaces/prod/pods/debug" => {
+                test_util::json_response(test_util::pod("debug", &["app"]))
+            }
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec!["staging".to_string(), "dev".to_string(), "prod".to_string()],
+            vec![],
+            vec!["debug".to_string()],
+            k8s::StreamOptions::default(),
+        );
+        let pods = manager.discover_pods().await.unwrap();
+
+        let namespaces: Vec<_> = pods.iter().map(|pod| pod.namespace.as_str()).collect();
+        assert_eq!(namespaces, vec!["staging", "prod"]);
+    }
+
+    #[tokio::test]
+    async fn test_discover_pods_by_selector() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![
+                    test_util::pod("api-1", &["app"]),
+                    test_util::pod("api-2", &["app"]),
+                ],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec![],
+            k8s::StreamOptions::default(),
+        )
+        .selector(Some("app=api,tier!=db".to_string()));
+        let pods = manager.discover_pods().await.unwrap();
+
+        let names: Vec<_> = pods.iter().map(|pod| pod.name.as_str()).collect();
+        assert_eq!(names, vec!["api-1", "api-2"]);
+        assert_eq!(
+            pods[0].resource.as_deref(),
+            Some("selector/app=api,tier!=db")
+        );
+        assert!(requests.all()[0].contains("labelSelector=app%3Dapi%2Ctier%21%3Ddb"));
+    }
+
+    #[tokio::test]
+    async fn test_discover_pods_by_regex() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![
+                    test_util::pod("api-1", &["app"]),
+                    test_util::pod("worker-1", &["app"]),
+                    test_util::pod("api-2", &["app"]),
+                ],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec!["api-.*".to_string()],
+            k8s::StreamOptions::default(),
+        );
+        let pods = manager.discover_pods().await.unwrap();
+
+        let names: Vec<_> = pods.iter().map(|pod| pod.name.as_str()).collect();
+        assert_eq!(names, vec!["api-1", "api-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_discover_pods_by_node() {
+        let (client, requests) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![test_util::pod("kube-proxy-x2", &["app"])],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let manager = PodManager::new(
+            client,
+            vec![],
+            vec![],
+            vec![],
+            k8s::StreamOptions::default(),
+        )
+        .all_namespaces(true)
+        .node(Some("worker-1".to_string()))
+        .selector(Some("k8s-app=kube-proxy".to_string()));
+        let pods = manager.discover_pods().await.unwrap();
+
+        assert_eq!(pods[0].name, "kube-proxy-x2");
+        let request = &requests.all()[0];
+        assert!(request.contains("fieldSelector=spec.nodeName%3Dworker-1"));
+        assert!(request.contains("labelSelector=k8s-app%3Dkube-proxy"));
+    }
+
+    #[tokio::test]
+    async fn test_exclude_pods() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods" => test_util::json_response(test_util::list(
+                "PodList",
+                vec![
+                    test_util::pod("web-1", &["app"]),
+                    test_util::pod("web-canary-1", &["app"]),
+                    test_util::pod("web-2", &["app"]),
+                ],
+            )),
+            _ => test_util::not_found(),
+        });
+
+        let mut manager = PodManager::new(
+            client,
+            vec!["default".to_string()],
+            vec![],
+            vec!["web-.*".to_string()],
+            k8s::StreamOptions::default(),
+        )
+        .exclude_pods(Some(Regex::new("canary").unwrap()));
+        manager.discover_and_start_new_pods().await.unwrap();
+
+        let mut names: Vec<_> = manager
+            .active_pods
+            .keys()
+            .map(|(_, name)| name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["web-1", "web-2"]);
+    }
+}
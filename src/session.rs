@@ -0,0 +1,540 @@
+use std::future::Future;
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_util::future;
+use k8s_openapi::serde_json::{Map, Value};
+use kube::Client;
+use regex::Regex;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::hooks::Hook;
+use crate::k8s::{self, ClientOptions, StreamOptions};
+use crate::metrics;
+use crate::output::{self, LogLine, RenderOptions, Writer, DEFAULT_BUFFER_LINES};
+use crate::pod_manager::{PodManager, PodTarget};
+use crate::sinks::SinkOptions;
+use crate::ResourceType;
+
+/// What to log and how: the clusters to connect to, the pods to stream, the options for
+/// streaming and rendering them and where else to send them. The command line tool builds
+/// one from its arguments, which hold the options of a config profile too.
+#[derive(Clone)]
+pub struct Klog<'a> {
+    client_options: ClientOptions,
+    contexts: Vec<String>,
+    namespaces: Vec<String>,
+    all_namespaces: bool,
+    resources: Vec<ResourceType<'a>>,
+    pods: Vec<String>,
+    selector: Option<String>,
+    node: Option<String>,
+    exclude_pods: Option<Regex>,
+    stream_options: StreamOptions,
+    render_options: RenderOptions,
+    refresh_interval: Duration,
+    watch: bool,
+    notify_removed: bool,
+    max_concurrent: Option<usize>,
+    buffer_lines: usize,
+    reconnect_on_rollout: bool,
+    wait_for_resources: bool,
+    watch_pod_statuses: bool,
+    latest_only: bool,
+    sinks: SinkOptions,
+    metrics_addr: Option<SocketAddr>,
+    status_interval: Option<Duration>,
+}
+
+impl<'a> Klog<'a> {
+    pub fn builder() -> KlogBuilder<'a> {
+        KlogBuilder(Klog {
+            client_options: ClientOptions::default(),
+            contexts: vec![],
+            namespaces: vec![],
+            all_namespaces: false,
+            resources: vec![],
+            pods: vec![],
+            selector: None,
+            node: None,
+            exclude_pods: None,
+            stream_options: StreamOptions::default(),
+            render_options: RenderOptions::default(),
+            refresh_interval: Duration::from_secs(5),
+            watch: true,
+            notify_removed: false,
+            max_concurrent: None,
+            buffer_lines: DEFAULT_BUFFER_LINES,
+            reconnect_on_rollout: false,
+            wait_for_resources: false,
+            watch_pod_statuses: false,
+            latest_only: false,
+            sinks: SinkOptions::default(),
+            metrics_addr: None,
+            status_interval: None,
+        })
+    }
+
+    pub fn render_options(&self) -> &RenderOptions {
+        &self.render_options
+    }
+
+    /// Ends the streams once cancelled, writing out what they read so far.
+    pub fn shutdown(&self) -> CancellationToken {
+        self.render_options.shutdown.clone()
+    }
+
+    /// The contexts to stream from, or just `None` for the one of the client options.
+    fn contexts(&self) -> Vec<Option<String>> {
+        match self.contexts.is_empty() {
+            true => vec![None],
+            false => self.contexts.iter().cloned().map(Some).collect(),
+        }
+    }
+
+    /// Creates a manager for the pods on the cluster of `client`. `context` names the
+    /// cluster in the lines when streaming several.
+    pub fn pod_manager(&self, client: Client, context: Option<String>) -> PodManager<'a> {
+        // Like kubectl, fall back to the namespace of the kubeconfig context, or of klog's own
+        // pod when running in a cluster.
+        let namespaces = if self.namespaces.is_empty() && !self.all_namespaces {
+            vec![client.default_namespace().to_string()]
+        } else {
+            self.namespaces.clone()
+        };
+        PodManager::new(
+            client,
+            namespaces,
+            self.resources.clone(),
+            self.pods.clone(),
+            StreamOptions {
+                context,
+                ..self.stream_options.clone()
+            },
+        )
+        .all_namespaces(self.all_namespaces)
+        .selector(self.selector.clone())
+        .node(self.node.clone())
+        .exclude_pods(self.exclude_pods.clone())
+        .refresh_interval(self.refresh_interval)
+        .watch(self.watch)
+        .notify_removed(self.notify_removed)
+        .max_concurrent(self.max_concurrent)
+        .buffer_lines(self.buffer_lines)
+        .reconnect_on_rollout(self.reconnect_on_rollout)
+        .wait_for_resources(self.wait_for_resources)
+        .watch_pod_statuses(self.watch_pod_statuses)
+        .latest_only(self.latest_only)
+        .render_options(self.render_options.clone())
+    }
+
+    /// Connects to the cluster of the first context and creates a manager for its pods.
+    pub async fn connect(&self) -> anyhow::Result<PodManager<'a>> {
+        let client = k8s::client(&ClientOptions {
+            context: self
+                .contexts
+                .first()
+                .cloned()
+                .or_else(|| self.client_options.context.clone()),
+            ..self.client_options.clone()
+        })
+        .await?;
+        Ok(self.pod_manager(client, None))
+    }
+
+    /// Connects to each context and creates a manager for its pods, naming the context in
+    /// their lines.
+    pub async fn pod_managers(&self) -> anyhow::Result<Vec<PodManager<'a>>> {
+        let mut managers = vec![];
+        for context in self.contexts() {
+            let client = match &context {
+                Some(context) => {
+                    k8s::client(&ClientOptions {
+                        context: Some(context.clone()),
+                        ..self.client_options.clone()
+                    })
+                    .await?
+                }
+                None => k8s::client(&self.client_options).await?,
+            };
+            managers.push(self.pod_manager(client, context));
+        }
+        Ok(managers)
+    }
+
+    /// Lists the workloads with their pods, keyed by context when there are several.
+    pub async fn list_resources(&self) -> anyhow::Result<Value> {
+        if self.contexts.is_empty() {
+            return self.connect().await?.list_resources().await;
+        }
+        let mut resources = Map::new();
+        for (context, manager) in self.contexts.iter().zip(self.pod_managers().await?) {
+            resources.insert(context.clone(), manager.list_resources().await?);
+        }
+        Ok(Value::Object(resources))
+    }
+
+    /// Lists the pods that would be streamed with their containers for each context.
+    pub async fn dry_run(
+        &self,
+    ) -> anyhow::Result<Vec<(Option<String>, Vec<(PodTarget, Vec<String>)>)>> {
+        let mut streams = vec![];
+        for (context, manager) in self.contexts().into_iter().zip(self.pod_managers().await?) {
+            streams.push((context, manager.dry_run().await?));
+        }
+        Ok(streams)
+    }
+
+    /// Streams the pods of all contexts and writes their lines to stdout and the sinks,
+    /// until all streams end or are shut down.
+    pub async fn run(&self) -> anyhow::Result<()> {
+        self.output(|render_options| async move {
+            // All clusters share one writer so their lines never interleave mid-line.
+            let (lines_tx, lines_rx) = mpsc::channel(self.buffer_lines.max(1));
+            let writer = tokio::spawn(Writer::new(std::io::stdout(), render_options).run(lines_rx));
+            self.stream_into(lines_tx).await?;
+            writer.await??;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Streams the pods of all contexts into `lines` instead of writing them to stdout,
+    /// e.g. for a TUI. The lines still go to the files and sinks, until `lines` is closed.
+    pub async fn run_into(&self, lines: mpsc::Sender<LogLine>) -> anyhow::Result<()> {
+        self.output(|mut render_options| async move {
+            render_options.no_stdout = true;
+            let (writer_tx, writer_rx) = mpsc::channel(self.buffer_lines.max(1));
+            let writer = tokio::spawn(Writer::new(std::io::sink(), render_options).run(writer_rx));
+            let (streamed_tx, mut streamed_rx) = mpsc::channel(self.buffer_lines.max(1));
+            let tee = async move {
+                while let Some(line) = streamed_rx.recv().await {
+                    if writer_tx.send(line.clone()).await.is_err()
+                        || lines.send(line).await.is_err()
+                    {
+                        // Dropping the receiver ends the streams.
+                        break;
+                    }
+                }
+            };
+            let (streamed, ()) = tokio::join!(self.stream_into(streamed_tx), tee);
+            streamed?;
+            writer.await??;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Writes lines read elsewhere, e.g. from an archive, to stdout and the sinks.
+    pub async fn write_lines(&self, lines: Vec<LogLine>) -> anyhow::Result<()> {
+        self.output(|render_options| async move {
+            let (lines_tx, lines_rx) = mpsc::channel(self.buffer_lines.max(1));
+            let writer = tokio::spawn(Writer::new(std::io::stdout(), render_options).run(lines_rx));
+            for line in lines {
+                lines_tx.send(line).await?;
+            }
+            drop(lines_tx);
+            writer.await??;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Writes a capture of `-o ndjson` output to stdout and the sinks, see `output::replay`.
+    pub async fn replay<R: BufRead + Send + 'static>(&self, reader: R) -> anyhow::Result<()> {
+        self.output(|render_options| async move {
+            output::replay(reader, Writer::new(std::io::stdout(), render_options)).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn stream_into(&self, lines: mpsc::Sender<LogLine>) -> anyhow::Result<()> {
+        let shutdown = self.shutdown();
+        let managers = self.pod_managers().await?;
+        future::try_join_all(managers.into_iter().map(|manager| {
+            manager
+                .output(lines.clone())
+                .shutdown(shutdown.clone())
+                .run()
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Serves the metrics and starts reporting the status of the streams, if requested.
+    async fn start_metrics(&self) -> anyhow::Result<()> {
+        if let Some(addr) = self.metrics_addr {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            tokio::spawn(metrics::serve(listener));
+        }
+        if let Some(interval) = self.status_interval {
+            tokio::spawn(metrics::report_status(interval));
+        }
+        Ok(())
+    }
+
+    /// Starts the sinks, then has `write` write lines with render options forwarding them
+    /// to the sinks, and waits for the sinks to send what is left.
+    async fn output<F, Fut>(&self, write: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(RenderOptions) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        self.start_metrics().await?;
+        let mut render_options = self.render_options.clone();
+        let tasks = self.sinks.start(&mut render_options)?;
+        let result = write(render_options).await;
+        // All writers dropped their senders by now, so the sinks can send what is left.
+        for task in tasks {
+            task.await??;
+        }
+        result
+    }
+}
+
+/// Builds a `Klog`, e.g.
+/// `Klog::builder().namespace("shop").deployment("web").follow(true).build()`.
+pub struct KlogBuilder<'a>(Klog<'a>);
+
+impl<'a> KlogBuilder<'a> {
+    /// Replaces the settings for connecting to the cluster.
+    pub fn client_options(mut self, client_options: ClientOptions) -> Self {
+        self.0.client_options = client_options;
+        self
+    }
+
+    /// Streams from the cluster of this kubeconfig context too, instead of from the current
+    /// one. The lines of each context are named after it.
+    pub fn context(mut self, context: impl Into<String>) -> Self {
+        self.0.contexts.push(context.into());
+        self
+    }
+
+    /// Looks for the targets in this namespace too. Without any, the namespace of the
+    /// kubeconfig context is used.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.0.namespaces.push(namespace.into());
+        self
+    }
+
+    /// Looks up pods and resources by name in all namespaces instead.
+    pub fn all_namespaces(mut self, all_namespaces: bool) -> Self {
+        self.0.all_namespaces = all_namespaces;
+        self
+    }
+
+    pub fn resource(mut self, resource: ResourceType<'a>) -> Self {
+        self.0.resources.push(resource);
+        self
+    }
+
+    pub fn deployment(self, name: &'a str) -> Self {
+        self.resource(ResourceType::Deployment(name))
+    }
+
+    pub fn statefulset(self, name: &'a str) -> Self {
+        self.resource(ResourceType::StatefulSet(name))
+    }
+
+    pub fn daemonset(self, name: &'a str) -> Self {
+        self.resource(ResourceType::DaemonSet(name))
+    }
+
+    pub fn job(self, name: &'a str) -> Self {
+        self.resource(ResourceType::Job(name))
+    }
+
+    pub fn cronjob(self, name: &'a str) -> Self {
+        self.resource(ResourceType::CronJob(name))
+    }
+
+    pub fn service(self, name: &'a str) -> Self {
+        self.resource(ResourceType::Service(name))
+    }
+
+    pub fn pod(mut self, name: impl Into<String>) -> Self {
+        self.0.pods.push(name.into());
+        self
+    }
+
+    /// Also streams all pods matching this label selector.
+    pub fn selector(mut self, selector: Option<String>) -> Self {
+        self.0.selector = selector;
+        self
+    }
+
+    /// Also streams all pods scheduled on this node.
+    pub fn node(mut self, node: Option<String>) -> Self {
+        self.0.node = node;
+        self
+    }
+
+    /// Skips discovered pods whose names match this regex.
+    pub fn exclude_pods(mut self, exclude_pods: Option<Regex>) -> Self {
+        self.0.exclude_pods = exclude_pods;
+        self
+    }
+
+    /// Replaces the options of the log streams, including `follow`.
+    pub fn stream_options(mut self, stream_options: StreamOptions) -> Self {
+        self.0.stream_options = stream_options;
+        self
+    }
+
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.0.stream_options.follow = follow;
+        self
+    }
+
+    /// Replaces the options for rendering the lines, including the filters.
+    pub fn render_options(mut self, render_options: RenderOptions) -> Self {
+        self.0.render_options = render_options;
+        self
+    }
+
+    /// Only keeps lines containing this text, or one of the other filters.
+    pub fn filter(mut self, filter: impl Into<String>) -> Self {
+        self.0.render_options.filter.push(filter.into());
+        self
+    }
+
+    /// Passes the lines through this hook before filtering and outputting them, after the
+    /// hooks added before.
+    pub fn hook(mut self, hook: impl Hook + 'static) -> Self {
+        self.0.render_options.hooks.push(hook);
+        self
+    }
+
+    pub fn refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.0.refresh_interval = refresh_interval;
+        self
+    }
+
+    /// Watches the pods of the targets while following, which is the default, instead of
+    /// looking for new ones every `refresh_interval`.
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.0.watch = watch;
+        self
+    }
+
+    pub fn notify_removed(mut self, notify_removed: bool) -> Self {
+        self.0.notify_removed = notify_removed;
+        self
+    }
+
+    pub fn max_concurrent(mut self, max_concurrent: Option<usize>) -> Self {
+        self.0.max_concurrent = max_concurrent;
+        self
+    }
+
+    pub fn buffer_lines(mut self, buffer_lines: usize) -> Self {
+        self.0.buffer_lines = buffer_lines;
+        self
+    }
+
+    pub fn reconnect_on_rollout(mut self, reconnect_on_rollout: bool) -> Self {
+        self.0.reconnect_on_rollout = reconnect_on_rollout;
+        self
+    }
+
+    pub fn wait_for_resources(mut self, wait_for_resources: bool) -> Self {
+        self.0.wait_for_resources = wait_for_resources;
+        self
+    }
+
+    pub fn watch_pod_statuses(mut self, watch_pod_statuses: bool) -> Self {
+        self.0.watch_pod_statuses = watch_pod_statuses;
+        self
+    }
+
+    pub fn latest_only(mut self, latest_only: bool) -> Self {
+        self.0.latest_only = latest_only;
+        self
+    }
+
+    /// Also forwards the lines to these sinks.
+    pub fn sinks(mut self, sinks: SinkOptions) -> Self {
+        self.0.sinks = sinks;
+        self
+    }
+
+    /// Serves the metrics over HTTP on this address while running.
+    pub fn metrics_addr(mut self, metrics_addr: Option<SocketAddr>) -> Self {
+        self.0.metrics_addr = metrics_addr;
+        self
+    }
+
+    /// Reports the status of the streams on stderr this often while running.
+    pub fn status_interval(mut self, status_interval: Option<Duration>) -> Self {
+        self.0.status_interval = status_interval;
+        self
+    }
+
+    pub fn build(self) -> Klog<'a> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_builder() {
+        let (client, _) = test_util::fake_client(|req| match req.uri().path() {
+            "/api/v1/namespaces/default/pods/web-1" => {
+                test_util::json_response(test_util::pod("web-1", &["app"]))
+            }
+            "/api/v1/namespaces/default/pods/web-1/log" => {
+                test_util::text_response("started\nGET /health\nstopped\n")
+            }
+            _ => test_util::not_found(),
+        });
+        let klog = Klog::builder()
+            .pod("web-1")
+            .filter("started")
+            .filter("stopped")
+            .follow(false)
+            .build();
+
+        let events: Vec<_> = klog.pod_manager(client, None).events().collect().await;
+
+        let lines: Vec<_> = events
+            .iter()
+            .map(|e| e.as_ref().unwrap().raw.as_str())
+            .collect();
+        assert_eq!(lines, vec!["started", "stopped"]);
+    }
+
+    #[tokio::test]
+    async fn test_write_lines_to_sinks() {
+        let path = std::env::temp_dir().join(format!("klog-session-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let klog = Klog::builder()
+            .render_options(RenderOptions {
+                no_stdout: true,
+                ..RenderOptions::default()
+            })
+            .filter("started")
+            .sinks(SinkOptions {
+                archive: Some(path.clone()),
+                ..SinkOptions::default()
+            })
+            .build();
+        let line = |line: &str| test_util::log_line("web-1", line);
+
+        klog.write_lines(vec![line("started"), line("GET /health")])
+            .await
+            .unwrap();
+
+        let connection = crate::archive::open(&path).unwrap();
+        let archived = crate::archive::query(&connection, &Default::default()).unwrap();
+        assert_eq!(archived, vec![line("started")]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+pub mod alert;
+pub mod elasticsearch;
+pub mod fluentd;
+pub mod gelf;
+pub mod otlp;
+pub mod server;
+pub mod syslog;
+pub mod webhook;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+use tokio::time::Instant;
+
+use crate::archive;
+use crate::output::{LogLine, RenderOptions, DEFAULT_BUFFER_LINES};
+use crate::upload::{self, UploadOptions};
+
+/// The sinks to forward lines to, and where to upload rotated files of the log file.
+#[derive(Clone, Debug)]
+pub struct SinkOptions {
+    pub elasticsearch: Option<elasticsearch::ElasticsearchOptions>,
+    pub fluentd: Option<fluentd::FluentdOptions>,
+    pub gelf: Option<gelf::GelfAddress>,
+    pub otlp: Option<otlp::OtlpOptions>,
+    pub webhook: Option<webhook::WebhookOptions>,
+    pub alert: Option<alert::AlertOptions>,
+    pub syslog: Option<syslog::SyslogAddress>,
+    /// Serve the lines to clients connecting here.
+    pub serve: Option<server::ServeAddress>,
+    /// Store the lines in this SQLite file, see `crate::archive`.
+    pub archive: Option<PathBuf>,
+    /// Upload the files `RenderOptions::log_file` is rotated to.
+    pub upload: Option<UploadOptions>,
+    /// How many lines are buffered for each sink before lines are dropped for it.
+    pub buffer_lines: usize,
+}
+
+impl Default for SinkOptions {
+    fn default() -> Self {
+        SinkOptions {
+            elasticsearch: None,
+            fluentd: None,
+            gelf: None,
+            otlp: None,
+            webhook: None,
+            alert: None,
+            syslog: None,
+            serve: None,
+            archive: None,
+            upload: None,
+            buffer_lines: DEFAULT_BUFFER_LINES,
+        }
+    }
+}
+
+impl SinkOptions {
+    /// Starts the sinks and has writers using `render_options` forward lines to them. The
+    /// returned tasks end once all those writers are dropped and the sinks sent what is left.
+    pub fn start(
+        &self,
+        render_options: &mut RenderOptions,
+    ) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
+        let buffer = self.buffer_lines.max(1);
+        let mut senders = vec![];
+        let mut channel = || {
+            let (lines_tx, lines_rx) = mpsc::channel(buffer);
+            senders.push(lines_tx);
+            lines_rx
+        };
+        let mut tasks = vec![];
+        if let Some(options) = &self.elasticsearch {
+            tasks.push(task::spawn(elasticsearch::run(options.clone(), channel())));
+        }
+        if let Some(options) = &self.fluentd {
+            tasks.push(task::spawn(fluentd::run(options.clone(), channel())));
+        }
+        if let Some(address) = &self.gelf {
+            tasks.push(task::spawn(gelf::run(address.clone(), channel())));
+        }
+        if let Some(options) = &self.otlp {
+            tasks.push(task::spawn(otlp::run(options.clone(), channel())));
+        }
+        if let Some(options) = &self.webhook {
+            tasks.push(task::spawn(webhook::run(options.clone(), channel())));
+        }
+        if let Some(options) = &self.alert {
+            tasks.push(task::spawn(alert::run(options.clone(), channel())));
+        }
+        if let Some(address) = &self.syslog {
+            tasks.push(task::spawn(syslog::run(address.clone(), channel())));
+        }
+        if let Some(address) = &self.serve {
+            let listener = server::Listener::bind(address)?;
+            tasks.push(task::spawn(server::run(listener, channel())));
+        }
+        if let Some(path) = &self.archive {
+            let connection = archive::open(path)?;
+            let lines = channel();
+            tasks.push(task::spawn_blocking(move || {
+                archive::run(connection, lines)
+            }));
+        }
+        render_options.sinks.extend(senders);
+        if let Some(options) = &self.upload {
+            let (segments_tx, segments_rx) = mpsc::channel(upload::PENDING_SEGMENTS);
+            tasks.push(task::spawn(upload::run(options.clone(), segments_rx)));
+            render_options.segments = Some(segments_tx);
+        }
+        Ok(tasks)
+    }
+}
+
+/// Parses a header like `Authorization=Bearer abc`.
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    match header.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!(
+            "Invalid header {}, expected e.g. Authorization=Bearer abc",
+            header
+        )),
+    }
+}
+
+/// Collects the next lines into a batch, which is complete once it holds `max_lines`
+/// lines or `interval` passed since its first line. Returns an empty batch once all
+/// senders are dropped and no lines are left.
+pub async fn next_batch(
+    lines: &mut mpsc::Receiver<LogLine>,
+    max_lines: usize,
+    interval: Duration,
+) -> Vec<LogLine> {
+    let Some(first) = lines.recv().await else {
+        return vec![];
+    };
+    let deadline = Instant::now() + interval;
+    let mut batch = vec![first];
+    while batch.len() < max_lines {
+        match tokio::time::timeout_at(deadline, lines.recv()).await {
+            Ok(Some(line)) => batch.push(line),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("Authorization=Bearer a=b").unwrap(),
+            ("Authorization".to_string(), "Bearer a=b".to_string())
+        );
+        assert!(parse_header("Authorization").is_err());
+        assert!(parse_header("=value").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_next_batch() {
+        let (tx, mut rx) = mpsc::channel(100);
+        for line in ["one", "two", "three"] {
+            tx.try_send(test_util::log_line("web-1", line)).unwrap();
+        }
+
+        let batch = next_batch(&mut rx, 2, Duration::from_secs(60)).await;
+        assert_eq!(batch.len(), 2);
+
+        let batch = next_batch(&mut rx, 2, Duration::from_millis(10)).await;
+        assert_eq!(batch[0].line, "three");
+        assert_eq!(batch.len(), 1);
+
+        drop(tx);
+        assert!(next_batch(&mut rx, 2, Duration::from_secs(60))
+            .await
+            .is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use k8s_openapi::serde_json::json;
+use regex::Regex;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::diagnostics;
+use crate::output::LogLine;
+
+/// When and where alerts are sent.
+#[derive(Clone, Debug)]
+pub struct AlertOptions {
+    /// Lines matching this send an alert.
+    pub pattern: Regex,
+    /// A Slack or Discord incoming webhook URL.
+    pub webhook: String,
+    /// How many preceding lines of the same pod are included in an alert.
+    pub context: usize,
+    /// How long to wait at least between alerts for the same pod.
+    pub interval: Duration,
+}
+
+type PodKey = (Option<String>, String, String);
+
+/// Decides which lines send an alert and renders their message.
+struct Alerter {
+    options: AlertOptions,
+    recent: HashMap<PodKey, VecDeque<String>>,
+    last_sent: HashMap<PodKey, Instant>,
+    suppressed: HashMap<PodKey, usize>,
+}
+
+impl Alerter {
+    fn new(options: AlertOptions) -> Self {
+        Alerter {
+            options,
+            recent: HashMap::new(),
+            last_sent: HashMap::new(),
+            suppressed: HashMap::new(),
+        }
+    }
+
+    /// Returns the message to send for the line, if it matches and its pod was not
+    /// alerted about within the interval. Matches within the interval are counted and
+    /// mentioned in the next alert.
+    fn check(&mut self, line: &LogLine, now: Instant) -> Option<String> {
+        let key = (
+            line.context.clone(),
+            line.namespace.clone(),
+            line.pod.clone(),
+        );
+        let recent = self.recent.entry(key.clone()).or_default();
+        let mut lines: Vec<String> = recent.iter().cloned().collect();
+        lines.push(line.line.clone());
+        recent.push_back(line.line.clone());
+        if recent.len() > self.options.context {
+            recent.pop_front();
+        }
+
+        if !self.options.pattern.is_match(&line.line) {
+            return None;
+        }
+        if let Some(last_sent) = self.last_sent.get(&key) {
+            if now.duration_since(*last_sent) < self.options.interval {
+                *self.suppressed.entry(key).or_default() += 1;
+                return None;
+            }
+        }
+        self.last_sent.insert(key.clone(), now);
+
+        let mut pod = format!("{}/{}", line.namespace, line.pod);
+        if let Some(container) = &line.container {
+            pod.push_str(&format!(" ({})", container));
+        }
+        if let Some(context) = &line.context {
+            pod = format!("{}: {}", context, pod);
+        }
+        let mut message = format!(
+            "klog: `{}` matched `{}`\n```\n{}\n```",
+            pod,
+            self.options.pattern,
+            lines.join("\n")
+        );
+        if let Some(suppressed) = self.suppressed.remove(&key) {
+            message.push_str(&format!(
+                "\n{} earlier matches were not alerted about",
+                suppressed
+            ));
+        }
+        Some(message)
+    }
+}
+
+async fn send(client: &reqwest::Client, webhook: &str, message: &str) -> anyhow::Result<()> {
+    // Slack reads the text, Discord the content.
+    let payload = json!({ "text": message, "content": message });
+    client
+        .post(webhook)
+        .header("Content-Type", "application/json")
+        .body(payload.to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Sends alerts for matching lines until all senders are dropped. Failed alerts are
+/// reported and dropped.
+pub async fn run(options: AlertOptions, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let webhook = options.webhook.clone();
+    let mut alerter = Alerter::new(options);
+    while let Some(line) = lines.recv().await {
+        if let Some(message) = alerter.check(&line, Instant::now()) {
+            if let Err(e) = send(&client, &webhook, &message).await {
+                diagnostics::error(format_args!("Could not send alert: {}", e));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn log_line(pod: &str, line: &str) -> LogLine {
+        LogLine {
+            container: Some("app".to_string()),
+            ..test_util::log_line(pod, line)
+        }
+    }
+
+    fn alerter() -> Alerter {
+        Alerter::new(AlertOptions {
+            pattern: Regex::new("panic|OOMKilled").unwrap(),
+            webhook: "http://localhost".to_string(),
+            context: 2,
+            interval: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn test_alert_with_context() {
+        let mut alerter = alerter();
+        let now = Instant::now();
+
+        for line in ["one", "two", "three"] {
+            assert_eq!(alerter.check(&log_line("web-1", line), now), None);
+        }
+        assert_eq!(alerter.check(&log_line("web-2", "other"), now), None);
+
+        assert_eq!(
+            alerter
+                .check(&log_line("web-1", "panic: oops"), now)
+                .unwrap(),
+            "klog: `shop/web-1 (app)` matched `panic|OOMKilled`\n```\ntwo\nthree\npanic: oops\n```"
+        );
+    }
+
+    #[test]
+    fn test_alerts_are_rate_limited_per_pod() {
+        let mut alerter = alerter();
+        let now = Instant::now();
+
+        assert!(alerter.check(&log_line("web-1", "panic"), now).is_some());
+        assert!(alerter.check(&log_line("web-1", "panic"), now).is_none());
+        assert!(alerter.check(&log_line("web-1", "panic"), now).is_none());
+        assert!(alerter.check(&log_line("web-2", "panic"), now).is_some());
+
+        let message = alerter
+            .check(
+                &log_line("web-1", "OOMKilled"),
+                now + Duration::from_secs(60),
+            )
+            .unwrap();
+        assert!(message.ends_with("\n2 earlier matches were not alerted about"));
+    }
+}
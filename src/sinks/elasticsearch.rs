@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use k8s_openapi::chrono::format::{Item, StrftimeItems};
+use k8s_openapi::chrono::{DateTime, Utc};
+use k8s_openapi::serde_json::{self, json, Value};
+use tokio::sync::mpsc;
+
+use crate::diagnostics;
+use crate::output::{self, LogLine};
+
+/// How many lines are indexed with one bulk request at most.
+const MAX_BATCH: usize = 500;
+
+/// Where and how lines are indexed.
+#[derive(Clone, Debug)]
+pub struct ElasticsearchOptions {
+    /// The URL of Elasticsearch or OpenSearch, e.g. `https://localhost:9200`.
+    pub url: String,
+    /// The name of the index, which may contain strftime fields like `%Y.%m.%d` that are
+    /// filled in from the time of each line.
+    pub index: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub api_key: Option<String>,
+    /// How long lines are collected at most before they are indexed.
+    pub flush_interval: Duration,
+}
+
+/// Checks that an index name only contains valid strftime fields.
+pub fn parse_index(index: &str) -> Result<String, String> {
+    if StrftimeItems::new(index).any(|item| item == Item::Error) {
+        return Err(format!(
+            "Invalid index {}, expected e.g. klog-%Y.%m.%d",
+            index
+        ));
+    }
+    Ok(index.to_string())
+}
+
+/// The time of a line, as recorded by Kubernetes if requested, or else now.
+fn timestamp(line: &LogLine) -> DateTime<Utc> {
+    line.timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or_else(Utc::now, |timestamp| timestamp.with_timezone(&Utc))
+}
+
+/// Renders the body of a bulk request indexing `lines`, as documents like those of
+/// `--output json` with an added `@timestamp`.
+fn bulk_body(index: &str, lines: &[LogLine]) -> String {
+    let mut body = String::new();
+    for line in lines {
+        let timestamp = timestamp(line);
+        let action = json!({ "index": { "_index": timestamp.format(index).to_string() } });
+        let mut document = output::to_json(line, 1, 1);
+        document["@timestamp"] = timestamp.to_rfc3339().into();
+        body.push_str(&format!("{}\n{}\n", action, document));
+    }
+    body
+}
+
+/// Describes the first document Elasticsearch failed to index, if any.
+fn first_error(response: &Value) -> Option<String> {
+    if response["errors"] != Value::Bool(true) {
+        return None;
+    }
+    let items = response["items"].as_array()?;
+    items
+        .iter()
+        .find_map(|item| item["index"].get("error"))
+        .map(|error| {
+            format!(
+                "{}: {}",
+                error["type"].as_str().unwrap_or_default(),
+                error["reason"].as_str().unwrap_or_default()
+            )
+        })
+}
+
+async fn index(
+    client: &reqwest::Client,
+    options: &ElasticsearchOptions,
+    lines: &[LogLine],
+) -> anyhow::Result<()> {
+    let mut request = client
+        .post(format!("{}/_bulk", options.url.trim_end_matches('/')))
+        .header("Content-Type", "application/x-ndjson")
+        .body(bulk_body(&options.index, lines));
+    if let Some(username) = &options.username {
+        request = request.basic_auth(username, options.password.as_ref());
+    }
+    if let Some(api_key) = &options.api_key {
+        request = request.header("Authorization", format!("ApiKey {}", api_key));
+    }
+    let response = request.send().await?.error_for_status()?;
+    let response: Value = serde_json::from_slice(&response.bytes().await?)?;
+    match first_error(&response) {
+        Some(error) => Err(anyhow::anyhow!("Failed to index lines: {}", error)),
+        None => Ok(()),
+    }
+}
+
+/// Indexes lines in batches until all senders are dropped. Failed batches are reported
+/// and dropped, so an unavailable cluster doesn't stop the output.
+pub async fn run(
+    options: ElasticsearchOptions,
+    mut lines: mpsc::Receiver<LogLine>,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    loop {
+        let batch = super::next_batch(&mut lines, MAX_BATCH, options.flush_interval).await;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = index(&client, &options, &batch).await {
+            diagnostics::error(format_args!("Could not send logs to Elasticsearch: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_parse_index() {
+        assert!(parse_index("klog-%Y.%m.%d").is_ok());
+        assert!(parse_index("klog").is_ok());
+        assert!(parse_index("klog-%Q").is_err());
+    }
+
+    #[test]
+    fn test_bulk_body() {
+        let line = LogLine {
+            timestamp: Some("2024-01-31T23:59:59Z".to_string()),
+            ..test_util::log_line("web-1", r#"{"msg": "started"}"#)
+        };
+
+        let body = bulk_body("klog-%Y.%m.%d", &[line]);
+
+        let lines: Vec<Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines[0],
+            json!({ "index": { "_index": "klog-2024.01.31" } })
+        );
+        assert_eq!(lines[1]["@timestamp"], "2024-01-31T23:59:59+00:00");
+        assert_eq!(lines[1]["pod"], "web-1");
+        assert_eq!(lines[1]["parsed"]["msg"], "started");
+        assert!(body.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_first_error() {
+        assert_eq!(first_error(&json!({ "errors": false, "items": [] })), None);
+        let response = json!({
+            "errors": true,
+            "items": [
+                { "index": { "status": 201 } },
+                { "index": { "error": { "type": "mapper_parsing_exception", "reason": "bad" } } }
+            ]
+        });
+        assert_eq!(
+            first_error(&response).unwrap(),
+            "mapper_parsing_exception: bad"
+        );
+    }
+}
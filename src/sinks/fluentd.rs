@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use k8s_openapi::chrono::{DateTime, Utc};
+use k8s_openapi::serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::diagnostics;
+use crate::output::LogLine;
+use crate::util;
+
+/// How many lines are sent with one batch at most.
+const MAX_BATCH: usize = 500;
+
+/// How long lines are collected at most before they are sent.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where and how lines are forwarded.
+#[derive(Clone, Debug)]
+pub struct FluentdOptions {
+    /// The address of a fluentd or fluent-bit `forward` input, e.g. `localhost:24224`.
+    pub address: String,
+    /// Tags are this followed by the namespace and pod, e.g. `klog.shop.web-1`.
+    pub tag_prefix: String,
+}
+
+/// Adds the default port 24224 to an address without one.
+pub fn parse_address(address: &str) -> Result<String, String> {
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(address.to_string())
+        }
+        Some(_) => Err(format!(
+            "Invalid address {}, expected e.g. localhost:24224",
+            address
+        )),
+        None if !address.is_empty() => Ok(format!("{}:24224", address)),
+        None => Err("Invalid address, expected e.g. localhost:24224".to_string()),
+    }
+}
+
+fn tag(prefix: &str, line: &LogLine) -> String {
+    format!("{}.{}.{}", prefix, line.namespace, line.pod)
+}
+
+/// Maps a line to a record with the Kubernetes metadata fluent-bit adds to container logs.
+fn record(line: &LogLine) -> Value {
+    let mut kubernetes = json!({
+        "namespace_name": line.namespace,
+        "pod_name": line.pod,
+    });
+    if let Some(container) = &line.container {
+        kubernetes["container_name"] = container.as_str().into();
+    }
+    let mut record = json!({ "log": line.line, "kubernetes": kubernetes });
+    if let Some(context) = &line.context {
+        record["context"] = context.as_str().into();
+    }
+    if let Some(resource) = &line.resource {
+        record["resource"] = resource.as_str().into();
+    }
+    if let Some(level) = util::detect_level(&line.line) {
+        record["level"] = level.as_str().into();
+    }
+    record
+}
+
+/// The time of a line in seconds, as recorded by Kubernetes if requested, or else now.
+fn time(line: &LogLine) -> i64 {
+    line.timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or_else(Utc::now, |timestamp| timestamp.with_timezone(&Utc))
+        .timestamp()
+}
+
+/// Encodes a batch as `Forward` mode messages, one per tag.
+fn encode(tag_prefix: &str, lines: &[LogLine]) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut entries: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for line in lines {
+        entries
+            .entry(tag(tag_prefix, line))
+            .or_default()
+            .push(json!([time(line), record(line)]));
+    }
+    let mut encoded = vec![];
+    for (tag, entries) in entries {
+        encoded.extend(rmp_serde::to_vec(&json!([tag, entries]))?);
+    }
+    Ok(encoded)
+}
+
+/// Forwards lines in batches until all senders are dropped. After errors, the connection
+/// is opened again for the next batch, and batches that could not be sent are reported
+/// and dropped.
+pub async fn run(
+    options: FluentdOptions,
+    mut lines: mpsc::Receiver<LogLine>,
+) -> anyhow::Result<()> {
+    let mut connection: Option<TcpStream> = None;
+    loop {
+        let batch = super::next_batch(&mut lines, MAX_BATCH, FLUSH_INTERVAL).await;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let sent = async {
+            let encoded = encode(&options.tag_prefix, &batch)?;
+            if connection.is_none() {
+                connection = Some(TcpStream::connect(&options.address).await?);
+            }
+            let result = connection.as_mut().unwrap().write_all(&encoded).await;
+            if result.is_err() {
+                connection = None;
+            }
+            Ok::<(), anyhow::Error>(result?)
+        };
+        if let Err(e) = sent.await {
+            diagnostics::error(format_args!("Could not send logs to fluentd: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn log_line(pod: &str, line: &str) -> LogLine {
+        LogLine {
+            container: Some("app".to_string()),
+            timestamp: Some("2024-01-01T00:00:01Z".to_string()),
+            ..test_util::log_line(pod, line)
+        }
+    }
+
+    /// Decodes the messages of an encoded batch.
+    fn decode(mut encoded: &[u8]) -> Vec<Value> {
+        let mut messages = vec![];
+        while !encoded.is_empty() {
+            messages.push(rmp_serde::decode::from_read(&mut encoded).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("fluentd").unwrap(), "fluentd:24224");
+        assert_eq!(parse_address("fluentd:24225").unwrap(), "fluentd:24225");
+        assert!(parse_address("fluentd:port").is_err());
+        assert!(parse_address("").is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let lines = [
+            log_line("web-1", "ERROR db down"),
+            log_line("web-2", "started"),
+            log_line("web-1", "retrying"),
+        ];
+
+        let messages = decode(&encode("klog", &lines).unwrap());
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0][0], "klog.shop.web-1");
+        let entries = messages[0][1].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0][0], 1704067201);
+        assert_eq!(
+            entries[0][1],
+            json!({
+                "log": "ERROR db down",
+                "level": "error",
+                "kubernetes": {
+                    "namespace_name": "shop",
+                    "pod_name": "web-1",
+                    "container_name": "app"
+                }
+            })
+        );
+        assert_eq!(entries[1][1]["log"], "retrying");
+        assert_eq!(messages[1][0], "klog.shop.web-2");
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = FluentdOptions {
+            address: listener.local_addr().unwrap().to_string(),
+            tag_prefix: "klog".to_string(),
+        };
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("web-1", "hello")).unwrap();
+        drop(tx);
+
+        run(options, rx).await.unwrap();
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut received = vec![];
+        socket.read_to_end(&mut received).await.unwrap();
+        let messages = decode(&received);
+        assert_eq!(messages[0][1][0][1]["log"], "hello");
+    }
+}
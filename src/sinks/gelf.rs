@@ -0,0 +1,265 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use k8s_openapi::chrono::{DateTime, Utc};
+use k8s_openapi::serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+use super::syslog;
+use crate::diagnostics;
+use crate::output::LogLine;
+
+/// The largest UDP datagram sent, above which messages are split into chunks.
+const MAX_DATAGRAM: usize = 8192;
+
+/// The size of the header of each chunk.
+const CHUNK_HEADER: usize = 12;
+
+/// Graylog drops messages with more chunks than this.
+const MAX_CHUNKS: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// Where messages are sent to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GelfAddress {
+    pub transport: Transport,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Parses an address like `udp://graylog:12201` or `tcp://graylog`, where the port
+/// defaults to 12201.
+pub fn parse_address(address: &str) -> Result<GelfAddress, String> {
+    let invalid = || {
+        format!(
+            "Invalid GELF address {}, expected e.g. udp://host:12201 or tcp://host:12201",
+            address
+        )
+    };
+    let (scheme, rest) = address.split_once("://").ok_or_else(invalid)?;
+    let transport = match scheme {
+        "udp" => Transport::Udp,
+        "tcp" => Transport::Tcp,
+        _ => return Err(invalid()),
+    };
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+        None => (rest, 12201),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok(GelfAddress {
+        transport,
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// Renders a line as a GELF 1.1 message, with the pod as host and its metadata as
+/// additional fields.
+pub fn format_message(line: &LogLine) -> Value {
+    let timestamp = line
+        .timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or_else(Utc::now, |timestamp| timestamp.with_timezone(&Utc));
+    let mut message = json!({
+        "version": "1.1",
+        "host": line.pod,
+        "short_message": line.line,
+        "timestamp": timestamp.timestamp_micros() as f64 / 1e6,
+        "level": syslog::severity(&line.line),
+        "_namespace": line.namespace,
+        "_pod": line.pod,
+    });
+    for (key, value) in [
+        ("_context", &line.context),
+        ("_container", &line.container),
+        ("_resource", &line.resource),
+    ] {
+        if let Some(value) = value {
+            message[key] = value.as_str().into();
+        }
+    }
+    message
+}
+
+/// Splits a message that is too large for one datagram into GELF chunks, or returns it
+/// as is. Returns no datagrams if the message is too large even for chunks.
+fn datagrams(message: Vec<u8>, id: [u8; 8]) -> Vec<Vec<u8>> {
+    if message.len() <= MAX_DATAGRAM {
+        return vec![message];
+    }
+    let chunks: Vec<&[u8]> = message.chunks(MAX_DATAGRAM - CHUNK_HEADER).collect();
+    if chunks.len() > MAX_CHUNKS {
+        return vec![];
+    }
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut datagram = vec![0x1e, 0x0f];
+            datagram.extend(id);
+            datagram.extend([index as u8, chunks.len() as u8]);
+            datagram.extend(*chunk);
+            datagram
+        })
+        .collect()
+}
+
+/// A message id that differs between the messages of this process.
+fn message_id() -> [u8; 8] {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    (nanos ^ NEXT.fetch_add(1, Ordering::Relaxed).rotate_right(16)).to_be_bytes()
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+async fn connect(address: &GelfAddress) -> std::io::Result<Connection> {
+    let target = (address.host.as_str(), address.port);
+    Ok(match address.transport {
+        Transport::Udp => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(target).await?;
+            Connection::Udp(socket)
+        }
+        Transport::Tcp => Connection::Tcp(TcpStream::connect(target).await?),
+    })
+}
+
+impl Connection {
+    /// Sends a message, chunked on UDP and terminated by a null byte on TCP.
+    async fn send(&mut self, message: &Value) -> std::io::Result<()> {
+        let mut message = message.to_string().into_bytes();
+        match self {
+            Connection::Udp(socket) => {
+                for datagram in datagrams(message, message_id()) {
+                    socket.send(&datagram).await?;
+                }
+                Ok(())
+            }
+            Connection::Tcp(stream) => {
+                message.push(0);
+                stream.write_all(&message).await
+            }
+        }
+    }
+}
+
+/// Sends lines as GELF messages until all senders are dropped. After errors, the
+/// connection is opened again for the next line, and lines that could not be sent are
+/// reported and dropped.
+pub async fn run(address: GelfAddress, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<()> {
+    let mut connection = None;
+    while let Some(line) = lines.recv().await {
+        let sent = async {
+            if connection.is_none() {
+                connection = Some(connect(&address).await?);
+            }
+            let result = connection
+                .as_mut()
+                .unwrap()
+                .send(&format_message(&line))
+                .await;
+            if result.is_err() {
+                connection = None;
+            }
+            result
+        };
+        if let Err(e) = sent.await {
+            diagnostics::error(format_args!("Could not send logs to GELF: {}", e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn log_line(line: &str) -> LogLine {
+        LogLine {
+            container: Some("app".to_string()),
+            timestamp: Some("2024-01-01T00:00:01.5Z".to_string()),
+            ..test_util::log_line("web-1", line)
+        }
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("udp://graylog").unwrap(),
+            GelfAddress {
+                transport: Transport::Udp,
+                host: "graylog".to_string(),
+                port: 12201,
+            }
+        );
+        assert_eq!(parse_address("tcp://graylog:12202").unwrap().port, 12202);
+        assert!(parse_address("graylog:12201").is_err());
+        assert!(parse_address("tls://graylog").is_err());
+    }
+
+    #[test]
+    fn test_format_message() {
+        assert_eq!(
+            format_message(&log_line("ERROR db down")),
+            json!({
+                "version": "1.1",
+                "host": "web-1",
+                "short_message": "ERROR db down",
+                "timestamp": 1704067201.5,
+                "level": 3,
+                "_namespace": "shop",
+                "_pod": "web-1",
+                "_container": "app"
+            })
+        );
+    }
+
+    #[test]
+    fn test_datagrams() {
+        let id = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(datagrams(vec![b'x'; 10], id), vec![vec![b'x'; 10]]);
+
+        let chunks = datagrams(vec![b'x'; MAX_DATAGRAM * 2], id);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].len(), MAX_DATAGRAM);
+        assert_eq!(
+            &chunks[1][..CHUNK_HEADER],
+            &[0x1e, 0x0f, 1, 2, 3, 4, 5, 6, 7, 8, 1, 3]
+        );
+        let payload: usize = chunks.iter().map(|chunk| chunk.len() - CHUNK_HEADER).sum();
+        assert_eq!(payload, MAX_DATAGRAM * 2);
+
+        assert!(datagrams(vec![b'x'; MAX_DATAGRAM * MAX_CHUNKS], id).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_udp_round_trip() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = parse_address(&format!("udp://{}", server.local_addr().unwrap())).unwrap();
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(log_line("hello")).unwrap();
+        drop(tx);
+
+        run(address, rx).await.unwrap();
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).await.unwrap();
+        let message: Value = k8s_openapi::serde_json::from_slice(&buf[..len]).unwrap();
+        assert_eq!(message["short_message"], "hello");
+    }
+}
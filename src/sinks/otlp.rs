@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use k8s_openapi::chrono::{DateTime, Utc};
+use k8s_openapi::serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::diagnostics;
+use crate::output::LogLine;
+use crate::util::{self, Level};
+
+/// How many lines are exported with one request at most.
+const MAX_BATCH: usize = 500;
+
+/// How long lines are collected at most before they are exported.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where lines are exported to.
+#[derive(Clone, Debug)]
+pub struct OtlpOptions {
+    /// The base URL of an OTLP/HTTP receiver, e.g. `http://localhost:4318`.
+    pub endpoint: String,
+    /// Headers sent with each request, e.g. for authentication.
+    pub headers: Vec<(String, String)>,
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// The resource attributes of a line, following the OpenTelemetry semantic conventions
+/// for Kubernetes.
+fn resource_attributes(line: &LogLine) -> Vec<(&'static str, String)> {
+    [
+        ("k8s.cluster.name", line.context.clone()),
+        ("k8s.namespace.name", Some(line.namespace.clone())),
+        ("k8s.pod.name", Some(line.pod.clone())),
+        ("k8s.container.name", line.container.clone()),
+        ("service.name", line.resource.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value?)))
+    .collect()
+}
+
+/// Maps a line to an OTLP LogRecord, with the severity found in the line.
+fn log_record(line: &LogLine, observed: DateTime<Utc>) -> Value {
+    let time = line
+        .timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or(observed, |timestamp| timestamp.with_timezone(&Utc));
+    let nanos = |time: DateTime<Utc>| time.timestamp_nanos_opt().unwrap_or_default().to_string();
+    let mut record = json!({
+        "timeUnixNano": nanos(time),
+        "observedTimeUnixNano": nanos(observed),
+        "body": { "stringValue": line.line },
+    });
+    if let Some(level) = util::detect_level(&line.line) {
+        record["severityNumber"] = match level {
+            Level::Trace => 1,
+            Level::Debug => 5,
+            Level::Info => 9,
+            Level::Warn => 13,
+            Level::Error => 17,
+            Level::Fatal => 21,
+        }
+        .into();
+        record["severityText"] = level.as_str().to_uppercase().into();
+    }
+    record
+}
+
+/// Renders an export request for `lines`, grouping their records by pod and container.
+fn export_request(lines: &[LogLine], observed: DateTime<Utc>) -> Value {
+    let mut resources: BTreeMap<Vec<(&str, String)>, Vec<Value>> = BTreeMap::new();
+    for line in lines {
+        resources
+            .entry(resource_attributes(line))
+            .or_default()
+            .push(log_record(line, observed));
+    }
+    let resource_logs: Vec<Value> = resources
+        .into_iter()
+        .map(|(attributes, records)| {
+            let attributes: Vec<Value> = attributes
+                .iter()
+                .map(|(key, value)| attribute(key, value))
+                .collect();
+            json!({
+                "resource": { "attributes": attributes },
+                "scopeLogs": [{
+                    "scope": { "name": "klog", "version": env!("CARGO_PKG_VERSION") },
+                    "logRecords": records,
+                }],
+            })
+        })
+        .collect();
+    json!({ "resourceLogs": resource_logs })
+}
+
+async fn export(
+    client: &reqwest::Client,
+    options: &OtlpOptions,
+    lines: &[LogLine],
+) -> anyhow::Result<()> {
+    let mut request = client
+        .post(format!(
+            "{}/v1/logs",
+            options.endpoint.trim_end_matches('/')
+        ))
+        .header("Content-Type", "application/json")
+        .body(export_request(lines, Utc::now()).to_string());
+    for (name, value) in &options.headers {
+        request = request.header(name, value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Exports lines as OTLP/HTTP JSON in batches until all senders are dropped. Failed
+/// batches are reported and dropped.
+pub async fn run(options: OtlpOptions, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    loop {
+        let batch = super::next_batch(&mut lines, MAX_BATCH, FLUSH_INTERVAL).await;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = export(&client, &options, &batch).await {
+            diagnostics::error(format_args!("Could not send logs to OTLP: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn log_line(pod: &str, line: &str) -> LogLine {
+        LogLine {
+            container: Some("app".to_string()),
+            timestamp: Some("2024-01-01T00:00:01Z".to_string()),
+            ..test_util::log_line(pod, line)
+        }
+    }
+
+    #[test]
+    fn test_export_request() {
+        let observed = "2024-01-01T00:00:02Z".parse().unwrap();
+        let lines = [
+            log_line("web-1", "ERROR db down"),
+            log_line("web-2", "started"),
+            log_line("web-1", "level=debug msg=retrying"),
+        ];
+
+        let request = export_request(&lines, observed);
+
+        let resource_logs = request["resourceLogs"].as_array().unwrap();
+        assert_eq!(resource_logs.len(), 2);
+        let web_1 = &resource_logs[0];
+        assert!(web_1["resource"]["attributes"]
+            .as_array()
+            .unwrap()
+            .contains(&attribute("k8s.pod.name", "web-1")));
+        let records = web_1["scopeLogs"][0]["logRecords"].as_array().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["timeUnixNano"], "1704067201000000000");
+        assert_eq!(records[0]["observedTimeUnixNano"], "1704067202000000000");
+        assert_eq!(records[0]["severityNumber"], 17);
+        assert_eq!(records[0]["severityText"], "ERROR");
+        assert_eq!(records[0]["body"]["stringValue"], "ERROR db down");
+        assert_eq!(records[1]["severityNumber"], 5);
+
+        let web_2 = &resource_logs[1]["scopeLogs"][0]["logRecords"][0];
+        assert!(web_2.get("severityNumber").is_none());
+    }
+}
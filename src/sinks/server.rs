@@ -0,0 +1,170 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::output::{self, LogLine};
+
+/// How many lines are buffered for a client that reads slower than lines arrive, before
+/// it misses some.
+const CLIENT_BUFFER: usize = 4096;
+
+/// Where clients connect to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServeAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// Parses an address like `tcp://0.0.0.0:5000` or `unix:///tmp/klog.sock`.
+pub fn parse_address(address: &str) -> Result<ServeAddress, String> {
+    let invalid = || {
+        format!(
+            "Invalid address {}, expected e.g. tcp://0.0.0.0:5000 or unix:///tmp/klog.sock",
+            address
+        )
+    };
+    match address.split_once("://") {
+        Some(("tcp", addr)) => addr.parse().map(ServeAddress::Tcp).map_err(|_| invalid()),
+        Some(("unix", path)) if !path.is_empty() => Ok(ServeAddress::Unix(path.into())),
+        _ => Err(invalid()),
+    }
+}
+
+type Client = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// A bound address that clients connect to.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    /// Binds the address, replacing the socket file of an earlier session.
+    pub fn bind(address: &ServeAddress) -> io::Result<Self> {
+        match address {
+            ServeAddress::Tcp(addr) => {
+                let listener = std::net::TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+            }
+            ServeAddress::Unix(path) => Self::bind_unix(path),
+        }
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(_path: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets are not supported on this platform",
+        ))
+    }
+
+    async fn accept(&self) -> io::Result<Client> {
+        Ok(match self {
+            Listener::Tcp(listener) => Box::new(listener.accept().await?.0),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Box::new(listener.accept().await?.0),
+        })
+    }
+}
+
+/// Writes lines to a client until it disconnects or no lines are left. Lines the client
+/// is too slow for are skipped.
+async fn serve_client(mut client: Client, mut lines: broadcast::Receiver<Arc<String>>) {
+    loop {
+        let line = match lines.recv().await {
+            Ok(line) => line,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if client.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+    let _ = client.shutdown().await;
+}
+
+/// Sends every line as NDJSON, like `--output json`, to each connected client until all
+/// senders are dropped. Clients only receive the lines that arrive after they connect.
+pub async fn run(listener: Listener, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<()> {
+    let (clients, _) = broadcast::channel(CLIENT_BUFFER);
+    loop {
+        tokio::select! {
+            line = lines.recv() => {
+                let Some(line) = line else {
+                    return Ok(());
+                };
+                // Sending only fails while no client is connected.
+                let _ = clients.send(Arc::new(format!("{}\n", output::to_json(&line, 1, 1))));
+            }
+            client = listener.accept() => {
+                if let Ok(client) = client {
+                    tokio::spawn(serve_client(client, clients.subscribe()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use k8s_openapi::serde_json::{self, Value};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("tcp://0.0.0.0:5000").unwrap(),
+            ServeAddress::Tcp("0.0.0.0:5000".parse().unwrap())
+        );
+        assert_eq!(
+            parse_address("unix:///tmp/klog.sock").unwrap(),
+            ServeAddress::Unix("/tmp/klog.sock".into())
+        );
+        assert!(parse_address("tcp://localhost").is_err());
+        assert!(parse_address("unix://").is_err());
+        assert!(parse_address("0.0.0.0:5000").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run() {
+        let path = std::env::temp_dir().join(format!("klog-serve-{}.sock", std::process::id()));
+        let listener = Listener::bind(&ServeAddress::Unix(path.clone())).unwrap();
+        let (tx, rx) = mpsc::channel(100);
+        let server = tokio::spawn(run(listener, rx));
+
+        let client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        // Give the server a moment to accept the client before lines arrive.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tx.try_send(test_util::log_line("web-1", "hello")).unwrap();
+        drop(tx);
+        server.await.unwrap().unwrap();
+
+        let mut lines = BufReader::new(client).lines();
+        let line: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(line["pod"], "web-1");
+        assert_eq!(line["line"], "hello");
+        assert_eq!(lines.next_line().await.unwrap(), None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
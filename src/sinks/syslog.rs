@@ -0,0 +1,263 @@
+use std::sync::Arc;
+
+use k8s_openapi::chrono::{DateTime, SecondsFormat, Utc};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::diagnostics;
+use crate::output::LogLine;
+use crate::util::{self, Level};
+
+/// The facility messages are sent with, `user`.
+const FACILITY: u8 = 1;
+
+/// The private enterprise number reserved for documentation, used to name klog's
+/// structured data.
+const ENTERPRISE_NUMBER: u32 = 32473;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Tls,
+}
+
+/// Where messages are sent to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyslogAddress {
+    pub transport: Transport,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Parses an address like `udp://logs.example.com:514`, `tcp://...` or `tls://...`,
+/// where the port defaults to 514 for UDP and TCP and to 6514 for TLS.
+pub fn parse_address(address: &str) -> Result<SyslogAddress, String> {
+    let invalid = || {
+        format!(
+            "Invalid syslog address {}, expected e.g. udp://host:514, tcp://host:514 or tls://host:6514",
+            address
+        )
+    };
+    let (scheme, rest) = address.split_once("://").ok_or_else(invalid)?;
+    let (transport, default_port) = match scheme {
+        "udp" => (Transport::Udp, 514),
+        "tcp" => (Transport::Tcp, 514),
+        "tls" => (Transport::Tls, 6514),
+        _ => return Err(invalid()),
+    };
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+        None => (rest, default_port),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok(SyslogAddress {
+        transport,
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// The syslog severity of a line, from its level.
+pub fn severity(line: &str) -> u8 {
+    match util::detect_level(line) {
+        Some(Level::Fatal) => 2,
+        Some(Level::Error) => 3,
+        Some(Level::Warn) => 4,
+        Some(Level::Debug | Level::Trace) => 7,
+        Some(Level::Info) | None => 6,
+    }
+}
+
+/// Escapes a structured data parameter value as RFC5424 requires.
+fn escape_param(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// Keeps at most `max` characters of a header field, or `-` if it is empty.
+fn header_field(value: Option<&str>, max: usize) -> String {
+    match value {
+        Some(value) if !value.is_empty() => value
+            .chars()
+            .filter(|c| c.is_ascii_graphic())
+            .take(max)
+            .collect(),
+        _ => "-".to_string(),
+    }
+}
+
+/// Renders a line as an RFC5424 message, with the pod as APP-NAME, the container as
+/// PROCID and the line's origin as structured data.
+pub fn format_message(line: &LogLine) -> String {
+    let timestamp = line
+        .timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or_else(Utc::now, |timestamp| timestamp.with_timezone(&Utc));
+    let mut data = format!("[klog@{}", ENTERPRISE_NUMBER);
+    for (key, value) in [
+        ("context", line.context.as_ref()),
+        ("namespace", Some(&line.namespace)),
+        ("pod", Some(&line.pod)),
+        ("container", line.container.as_ref()),
+    ] {
+        if let Some(value) = value {
+            data.push_str(&format!(" {}=\"{}\"", key, escape_param(value)));
+        }
+    }
+    data.push(']');
+    format!(
+        "<{}>1 {} {} {} {} - {} {}",
+        FACILITY * 8 + severity(&line.line),
+        timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+        header_field(line.context.as_deref(), 255),
+        header_field(Some(&line.pod), 48),
+        header_field(line.container.as_deref(), 128),
+        data,
+        line.line
+    )
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Stream(Box<dyn AsyncWrite + Unpin + Send>),
+}
+
+async fn connect(address: &SyslogAddress) -> anyhow::Result<Connection> {
+    let target = (address.host.as_str(), address.port);
+    Ok(match address.transport {
+        Transport::Udp => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(target).await?;
+            Connection::Udp(socket)
+        }
+        Transport::Tcp => Connection::Stream(Box::new(TcpStream::connect(target).await?)),
+        Transport::Tls => {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs()?);
+            let config = ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+            let server_name = ServerName::try_from(address.host.clone())?;
+            let stream = TcpStream::connect(target).await?;
+            let stream = TlsConnector::from(Arc::new(config))
+                .connect(server_name, stream)
+                .await?;
+            Connection::Stream(Box::new(stream))
+        }
+    })
+}
+
+impl Connection {
+    /// Sends a message, framed by octet counting on streams as RFC6587 describes.
+    async fn send(&mut self, message: &str) -> std::io::Result<()> {
+        match self {
+            Connection::Udp(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+            Connection::Stream(stream) => {
+                let framed = format!("{} {}", message.len(), message);
+                stream.write_all(framed.as_bytes()).await?;
+                stream.flush().await
+            }
+        }
+    }
+}
+
+/// Sends lines as syslog messages until all senders are dropped. After errors, the
+/// connection is opened again for the next line, and lines that could not be sent are
+/// reported and dropped.
+pub async fn run(address: SyslogAddress, mut lines: mpsc::Receiver<LogLine>) -> anyhow::Result<()> {
+    let mut connection = None;
+    while let Some(line) = lines.recv().await {
+        let sent = async {
+            if connection.is_none() {
+                connection = Some(connect(&address).await?);
+            }
+            let result = connection
+                .as_mut()
+                .unwrap()
+                .send(&format_message(&line))
+                .await;
+            if result.is_err() {
+                connection = None;
+            }
+            Ok::<(), anyhow::Error>(result?)
+        };
+        if let Err(e) = sent.await {
+            diagnostics::error(format_args!("Could not send logs to syslog: {}", e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("udp://logs.example.com").unwrap(),
+            SyslogAddress {
+                transport: Transport::Udp,
+                host: "logs.example.com".to_string(),
+                port: 514,
+            }
+        );
+        assert_eq!(parse_address("tls://logs:6000").unwrap().port, 6000);
+        assert_eq!(parse_address("tls://logs").unwrap().port, 6514);
+        assert!(parse_address("logs:514").is_err());
+        assert!(parse_address("http://logs:514").is_err());
+        assert!(parse_address("tcp://logs:port").is_err());
+    }
+
+    #[test]
+    fn test_format_message() {
+        let line = LogLine {
+            container: Some("app".to_string()),
+            timestamp: Some("2024-01-01T12:00:00Z".to_string()),
+            ..test_util::log_line("web-1", r#"level=error msg="quote \"]\"""#)
+        };
+
+        assert_eq!(
+            format_message(&line),
+            r#"<11>1 2024-01-01T12:00:00.000000Z - web-1 app - [klog@32473 namespace="shop" pod="web-1" container="app"] level=error msg="quote \"]\"""#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = SyslogAddress {
+            transport: Transport::Udp,
+            host: "127.0.0.1".to_string(),
+            port: server.local_addr().unwrap().port(),
+        };
+        let (tx, rx) = mpsc::channel(100);
+        tx.try_send(test_util::log_line("web-1", "started"))
+            .unwrap();
+        drop(tx);
+
+        run(address, rx).await.unwrap();
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).await.unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]);
+        assert!(message.starts_with("<14>1 "));
+        assert!(
+            message.ends_with(r#" - web-1 - - [klog@32473 namespace="shop" pod="web-1"] started"#)
+        );
+    }
+}
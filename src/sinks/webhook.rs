@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use k8s_openapi::serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::diagnostics;
+use crate::output::{self, LogLine};
+use crate::util;
+
+/// The longest wait before retrying a request.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where and how batches of lines are posted.
+#[derive(Clone, Debug)]
+pub struct WebhookOptions {
+    pub url: String,
+    /// Headers sent with each request, e.g. for authentication.
+    pub headers: Vec<(String, String)>,
+    /// How many lines are posted with one request at most.
+    pub batch_size: usize,
+    /// How long lines are collected at most before they are posted.
+    pub flush_interval: Duration,
+    /// How often a failed request is retried before its batch is dropped.
+    pub retries: u32,
+}
+
+/// Renders a batch as a JSON array of events like those of `--output json`.
+fn body(lines: &[LogLine]) -> String {
+    Value::Array(
+        lines
+            .iter()
+            .map(|line| output::to_json(line, 1, 1))
+            .collect(),
+    )
+    .to_string()
+}
+
+async fn post(
+    client: &reqwest::Client,
+    options: &WebhookOptions,
+    body: &str,
+) -> anyhow::Result<()> {
+    let mut request = client
+        .post(&options.url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string());
+    for (name, value) in &options.headers {
+        request = request.header(name, value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Posts lines in batches until all senders are dropped. Failed requests are retried
+/// with a backoff, and their batch is reported and dropped once all retries failed.
+pub async fn run(
+    options: WebhookOptions,
+    mut lines: mpsc::Receiver<LogLine>,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    loop {
+        let batch = super::next_batch(&mut lines, options.batch_size, options.flush_interval).await;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let body = body(&batch);
+        let mut attempt = 0;
+        while let Err(e) = post(&client, &options, &body).await {
+            if attempt == options.retries {
+                diagnostics::error(format_args!(
+                    "Could not post {} lines to webhook, dropping them: {}",
+                    batch.len(),
+                    e
+                ));
+                break;
+            }
+            attempt += 1;
+            tokio::time::sleep(util::backoff(attempt, MAX_BACKOFF)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use k8s_openapi::serde_json;
+
+    #[test]
+    fn test_body() {
+        let line = LogLine {
+            container: Some("app".to_string()),
+            ..test_util::log_line("web-1", r#"{"level":"warn","msg":"slow"}"#)
+        };
+
+        let events: Value = serde_json::from_str(&body(&[line.clone(), line])).unwrap();
+
+        let events = events.as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["pod"], "web-1");
+        assert_eq!(events[0]["container"], "app");
+        assert_eq!(events[0]["parsed"]["msg"], "slow");
+    }
+}
@@ -0,0 +1,131 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::stream::{self, StreamExt};
+use http::{Request, Response, StatusCode};
+use http_body::Frame;
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+use k8s_openapi::serde_json::{self, json, Value};
+use kube::client::Body;
+use kube::Client;
+
+use crate::output::LogLine;
+
+pub type TestBody = UnsyncBoxBody<Bytes, Infallible>;
+
+/// Requests received by a fake API server, as `path?query` strings.
+#[derive(Clone, Default)]
+pub struct Requests(Arc<Mutex<Vec<String>>>);
+
+impl Requests {
+    pub fn all(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn count(&self, prefix: &str) -> usize {
+        self.all().iter().filter(|r| r.starts_with(prefix)).count()
+    }
+
+    pub async fn wait_for(&self, prefix: &str) {
+        while self.count(prefix) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// Creates a client backed by `handler` instead of a real API server.
+pub fn fake_client<F>(handler: F) -> (Client, Requests)
+where
+    F: Fn(&Request<Body>) -> Response<TestBody> + Send + Sync + 'static,
+{
+    let requests = Requests::default();
+    let recorded = requests.clone();
+    let handler = Arc::new(handler);
+    let service = tower::service_fn(move |req: Request<Body>| {
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        recorded.0.lock().unwrap().push(path);
+        let response = handler(&req);
+        async move { Ok::<_, Infallible>(response) }
+    });
+    (Client::new(service, "default"), requests)
+}
+
+pub fn json_response(value: Value) -> Response<TestBody> {
+    text_response(&serde_json::to_string(&value).unwrap())
+}
+
+pub fn text_response(body: &str) -> Response<TestBody> {
+    Response::new(Full::new(Bytes::from(body.to_string())).boxed_unsync())
+}
+
+/// A response whose body is `body` followed by a connection that never closes,
+/// like a followed log stream.
+pub fn open_response(body: &str) -> Response<TestBody> {
+    let first = stream::iter([Ok(Frame::data(Bytes::from(body.to_string())))]);
+    let body = StreamBody::new(first.chain(stream::pending()));
+    Response::new(body.boxed_unsync())
+}
+
+fn failure(status: StatusCode, reason: &str) -> Response<TestBody> {
+    let mut response = json_response(json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "status": "Failure",
+        "message": status.canonical_reason().unwrap_or_default().to_lowercase(),
+        "reason": reason,
+        "code": status.as_u16()
+    }));
+    *response.status_mut() = status;
+    response
+}
+
+pub fn not_found() -> Response<TestBody> {
+    failure(StatusCode::NOT_FOUND, "NotFound")
+}
+
+/// The response of an API server that is overloaded or restarting.
+pub fn unavailable() -> Response<TestBody> {
+    failure(StatusCode::SERVICE_UNAVAILABLE, "ServiceUnavailable")
+}
+
+pub fn pod(name: &str, containers: &[&str]) -> Value {
+    let containers: Vec<Value> = containers
+        .iter()
+        .map(|name| json!({ "name": name, "image": "busybox" }))
+        .collect();
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": name, "namespace": "default" },
+        "spec": { "containers": containers }
+    })
+}
+
+pub fn list(kind: &str, items: Vec<Value>) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": kind,
+        "metadata": { "resourceVersion": "1" },
+        "items": items
+    })
+}
+
+/// A line of pod `pod` in namespace `shop`, to build variants of with `..log_line(..)`.
+pub fn log_line(pod: &str, line: &str) -> LogLine {
+    LogLine {
+        context: None,
+        namespace: "shop".to_string(),
+        pod: pod.to_string(),
+        container: None,
+        resource: None,
+        timestamp: None,
+        line: line.to_string(),
+        restart: false,
+    }
+}
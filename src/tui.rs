@@ -0,0 +1,1038 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::StreamExt;
+use k8s_openapi::chrono::{TimeDelta, Utc};
+use klog::diagnostics;
+use klog::k8s::PodStatus;
+use klog::metrics::{StreamState, METRICS};
+use klog::output::{self, LogLine, RenderOptions};
+use klog::util;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::Frame;
+use regex::{Regex, RegexBuilder};
+use tokio::sync::mpsc;
+
+/// How many lines the TUI keeps.
+const HISTORY: usize = 10_000;
+/// How often the TUI is redrawn.
+const TICK: Duration = Duration::from_millis(100);
+/// How often the lines per second of each pod are measured.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+/// The width of the pod list.
+const PODS_WIDTH: u16 = 36;
+/// How matches of the search are highlighted, and the current one.
+const MATCH: Style = Style::new().fg(Color::Black).bg(Color::Yellow);
+const CURRENT_MATCH: Style = Style::new().fg(Color::Black).bg(Color::LightRed);
+/// How the markers of restarted containers are shown.
+const RESTART: Style = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+
+/// Where the log view stopped following new lines, by pausing or scrolling back.
+struct Paused {
+    /// The number of the last line shown, counting all lines received.
+    bottom: u64,
+    /// How many lines were received when the view was paused.
+    received: u64,
+}
+
+/// A search through the kept lines, started with `/`. Matches are looked for from the newest
+/// line backwards, since that is where the lines of interest usually are.
+struct Search {
+    query: String,
+    /// Matches the query regardless of case, or is `None` while it is empty.
+    regex: Option<Regex>,
+    /// Whether the query is still being typed.
+    editing: bool,
+    /// The number of the line shown last when the search started, where matches are looked
+    /// for from while typing.
+    origin: u64,
+    /// The number of the line of the current match.
+    current: Option<u64>,
+}
+
+/// The filters that can be edited in the TUI.
+#[derive(Clone, Copy, PartialEq)]
+enum FilterKind {
+    /// The regex lines must match, which `--filter` texts are turned into.
+    Include,
+    Exclude,
+}
+
+/// A filter being typed, which replaces the current one once confirmed.
+struct FilterEdit {
+    kind: FilterKind,
+    text: String,
+}
+
+/// What the TUI shows, apart from the terminal it is drawn on.
+pub struct App {
+    options: RenderOptions,
+    /// The most recent lines, oldest first.
+    lines: VecDeque<LogLine>,
+    /// Whether each kept line passed the filters when it arrived, or when they were last
+    /// applied to all kept lines.
+    passed: VecDeque<bool>,
+    filter_edit: Option<FilterEdit>,
+    paused: Option<Paused>,
+    /// How many lines the log view showed when it was last drawn, to scroll by pages.
+    view_height: Cell<usize>,
+    search: Option<Search>,
+    /// The namespaces and names of the pods whose lines are hidden.
+    muted: Vec<(String, String)>,
+    /// The pod whose lines are the only ones shown, if any.
+    solo: Option<(String, String)>,
+    /// Whether keys go to the pod list, to pick pods to mute or solo.
+    pods_focused: bool,
+    /// The index of the selected entry of the pod list.
+    selected: usize,
+    /// The streamed containers, keyed by namespace, pod and container, with the state of
+    /// their streams.
+    streams: Vec<((String, String, String), StreamState)>,
+    /// The status of the pods, keyed by namespace and pod, see `Metrics::pod_statuses`.
+    pods: BTreeMap<(String, String), PodStatus>,
+    /// The lines received from each pod since the rates were last measured.
+    counts: HashMap<(String, String), u64>,
+    /// The lines per second of each pod, see `sample_rates`.
+    rates: HashMap<(String, String), f64>,
+    received: u64,
+    /// The last notice of klog, shown in the status bar.
+    notice: Option<String>,
+    /// Whether all streams ended, so that no more lines arrive.
+    ended: bool,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(mut options: RenderOptions) -> Self {
+        options.fold_case();
+        App {
+            options,
+            lines: VecDeque::new(),
+            passed: VecDeque::new(),
+            filter_edit: None,
+            paused: None,
+            view_height: Cell::new(1),
+            search: None,
+            muted: vec![],
+            solo: None,
+            pods_focused: false,
+            selected: 0,
+            streams: vec![],
+            pods: BTreeMap::new(),
+            counts: HashMap::new(),
+            rates: HashMap::new(),
+            received: 0,
+            notice: None,
+            ended: false,
+            quit: false,
+        }
+    }
+
+    pub fn push(&mut self, line: LogLine) {
+        let Some(line) = self.options.hooks.apply_line(line) else {
+            return;
+        };
+        if self.lines.len() == HISTORY {
+            self.lines.pop_front();
+            self.passed.pop_front();
+        }
+        self.passed
+            .push_back(self.options.matches_filters(&line.line));
+        *self
+            .counts
+            .entry((line.namespace.clone(), line.pod.clone()))
+            .or_default() += 1;
+        self.lines.push_back(line);
+        self.received += 1;
+    }
+
+    /// Updates the status of the pods, see `Metrics::pod_statuses`.
+    pub fn refresh_pods(&mut self, pods: BTreeMap<(String, String), PodStatus>) {
+        self.pods = pods;
+    }
+
+    /// Turns the lines received since the last call, `elapsed` ago, into lines per second.
+    pub fn sample_rates(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        self.rates = self
+            .counts
+            .drain()
+            .map(|(pod, count)| (pod, count as f64 / seconds))
+            .collect();
+    }
+
+    /// Updates the pod list from the state of the streams, see `Metrics::stream_states`.
+    pub fn refresh(&mut self, streams: Vec<((String, String, String), StreamState)>) {
+        self.streams = streams;
+        self.selected = self.selected.min(self.streams.len().saturating_sub(1));
+    }
+
+    /// Whether the lines of a pod are shown, as they are unless it is muted or another pod
+    /// is soloed.
+    fn is_pod_shown(&self, namespace: &str, pod: &str) -> bool {
+        match &self.solo {
+            Some(solo) => solo.0 == namespace && solo.1 == pod,
+            None => !self.muted.iter().any(|m| m.0 == namespace && m.1 == pod),
+        }
+    }
+
+    /// Whether the kept line at `index` is shown, having passed the filters.
+    fn is_shown(&self, index: usize) -> bool {
+        let line = &self.lines[index];
+        self.passed[index] && self.is_pod_shown(&line.namespace, &line.pod)
+    }
+
+    /// The number of the shown line `count` shown lines before line `from`, or after it,
+    /// stopping at the oldest or newest one.
+    fn step(&self, from: u64, count: usize, backwards: bool) -> u64 {
+        let first = self.first_number();
+        let from = (from.saturating_sub(first) as usize).min(self.lines.len().saturating_sub(1));
+        let shown = |index: &usize| self.is_shown(*index);
+        let index = match backwards {
+            true => (0..from).rev().filter(shown).take(count).last(),
+            false => (from + 1..self.lines.len())
+                .filter(shown)
+                .take(count)
+                .last(),
+        };
+        first + index.unwrap_or(from) as u64
+    }
+
+    /// The number of the oldest line still kept.
+    fn first_number(&self) -> u64 {
+        self.received - self.lines.len() as u64
+    }
+
+    /// The number of the last line shown, which is the newest one unless paused.
+    fn bottom(&self) -> u64 {
+        let newest = self.received.saturating_sub(1);
+        match &self.paused {
+            // Lines scrolled back to may have been dropped from the history since.
+            Some(paused) => paused.bottom.max(self.first_number()).min(newest),
+            None => newest,
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.paused.is_none() {
+            self.paused = Some(Paused {
+                bottom: self.bottom(),
+                received: self.received,
+            });
+        }
+    }
+
+    /// Scrolls back by `lines`, pausing the view. The view stays filled with lines as long
+    /// as there are enough.
+    fn scroll_up(&mut self, lines: usize) {
+        self.pause();
+        let filled = (0..self.lines.len())
+            .filter(|index| self.is_shown(*index))
+            .take(self.view_height.get())
+            .last()
+            .map_or(0, |index| self.first_number() + index as u64);
+        let bottom = self.step(self.bottom(), lines, true).max(filled);
+        if let Some(paused) = &mut self.paused {
+            paused.bottom = bottom;
+        }
+    }
+
+    /// Scrolls towards the newest line while paused.
+    fn scroll_down(&mut self, lines: usize) {
+        let bottom = self.step(self.bottom(), lines, false);
+        if let Some(paused) = &mut self.paused {
+            paused.bottom = bottom;
+        }
+    }
+
+    /// The lines received since the view was paused.
+    fn new_lines(&self) -> Option<u64> {
+        self.paused
+            .as_ref()
+            .map(|paused| self.received - paused.received)
+    }
+
+    /// Finds the number of the first line matching the search from line `from` on, going
+    /// backwards or forwards.
+    fn find(&self, from: u64, backwards: bool) -> Option<u64> {
+        let regex = self.search.as_ref()?.regex.as_ref()?;
+        let first = self.first_number();
+        let is_match = |index: &usize| {
+            self.is_shown(*index) && regex.is_match(&util::strip_ansi(&self.lines[*index].line))
+        };
+        let index = match backwards {
+            true => (0..self
+                .lines
+                .len()
+                .min((from + 1).saturating_sub(first) as usize))
+                .rev()
+                .find(is_match),
+            false => (from.saturating_sub(first) as usize..self.lines.len()).find(is_match),
+        }?;
+        Some(first + index as u64)
+    }
+
+    /// Pauses the view with the matching line `number` at its bottom.
+    fn jump_to(&mut self, number: u64) {
+        self.pause();
+        if let Some(paused) = &mut self.paused {
+            paused.bottom = number;
+        }
+        if let Some(search) = &mut self.search {
+            search.current = Some(number);
+        }
+    }
+
+    fn start_search(&mut self) {
+        self.search = Some(Search {
+            query: String::new(),
+            regex: None,
+            editing: true,
+            origin: self.bottom(),
+            current: None,
+        });
+    }
+
+    /// Edits the query while it is typed, jumping to the closest match as it changes.
+    fn edit_search(&mut self, key: KeyEvent) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        match key.code {
+            KeyCode::Char(c) => search.query.push(c),
+            KeyCode::Backspace => {
+                search.query.pop();
+            }
+            KeyCode::Enter if !search.query.is_empty() => {
+                search.editing = false;
+                return;
+            }
+            KeyCode::Enter | KeyCode::Esc => {
+                self.search = None;
+                return;
+            }
+            _ => return,
+        }
+        search.regex = RegexBuilder::new(&regex::escape(&search.query))
+            .case_insensitive(true)
+            .build()
+            .ok()
+            .filter(|_| !search.query.is_empty());
+        search.current = None;
+        let origin = search.origin;
+        if let Some(number) = self.find(origin, true) {
+            self.jump_to(number);
+        }
+    }
+
+    /// Jumps to the match before the current one, or after it.
+    fn next_match(&mut self, backwards: bool) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let from = match search.current {
+            Some(current) if backwards => current.checked_sub(1),
+            Some(current) => Some(current + 1),
+            None => Some(self.bottom()),
+        };
+        if let Some(number) = from.and_then(|from| self.find(from, backwards)) {
+            self.jump_to(number);
+        }
+    }
+
+    /// How many of the kept lines match the search.
+    fn match_count(&self) -> usize {
+        match self
+            .search
+            .as_ref()
+            .and_then(|search| search.regex.as_ref())
+        {
+            Some(regex) => (0..self.lines.len())
+                .filter(|index| {
+                    self.is_shown(*index)
+                        && regex.is_match(&util::strip_ansi(&self.lines[*index].line))
+                })
+                .count(),
+            None => 0,
+        }
+    }
+
+    fn start_filter_edit(&mut self, kind: FilterKind) {
+        let current = match kind {
+            FilterKind::Include => output::highlight_regex(&self.options),
+            FilterKind::Exclude => self.options.exclude.clone(),
+        };
+        self.filter_edit = Some(FilterEdit {
+            kind,
+            text: current.map_or(String::new(), |regex| regex.as_str().to_string()),
+        });
+    }
+
+    fn edit_filter(&mut self, key: KeyEvent) {
+        let Some(edit) = &mut self.filter_edit else {
+            return;
+        };
+        match key.code {
+            KeyCode::Char(c) => edit.text.push(c),
+            KeyCode::Backspace => {
+                edit.text.pop();
+            }
+            KeyCode::Enter => self.apply_filter(),
+            KeyCode::Esc => self.filter_edit = None,
+            _ => {}
+        }
+    }
+
+    /// Replaces the filter being edited for the lines arriving from now on. An empty filter
+    /// is removed, while an invalid one is left to be corrected.
+    fn apply_filter(&mut self) {
+        let Some(edit) = self.filter_edit.take() else {
+            return;
+        };
+        let regex = match edit.text.is_empty() {
+            true => None,
+            false => match RegexBuilder::new(&edit.text)
+                .case_insensitive(self.options.ignore_case)
+                .build()
+            {
+                Ok(regex) => Some(regex),
+                Err(_) => {
+                    self.notice = Some(format!("Invalid regex {}", edit.text));
+                    self.filter_edit = Some(edit);
+                    return;
+                }
+            },
+        };
+        match edit.kind {
+            FilterKind::Include => {
+                self.options.filter.clear();
+                self.options.filter_regex = regex;
+            }
+            FilterKind::Exclude => self.options.exclude = regex,
+        }
+    }
+
+    /// Applies the filters to the kept lines too, not only to those arriving since they
+    /// were changed.
+    fn refilter(&mut self) {
+        for (passed, line) in self.passed.iter_mut().zip(&self.lines) {
+            *passed = self.options.matches_filters(&line.line);
+        }
+    }
+
+    /// The namespace and name of the pod selected in the pod list.
+    fn selected_pod(&self) -> Option<(String, String)> {
+        let ((namespace, pod, _), _) = self.streams.get(self.selected)?;
+        Some((namespace.clone(), pod.clone()))
+    }
+
+    fn toggle_mute(&mut self) {
+        if let Some(pod) = self.selected_pod() {
+            match self.muted.iter().position(|muted| *muted == pod) {
+                Some(index) => {
+                    self.muted.remove(index);
+                }
+                None => self.muted.push(pod),
+            }
+        }
+    }
+
+    /// Shows only the lines of the selected pod, or all again if it is soloed already.
+    fn toggle_solo(&mut self) {
+        let pod = self.selected_pod();
+        self.solo = match self.solo == pod {
+            true => None,
+            false => pod,
+        };
+    }
+
+    /// Handles the keys of the pod list while it is focused, returning whether the key was
+    /// one of them.
+    fn handle_pods_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.streams.len().saturating_sub(1))
+            }
+            KeyCode::Char('m') | KeyCode::Char(' ') => self.toggle_mute(),
+            KeyCode::Char('s') => self.toggle_solo(),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+        if self.search.as_ref().is_some_and(|search| search.editing) {
+            return self.edit_search(key);
+        }
+        if self.filter_edit.is_some() {
+            return self.edit_filter(key);
+        }
+        if self.pods_focused && self.handle_pods_key(key) {
+            return;
+        }
+        let page = self.view_height.get().max(1);
+        match key.code {
+            KeyCode::Tab => self.pods_focused = !self.pods_focused,
+            KeyCode::Esc if self.search.is_some() => self.search = None,
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('/') => self.start_search(),
+            KeyCode::Char('n') => self.next_match(true),
+            KeyCode::Char('N') => self.next_match(false),
+            KeyCode::Char('f') => self.start_filter_edit(FilterKind::Include),
+            KeyCode::Char('x') => self.start_filter_edit(FilterKind::Exclude),
+            KeyCode::Char('r') => self.refilter(),
+            KeyCode::Char('p') | KeyCode::Char(' ') => match self.paused {
+                Some(_) => self.paused = None,
+                None => self.pause(),
+            },
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_up(1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_down(1),
+            KeyCode::PageUp => self.scroll_up(page),
+            KeyCode::PageDown => self.scroll_down(page),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll_up(self.lines.len()),
+            KeyCode::End | KeyCode::Char('G') => self.paused = None,
+            _ => {}
+        }
+    }
+
+    fn pod_style(&self, line: &LogLine) -> Style {
+        let color = self.options.palette.color_for_pod(
+            &line.pod,
+            line.resource.as_deref(),
+            &self.options.color_salt,
+        );
+        Style::default().fg(Color::Rgb(color.r, color.g, color.b))
+    }
+
+    /// Renders the line with the given number, highlighting matches of the search.
+    fn render_line<'l>(&self, line: &'l LogLine, number: u64) -> Line<'l> {
+        let prefix = match &line.container {
+            Some(container) => format!("{}[{}] ", line.pod, container),
+            None => format!("{} ", line.pod),
+        };
+        let mut spans = vec![Span::styled(prefix, self.pod_style(line))];
+        let text = util::strip_ansi(&line.line);
+        let base = match line.restart {
+            true => RESTART,
+            false => Style::default(),
+        };
+        let search = self.search.as_ref();
+        match search.and_then(|search| search.regex.as_ref()) {
+            Some(regex) => {
+                let style = match search.and_then(|search| search.current) == Some(number) {
+                    true => CURRENT_MATCH,
+                    false => MATCH,
+                };
+                let mut last = 0;
+                for found in regex.find_iter(&text) {
+                    spans.push(Span::styled(text[last..found.start()].to_string(), base));
+                    spans.push(Span::styled(found.as_str().to_string(), style));
+                    last = found.end();
+                }
+                spans.push(Span::styled(text[last..].to_string(), base));
+            }
+            None => spans.push(Span::styled(text, base)),
+        }
+        Line::from(spans)
+    }
+}
+
+fn state_style(state: StreamState) -> (&'static str, Style) {
+    match state {
+        StreamState::Streaming => ("streaming", Style::default().fg(Color::Green)),
+        StreamState::Waiting => ("waiting", Style::default().fg(Color::Yellow)),
+        StreamState::Reconnecting => ("reconnecting", Style::default().fg(Color::Red)),
+        StreamState::Ended => ("ended", Style::default().fg(Color::DarkGray)),
+    }
+}
+
+/// Formats the age of a pod like kubectl, e.g. `45s`, `12m` or `3d`.
+fn format_age(age: TimeDelta) -> String {
+    let seconds = age.num_seconds().max(0);
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// The status of a pod below its streams in the pod list, like `Running ↻2 3h 1.5/s`.
+fn pod_status_line(app: &App, namespace: &str, pod: &str) -> Line<'static> {
+    let key = (namespace.to_string(), pod.to_string());
+    let mut spans = vec![Span::raw("  ")];
+    if let Some(status) = app.pods.get(&key) {
+        let phase_style = match status.phase.as_str() {
+            "Running" | "Succeeded" => Style::default().fg(Color::Green),
+            "Pending" | "Terminating" => Style::default().fg(Color::Yellow),
+            _ => Style::default().fg(Color::Red),
+        };
+        let restarts_style = match status.restarts {
+            0 => Style::default(),
+            _ => Style::default().fg(Color::Red),
+        };
+        spans.push(Span::styled(status.phase.clone(), phase_style));
+        spans.push(Span::styled(
+            format!(" ↻{}", status.restarts),
+            restarts_style,
+        ));
+        if let Some(created) = status.created {
+            spans.push(Span::raw(format!(" {}", format_age(Utc::now() - created))));
+        }
+        spans.push(Span::raw(" "));
+    }
+    let rate = app.rates.get(&key).copied().unwrap_or_default();
+    spans.push(Span::styled(
+        format!("{:.1}/s", rate),
+        Style::default().fg(Color::DarkGray),
+    ));
+    Line::from(spans)
+}
+
+fn draw_pods(frame: &mut Frame, app: &App, area: Rect) {
+    let mut items = vec![];
+    for (index, ((namespace, pod, container), state)) in app.streams.iter().enumerate() {
+        let (label, style) = state_style(*state);
+        let mut name_style = match app.is_pod_shown(namespace, pod) {
+            true => Style::default(),
+            false => Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::CROSSED_OUT),
+        };
+        if app.pods_focused && index == app.selected {
+            name_style = name_style.add_modifier(Modifier::REVERSED);
+        }
+        items.push(ListItem::new(Line::from(vec![
+            Span::styled("● ", style),
+            Span::styled(format!("{} [{}]", pod, container), name_style),
+            Span::raw(" "),
+            Span::styled(label, style),
+        ])));
+        // The streams are sorted, so the containers of a pod are listed together.
+        let last_of_pod =
+            app.streams
+                .get(index + 1)
+                .is_none_or(|((next_namespace, next_pod, _), _)| {
+                    next_namespace != namespace || next_pod != pod
+                });
+        if last_of_pod {
+            items.push(ListItem::new(pod_status_line(app, namespace, pod)));
+        }
+    }
+    let title = match app.solo {
+        Some(_) => format!(" Pods ({}, solo) ", app.streams.len()),
+        None => format!(" Pods ({}) ", app.streams.len()),
+    };
+    frame.render_widget(
+        List::new(items).block(Block::default().borders(Borders::ALL).title(title)),
+        area,
+    );
+}
+
+fn draw_logs(frame: &mut Frame, app: &App, area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+    app.view_height.set(height);
+    // The index of the line after the last one shown.
+    let end = match app.lines.is_empty() {
+        true => 0,
+        false => (app.bottom() - app.first_number()) as usize + 1,
+    };
+    let mut lines: Vec<Line> = app
+        .lines
+        .range(..end)
+        .enumerate()
+        .rev()
+        .filter(|(index, _)| app.is_shown(*index))
+        .take(height)
+        .map(|(index, line)| app.render_line(line, app.first_number() + index as u64))
+        .collect();
+    lines.reverse();
+    let title = match app.paused {
+        Some(_) => " Logs (paused) ",
+        None => " Logs ",
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+        area,
+    );
+}
+
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let streaming = app
+        .streams
+        .iter()
+        .filter(|(_, state)| *state == StreamState::Streaming)
+        .count();
+    let mut status = format!(
+        " {} lines | {}/{} streaming{}",
+        app.received,
+        streaming,
+        app.streams.len(),
+        if app.ended {
+            " | all streams ended"
+        } else {
+            ""
+        }
+    );
+    match app.new_lines() {
+        Some(new_lines) => {
+            status.push_str(&format!(" | PAUSED, {} new lines | G resume", new_lines))
+        }
+        None => status.push_str(" | p pause | / search | q quit"),
+    }
+    match &app.search {
+        Some(search) if search.editing => status.push_str(&format!(" | /{}", search.query)),
+        Some(search) => status.push_str(&format!(
+            " | /{}: {} matches | n/N older/newer",
+            search.query,
+            app.match_count()
+        )),
+        None => {}
+    }
+    match &app.filter_edit {
+        Some(FilterEdit {
+            kind: FilterKind::Include,
+            text,
+        }) => status.push_str(&format!(" | filter: {}", text)),
+        Some(FilterEdit {
+            kind: FilterKind::Exclude,
+            text,
+        }) => status.push_str(&format!(" | exclude: {}", text)),
+        None => {}
+    }
+    match app.pods_focused {
+        true => status.push_str(" | m mute | s solo | Tab logs"),
+        false => status.push_str(" | Tab pods"),
+    }
+    if let Some(notice) = &app.notice {
+        status.push_str(" | ");
+        status.push_str(notice);
+    }
+    let style = Style::default().add_modifier(Modifier::REVERSED);
+    frame.render_widget(Paragraph::new(status).style(style), area);
+}
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [main, status] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+    let [pods, logs] =
+        Layout::horizontal([Constraint::Length(PODS_WIDTH), Constraint::Min(1)]).areas(main);
+    draw_pods(frame, app, pods);
+    draw_logs(frame, app, logs);
+    draw_status(frame, app, status);
+}
+
+/// Shows the lines in a full-screen terminal UI until it is quit or `options.shutdown` is
+/// cancelled, cancelling it on quitting so that the streams end. klog's own messages are
+/// shown in the status bar meanwhile, since they would garble the screen.
+pub async fn run(mut lines: mpsc::Receiver<LogLine>, options: RenderOptions) -> anyhow::Result<()> {
+    let shutdown = options.shutdown.clone();
+    let mut notices = diagnostics::capture();
+    let mut terminal = ratatui::init();
+    let mut app = App::new(options);
+    let mut events = EventStream::new();
+    let mut ticks = tokio::time::interval(TICK);
+    let mut rate_ticks = tokio::time::interval(RATE_INTERVAL);
+    let mut sampled = Instant::now();
+    let result = loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break Ok(()),
+            line = lines.recv(), if !app.ended => match line {
+                Some(line) => app.push(line),
+                None => app.ended = true,
+            },
+            Some(notice) = notices.recv() => app.notice = Some(notice),
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => app.handle_key(key),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(e.into()),
+                None => break Ok(()),
+            },
+            _ = rate_ticks.tick() => {
+                app.sample_rates(sampled.elapsed());
+                sampled = Instant::now();
+            }
+            _ = ticks.tick() => {
+                app.refresh(METRICS.stream_states());
+                app.refresh_pods(METRICS.pod_statuses());
+                if let Err(e) = terminal.draw(|frame| draw(frame, &app)) {
+                    break Err(e.into());
+                }
+            }
+        }
+        if app.quit {
+            shutdown.cancel();
+            break Ok(());
+        }
+    };
+    ratatui::restore();
+    diagnostics::release();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn log_line(pod: &str, line: &str) -> LogLine {
+        LogLine {
+            context: None,
+            namespace: "shop".to_string(),
+            pod: pod.to_string(),
+            container: None,
+            resource: None,
+            timestamp: None,
+            line: line.to_string(),
+            restart: false,
+        }
+    }
+
+    /// Draws the app on a terminal of the given size and returns its text.
+    fn render(app: &App, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| draw(frame, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut app = App::new(RenderOptions::default());
+        for index in 0..HISTORY + 5 {
+            app.push(log_line("web-1", &index.to_string()));
+        }
+
+        assert_eq!(app.lines.len(), HISTORY);
+        assert_eq!(app.lines.front().unwrap().line, "5");
+        assert_eq!(app.received, HISTORY as u64 + 5);
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut app = App::new(RenderOptions::default());
+        app.refresh(vec![(
+            ("shop".to_string(), "web-1".to_string(), "app".to_string()),
+            StreamState::Streaming,
+        )]);
+        app.push(log_line("web-1", "\x1b[32mstarted\x1b[0m"));
+
+        let screen = render(&app, 100, 6);
+
+        assert!(screen.contains("web-1 [app] streaming"));
+        assert!(screen.contains("web-1 started"));
+        assert!(screen.contains(" 1 lines | 1/1 streaming | p pause | / search | q quit"));
+    }
+
+    #[test]
+    fn test_pod_status() {
+        let mut app = App::new(RenderOptions::default());
+        app.refresh(
+            ["web-1", "web-2"]
+                .iter()
+                .map(|pod| {
+                    let key = ("shop".to_string(), pod.to_string(), "app".to_string());
+                    (key, StreamState::Streaming)
+                })
+                .collect(),
+        );
+        app.refresh_pods(BTreeMap::from([(
+            ("shop".to_string(), "web-1".to_string()),
+            PodStatus {
+                phase: "CrashLoopBackOff".to_string(),
+                restarts: 4,
+                created: Some(Utc::now() - TimeDelta::hours(3)),
+            },
+        )]));
+        for _ in 0..3 {
+            app.push(log_line("web-1", "starting"));
+        }
+        app.sample_rates(Duration::from_secs(2));
+
+        let screen = render(&app, 100, 10);
+
+        assert!(screen.contains("│  CrashLoopBackOff ↻4 3h 1.5/s"));
+        assert!(screen.contains("│  0.0/s"));
+        assert_eq!(format_age(TimeDelta::seconds(45)), "45s");
+        assert_eq!(format_age(TimeDelta::days(2)), "2d");
+    }
+
+    #[test]
+    fn test_pause_and_scroll_back() {
+        let mut app = App::new(RenderOptions::default());
+        for index in 0..10 {
+            app.push(log_line("web-1", &format!("line {}", index)));
+        }
+        // The log view shows 3 lines.
+        render(&app, 80, 6);
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('p')));
+        app.push(log_line("web-1", "line 10"));
+        app.push(log_line("web-1", "line 11"));
+        let screen = render(&app, 80, 6);
+        assert!(screen.contains("line 9"));
+        assert!(!screen.contains("line 10"));
+        assert!(screen.contains("PAUSED, 2 new lines"));
+
+        app.handle_key(KeyEvent::from(KeyCode::PageUp));
+        let screen = render(&app, 80, 6);
+        assert!(screen.contains("line 4"));
+        assert!(screen.contains("line 6"));
+        assert!(!screen.contains("line 7"));
+
+        app.handle_key(KeyEvent::from(KeyCode::Home));
+        assert!(render(&app, 80, 6).contains("line 0"));
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('G')));
+        let screen = render(&app, 80, 6);
+        assert!(screen.contains("line 11"));
+        assert!(!screen.contains("PAUSED"));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut app = App::new(RenderOptions::default());
+        for index in 0..10 {
+            let id = if index % 3 == 0 { "abc" } else { "xyz" };
+            app.push(log_line("web-1", &format!("request {} id={}", index, id)));
+        }
+        render(&app, 80, 6);
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('/')));
+        for c in "ABC".chars() {
+            app.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+        assert_eq!(app.search.as_ref().unwrap().current, Some(9));
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+        assert!(render(&app, 80, 6).contains("/ABC: 4 matches"));
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('n')));
+        app.handle_key(KeyEvent::from(KeyCode::Char('n')));
+        assert_eq!(app.search.as_ref().unwrap().current, Some(3));
+        assert!(render(&app, 80, 6).contains("request 3 id=abc"));
+        app.handle_key(KeyEvent::from(KeyCode::Char('N')));
+        assert_eq!(app.search.as_ref().unwrap().current, Some(6));
+
+        let line = app.render_line(&app.lines[3], 3);
+        assert_eq!(line.spans[2].content, "abc");
+        assert_eq!(line.spans[2].style, MATCH);
+        let line = app.render_line(&app.lines[6], 6);
+        assert_eq!(line.spans[2].style, CURRENT_MATCH);
+
+        // Esc ends the search before it quits.
+        app.handle_key(KeyEvent::from(KeyCode::Esc));
+        assert!(app.search.is_none());
+        assert!(!app.quit);
+    }
+
+    #[test]
+    fn test_mute_and_solo() {
+        let mut app = App::new(RenderOptions::default());
+        app.refresh(
+            ["web-1", "web-2", "web-3"]
+                .iter()
+                .map(|pod| {
+                    let key = ("shop".to_string(), pod.to_string(), "app".to_string());
+                    (key, StreamState::Streaming)
+                })
+                .collect(),
+        );
+        for pod in ["web-1", "web-2", "web-3"] {
+            app.push(log_line(pod, &format!("from {}", pod)));
+        }
+
+        app.handle_key(KeyEvent::from(KeyCode::Tab));
+        app.handle_key(KeyEvent::from(KeyCode::Char('m')));
+        let screen = render(&app, 100, 8);
+        assert!(!screen.contains("from web-1"));
+        assert!(screen.contains("from web-2"));
+        assert!(screen.contains("m mute | s solo"));
+
+        app.handle_key(KeyEvent::from(KeyCode::Down));
+        app.handle_key(KeyEvent::from(KeyCode::Down));
+        app.handle_key(KeyEvent::from(KeyCode::Char('s')));
+        let screen = render(&app, 100, 8);
+        assert!(screen.contains(" Pods (3, solo) "));
+        assert!(screen.contains("from web-3"));
+        assert!(!screen.contains("from web-2"));
+
+        // Ending the solo shows all pods again but those muted.
+        app.handle_key(KeyEvent::from(KeyCode::Char('s')));
+        app.handle_key(KeyEvent::from(KeyCode::Tab));
+        let screen = render(&app, 100, 8);
+        assert!(!screen.contains("from web-1"));
+        assert!(screen.contains("from web-2"));
+        assert!(screen.contains("from web-3"));
+        assert!(screen.contains(" | Tab pods"));
+    }
+
+    /// Types `text` into the TUI, key by key.
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            app.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    #[test]
+    fn test_live_filters() {
+        let mut app = App::new(RenderOptions {
+            exclude: Some(Regex::new("healthz").unwrap()),
+            ..Default::default()
+        });
+        app.push(log_line("web-1", "GET /healthz"));
+        app.push(log_line("web-1", "POST /users"));
+        let screen = render(&app, 100, 8);
+        assert!(!screen.contains("GET /healthz"));
+        assert!(screen.contains("POST /users"));
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('f')));
+        type_text(&mut app, "ORDERS(");
+        assert!(render(&app, 100, 8).contains(" | filter: ORDERS("));
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+        assert!(app.notice.as_ref().unwrap().starts_with("Invalid regex"));
+        app.handle_key(KeyEvent::from(KeyCode::Backspace));
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+        assert!(app.filter_edit.is_none());
+
+        // The filter applies to new lines, and to the kept ones on request.
+        app.push(log_line("web-1", "GET /ORDERS/7"));
+        app.push(log_line("web-1", "GET /users/7"));
+        let screen = render(&app, 100, 8);
+        assert!(screen.contains("GET /ORDERS/7"));
+        assert!(!screen.contains("GET /users/7"));
+        assert!(screen.contains("POST /users"));
+        app.handle_key(KeyEvent::from(KeyCode::Char('r')));
+        assert!(!render(&app, 100, 8).contains("POST /users"));
+
+        // Editing the exclude filter starts from the current one.
+        app.handle_key(KeyEvent::from(KeyCode::Char('x')));
+        assert_eq!(app.filter_edit.as_ref().unwrap().text, "healthz");
+        app.handle_key(KeyEvent::from(KeyCode::Esc));
+        assert!(app.options.exclude.is_some());
+    }
+
+    #[test]
+    fn test_quit() {
+        let mut app = App::new(RenderOptions::default());
+        app.handle_key(KeyEvent::from(KeyCode::Char('x')));
+        assert!(!app.quit);
+        app.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        assert!(app.quit);
+    }
+}
@@ -0,0 +1,169 @@
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectStore, RetryConfig, WriteMultipart};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+use crate::diagnostics;
+use crate::files::Segment;
+
+/// How many rotated files can wait for their upload before further ones are dropped.
+pub const PENDING_SEGMENTS: usize = 16;
+
+/// How many parts of a file are uploaded at once, which bounds the memory used to
+/// `part_size` times this.
+const CONCURRENT_PARTS: usize = 2;
+
+/// A bucket and key prefix to upload segments to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadTarget {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+/// Where and how rotated files are uploaded.
+#[derive(Clone, Debug)]
+pub struct UploadOptions {
+    pub target: UploadTarget,
+    /// Files larger than this are uploaded in parts of this size.
+    pub part_size: usize,
+    /// How often a failed request is retried.
+    pub retries: usize,
+}
+
+/// Parses a target like `s3://bucket/prefix`.
+pub fn parse_target(target: &str) -> Result<UploadTarget, String> {
+    let invalid = || {
+        format!(
+            "Invalid target {}, expected e.g. s3://bucket/prefix",
+            target
+        )
+    };
+    let rest = target.strip_prefix("s3://").ok_or_else(invalid)?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(invalid());
+    }
+    Ok(UploadTarget {
+        bucket: bucket.to_string(),
+        prefix: prefix.trim_matches('/').to_string(),
+    })
+}
+
+fn key(target: &UploadTarget, segment: &Segment) -> Path {
+    match target.prefix.as_str() {
+        "" => Path::from(segment.name.as_str()),
+        prefix => Path::from(format!("{}/{}", prefix, segment.name)),
+    }
+}
+
+async fn upload(
+    store: &dyn ObjectStore,
+    options: &UploadOptions,
+    segment: &Segment,
+) -> anyhow::Result<()> {
+    let key = key(&options.target, segment);
+    let mut file = tokio::fs::File::open(&segment.path).await?;
+    if file.metadata().await?.len() <= options.part_size as u64 {
+        let mut contents = vec![];
+        file.read_to_end(&mut contents).await?;
+        store.put(&key, contents.into()).await?;
+        return Ok(());
+    }
+    let mut upload =
+        WriteMultipart::new_with_chunk_size(store.put_multipart(&key).await?, options.part_size);
+    let mut buffer = vec![0; options.part_size];
+    loop {
+        let read = match file.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                upload.abort().await?;
+                return Err(e.into());
+            }
+        };
+        upload.wait_for_capacity(CONCURRENT_PARTS).await?;
+        upload.write(&buffer[..read]);
+    }
+    upload.finish().await?;
+    Ok(())
+}
+
+/// Uploads segments until all senders are dropped, deleting their files afterwards.
+/// Credentials, region and the endpoint of S3-compatible stores are read from the `AWS_*`
+/// environment variables. Segments that fail to upload are reported and dropped.
+pub async fn run(
+    options: UploadOptions,
+    mut segments: mpsc::Receiver<Segment>,
+) -> anyhow::Result<()> {
+    let store = AmazonS3Builder::from_env()
+        .with_bucket_name(&options.target.bucket)
+        .with_retry(RetryConfig {
+            max_retries: options.retries,
+            ..RetryConfig::default()
+        })
+        .build()?;
+    while let Some(segment) = segments.recv().await {
+        if let Err(e) = upload(&store, &options, &segment).await {
+            diagnostics::error(format_args!("Could not upload {}: {}", segment.name, e));
+        }
+        if let Err(e) = tokio::fs::remove_file(&segment.path).await {
+            diagnostics::error(format_args!(
+                "Could not remove {}: {}",
+                segment.path.display(),
+                e
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    async fn read(store: &InMemory, key: &str) -> Vec<u8> {
+        let result = store.get(&Path::from(key)).await.unwrap();
+        result.bytes().await.unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            parse_target("s3://logs/klog/prod/").unwrap(),
+            UploadTarget {
+                bucket: "logs".to_string(),
+                prefix: "klog/prod".to_string(),
+            }
+        );
+        assert_eq!(parse_target("s3://logs").unwrap().prefix, "");
+        assert!(parse_target("logs/klog").is_err());
+        assert!(parse_target("s3:///klog").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let store = InMemory::new();
+        let options = UploadOptions {
+            target: parse_target("s3://logs/klog").unwrap(),
+            part_size: 4,
+            retries: 0,
+        };
+
+        let dir = std::env::temp_dir().join(format!("klog-upload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in [("small", "l1\n"), ("large", "l1\nl2\nl3\n")] {
+            let segment = Segment {
+                name: name.to_string(),
+                path: dir.join(name),
+            };
+            std::fs::write(&segment.path, contents).unwrap();
+            upload(&store, &options, &segment).await.unwrap();
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(read(&store, "klog/small").await, b"l1\n");
+        assert_eq!(read(&store, "klog/large").await, b"l1\nl2\nl3\n");
+    }
+}
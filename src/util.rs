@@ -1,4 +1,16 @@
-use rand::Rng;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use colored::Colorize;
+use itertools::Itertools;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::chrono::{DateTime, FixedOffset, Local, SecondsFormat, TimeDelta};
+use k8s_openapi::serde_json::Value;
+use kube::core::GroupVersionKind;
+use regex::{Regex, RegexBuilder};
 
 pub struct Color {
     pub r: u8,
@@ -6,22 +18,1110 @@ pub struct Color {
     pub b: u8,
 }
 
-pub fn get_rnd_color() -> Color {
-    let mut rng = rand::thread_rng();
-    let r = rng.gen_range(0..255);
-    let g = rng.gen_range(0..255);
-    let b = rng.gen_range(0..255);
-    Color { r, g, b }
+/// 64-bit FNV-1a, used because it is stable across runs and Rust versions.
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Derives a stable color from `name`. A different `salt` reshuffles the colors.
+pub fn color_for_name(name: &str, salt: &str) -> Color {
+    let hash = fnv1a(salt.bytes().chain([0]).chain(name.bytes()));
+    Color {
+        r: (hash >> 40) as u8,
+        g: (hash >> 24) as u8,
+        b: (hash >> 8) as u8,
+    }
+    .readable()
+}
+
+/// The range of luma derived colors are kept in, so they can be read on both dark and
+/// light terminals.
+const MIN_LUMA: f64 = 0.35;
+const MAX_LUMA: f64 = 0.8;
+
+impl Color {
+    /// The perceived brightness between 0 and 1.
+    fn luma(&self) -> f64 {
+        (0.2126 * self.r as f64 + 0.7152 * self.g as f64 + 0.0722 * self.b as f64) / 255.0
+    }
+
+    /// Lightens colors that are too dark and darkens colors that are too bright, keeping
+    /// their hue.
+    fn readable(self) -> Color {
+        let luma = self.luma();
+        let adjust = |channel: u8| -> u8 {
+            let channel = channel as f64;
+            let adjusted = if luma < MIN_LUMA {
+                channel + (255.0 - channel) * (MIN_LUMA - luma) / (1.0 - luma)
+            } else if luma > MAX_LUMA {
+                channel * MAX_LUMA / luma
+            } else {
+                channel
+            };
+            adjusted.round() as u8
+        };
+        Color {
+            r: adjust(self.r),
+            g: adjust(self.g),
+            b: adjust(self.b),
+        }
+    }
+
+    /// Converts a hue in degrees, saturation and lightness between 0 and 1 to RGB.
+    fn from_hsl(hue: f64, saturation: f64, lightness: f64) -> Color {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = lightness - chroma / 2.0;
+        let channel = |value: f64| ((value + m) * 255.0).round() as u8;
+        Color {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        }
+    }
+}
+
+/// Derives a stable color for a pod. Pods of the same `resource` share a hue that their
+/// names only shift slightly, so replicas get related colors.
+pub fn color_for_pod(pod: &str, resource: Option<&str>, salt: &str) -> Color {
+    let Some(resource) = resource else {
+        return color_for_name(pod, salt);
+    };
+    let hue = fnv1a(salt.bytes().chain([0]).chain(resource.bytes())) % 360;
+    let shade = fnv1a(salt.bytes().chain([0]).chain(pod.bytes()));
+    Color::from_hsl(
+        hue as f64 + (shade % 41) as f64 - 20.0,
+        0.7,
+        0.5 + (shade >> 8) as f64 % 21.0 / 100.0,
+    )
+    .readable()
+}
+
+/// The colors pods are shown in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Palette {
+    /// Colors derived from the pod names, related for replicas of a resource.
+    #[default]
+    Default,
+    /// The Okabe-Ito colors, which people with color vision deficiencies like
+    /// deuteranopia can tell apart.
+    Colorblind,
+    /// The Tableau 10 colors.
+    Tableau,
+}
+
+const OKABE_ITO: [(u8, u8, u8); 7] = [
+    (230, 159, 0),
+    (86, 180, 233),
+    (0, 158, 115),
+    (240, 228, 66),
+    (0, 114, 178),
+    (213, 94, 0),
+    (204, 121, 167),
+];
+
+const TABLEAU_10: [(u8, u8, u8); 10] = [
+    (78, 121, 167),
+    (242, 142, 43),
+    (225, 87, 89),
+    (118, 183, 178),
+    (89, 161, 79),
+    (237, 201, 72),
+    (176, 122, 161),
+    (255, 157, 167),
+    (156, 117, 95),
+    (186, 176, 172),
+];
+
+impl Palette {
+    /// Picks a stable color for a pod from this palette, see `color_for_pod`.
+    pub fn color_for_pod(self, pod: &str, resource: Option<&str>, salt: &str) -> Color {
+        let colors: &[(u8, u8, u8)] = match self {
+            Palette::Default => return color_for_pod(pod, resource, salt),
+            Palette::Colorblind => &OKABE_ITO,
+            Palette::Tableau => &TABLEAU_10,
+        };
+        let hash = fnv1a(salt.bytes().chain([0]).chain(pod.bytes()));
+        let (r, g, b) = colors[(hash % colors.len() as u64) as usize];
+        Color { r, g, b }
+    }
+}
+
+/// Decides whether to write colors: not if `--no-color` is given, the `NO_COLOR` variable
+/// is set to anything but an empty string, or the output is not a terminal.
+pub fn use_color(no_color: bool, no_color_var: Option<OsString>, is_terminal: bool) -> bool {
+    !no_color && no_color_var.is_none_or(|value| value.is_empty()) && is_terminal
+}
+
+/// Compiles `pattern` into a regex matching whole pod names, unless it is a plain pod
+/// name made up of lowercase letters, digits, `-` and `.`.
+pub fn pod_name_regex(pattern: &str) -> Result<Option<Regex>, regex::Error> {
+    let is_plain_name = pattern
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.');
+    if is_plain_name {
+        return Ok(None);
+    }
+    Regex::new(&format!("^(?:{})$", pattern)).map(Some)
+}
+
+/// Formats label equality requirements as a selector string, e.g. `app=web,tier=db`.
+pub fn match_labels_selector(labels: &BTreeMap<String, String>) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .join(",")
+}
+
+/// Formats a `LabelSelector` with both its `matchLabels` and `matchExpressions` as a
+/// selector string, e.g. `app=web,tier in (api,db),!canary`.
+pub fn label_selector(selector: &LabelSelector) -> anyhow::Result<String> {
+    let mut requirements = vec![];
+    if let Some(labels) = &selector.match_labels {
+        if !labels.is_empty() {
+            requirements.push(match_labels_selector(labels));
+        }
+    }
+    for expression in selector.match_expressions.iter().flatten() {
+        let values = expression.values.as_deref().unwrap_or_default().join(",");
+        requirements.push(match expression.operator.as_str() {
+            "In" => format!("{} in ({})", expression.key, values),
+            "NotIn" => format!("{} notin ({})", expression.key, values),
+            "Exists" => expression.key.clone(),
+            "DoesNotExist" => format!("!{}", expression.key),
+            operator => anyhow::bail!("Unsupported label selector operator {}", operator),
+        });
+    }
+    Ok(requirements.join(","))
+}
+
+/// Recompiles `regex` to match regardless of case.
+pub fn case_insensitive(regex: &Regex) -> Regex {
+    RegexBuilder::new(regex.as_str())
+        .case_insensitive(true)
+        .build()
+        .expect("a valid regex stays valid when matching case-insensitively")
+}
+
+/// Parses a `--resource` argument of the form `group/version/Kind/name`, or
+/// `version/Kind/name` for the core group, e.g. `apps/v1/Deployment/web`.
+pub fn parse_resource_spec(spec: &str) -> anyhow::Result<(GroupVersionKind, String)> {
+    let parts: Vec<&str> = spec.split('/').collect();
+    match parts[..] {
+        [group, version, kind, name] if parts.iter().all(|part| !part.is_empty()) => Ok((
+            GroupVersionKind::gvk(group, version, kind),
+            name.to_string(),
+        )),
+        [version, kind, name] if parts.iter().all(|part| !part.is_empty()) => {
+            Ok((GroupVersionKind::gvk("", version, kind), name.to_string()))
+        }
+        _ => anyhow::bail!(
+            "Invalid resource {}, expected group/version/Kind/name, e.g. apps/v1/Deployment/web",
+            spec
+        ),
+    }
+}
+
+/// Parses a duration like `30s`, `10m` or `1h30m` into seconds, as accepted by
+/// `kubectl logs --since`.
+pub fn parse_duration_seconds(duration: &str) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration {}, expected e.g. 30s, 10m or 1h30m",
+            duration
+        )
+    };
+    let mut seconds: i64 = 0;
+    let mut number = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: i64 = number.parse().map_err(|_| invalid())?;
+        seconds = value
+            .checked_mul(unit)
+            .and_then(|value| seconds.checked_add(value))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() || seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(seconds)
+}
+
+/// Parses a size like `512K`, `100MB` or `1G` into bytes, counting in powers of 1024.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size {}, expected e.g. 512K, 100MB or 1G", size);
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let number: u64 = size[..digits].parse().map_err(|_| invalid())?;
+    let unit = match size[digits..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    match number.checked_mul(unit).ok_or_else(invalid)? {
+        0 => Err(invalid()),
+        bytes => Ok(bytes),
+    }
+}
+
+const LEVEL_KEYS: [&str; 5] = ["level", "lvl", "severity", "levelname", "log.level"];
+const TIMESTAMP_KEYS: [&str; 4] = ["ts", "time", "timestamp", "@timestamp"];
+const MESSAGE_KEYS: [&str; 3] = ["msg", "message", "@message"];
+
+/// Parses a log line as JSON if it holds a JSON object.
+pub fn maybe_parse_json(line: &str) -> Option<Value> {
+    if !line.trim_start().starts_with('{') {
+        return None;
+    }
+    k8s_openapi::serde_json::from_str(line)
+        .ok()
+        .filter(Value::is_object)
+}
+
+/// Parses a logfmt log line like `ts=... level=info msg="hello world"` into a JSON
+/// object of strings, if every word of the line is a `key=value` pair.
+pub fn maybe_parse_logfmt(line: &str) -> Option<Value> {
+    let mut fields = k8s_openapi::serde_json::Map::new();
+    let mut chars = line.trim().chars().peekable();
+    while chars.peek().is_some() {
+        let mut key = String::new();
+        loop {
+            match chars.next()? {
+                '=' => break,
+                c if c.is_whitespace() => return None,
+                c => key.push(c),
+            }
+        }
+        if key.is_empty() {
+            return None;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+            if chars.next().is_some_and(|c| !c.is_whitespace()) {
+                return None;
+            }
+        } else {
+            value = chars.by_ref().take_while(|c| !c.is_whitespace()).collect();
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        fields.insert(key, Value::String(value));
+    }
+    (!fields.is_empty()).then_some(Value::Object(fields))
+}
+
+/// Parses a log line as JSON or logfmt, see `maybe_parse_json` and `maybe_parse_logfmt`.
+pub fn maybe_parse_structured(line: &str) -> Option<Value> {
+    maybe_parse_json(line).or_else(|| maybe_parse_logfmt(line))
+}
+
+fn find_field(json: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| json.get(key))
+        .and_then(|value| match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+}
+
+/// Finds the log level of a JSON log line.
+pub fn find_level(json: &Value) -> Option<String> {
+    find_field(json, &LEVEL_KEYS)
+}
+
+/// Maps the many spellings of log levels (`W`, `Warning`, syslog and pino numbers)
+/// to one of `trace`, `debug`, `info`, `warn`, `error` or `fatal`.
+///
+/// Unrecognized levels are treated as `info`.
+pub fn normalize_level(level: &str) -> &'static str {
+    if let Ok(number) = level.trim().parse::<u32>() {
+        return match number {
+            // syslog severities
+            0..=2 => "fatal",
+            3 => "error",
+            4 => "warn",
+            5 | 6 => "info",
+            7 => "debug",
+            // pino / bunyan levels
+            8..=10 => "trace",
+            11..=20 => "debug",
+            21..=30 => "info",
+            31..=40 => "warn",
+            41..=50 => "error",
+            _ => "fatal",
+        };
+    }
+    match level.trim().to_ascii_lowercase().as_str() {
+        "t" | "trc" | "trace" | "finest" => "trace",
+        "d" | "dbg" | "debug" | "fine" | "verbose" => "debug",
+        "i" | "inf" | "info" | "information" | "notice" => "info",
+        "w" | "wrn" | "warn" | "warning" => "warn",
+        "e" | "err" | "error" | "severe" => "error",
+        "f" | "c" | "ftl" | "fatal" | "crit" | "critical" | "panic" | "alert" | "emerg"
+        | "emergency" => "fatal",
+        _ => "info",
+    }
+}
+
+/// A condition on a field of JSON log lines, e.g. `status>=500` or `user.id=42`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonFilter {
+    /// Dotted path to the field, e.g. `user.id` for `{"user": {"id": 42}}`.
+    pub path: String,
+    pub operator: Operator,
+    pub value: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl std::str::FromStr for JsonFilter {
+    type Err = String;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        // Two-character operators come first so `>=` is not read as `>`.
+        const OPERATORS: [(&str, Operator); 6] = [
+            ("!=", Operator::Ne),
+            (">=", Operator::Ge),
+            ("<=", Operator::Le),
+            ("=", Operator::Eq),
+            (">", Operator::Gt),
+            ("<", Operator::Lt),
+        ];
+        let (position, symbol, operator) = OPERATORS
+            .iter()
+            .filter_map(|(symbol, operator)| Some((filter.find(symbol)?, *symbol, *operator)))
+            .min_by_key(|(position, symbol, _)| (*position, std::cmp::Reverse(symbol.len())))
+            .ok_or_else(|| format!("Invalid JSON filter {}, expected e.g. status>=500", filter))?;
+        let path = filter[..position].trim();
+        if path.is_empty() {
+            return Err(format!("Invalid JSON filter {}, missing field", filter));
+        }
+        Ok(JsonFilter {
+            path: path.to_string(),
+            operator,
+            value: filter[position + symbol.len()..].trim().to_string(),
+        })
+    }
+}
+
+/// Looks up a dotted path in `json`, preferring keys that contain dots themselves.
+fn lookup<'v>(json: &'v Value, path: &str) -> Option<&'v Value> {
+    json.get(path).or_else(|| {
+        let (head, rest) = path.split_once('.')?;
+        lookup(json.get(head)?, rest)
+    })
+}
+
+impl JsonFilter {
+    /// Checks the condition against a parsed JSON log line. Fields compare as numbers
+    /// if both sides are numbers, otherwise only `=` and `!=` apply, comparing text.
+    pub fn matches(&self, json: &Value) -> bool {
+        let Some(field) = lookup(json, &self.path) else {
+            return self.operator == Operator::Ne;
+        };
+        let field = match field {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        if let (Ok(field), Ok(value)) = (field.parse::<f64>(), self.value.parse::<f64>()) {
+            return match self.operator {
+                Operator::Eq => field == value,
+                Operator::Ne => field != value,
+                Operator::Gt => field > value,
+                Operator::Ge => field >= value,
+                Operator::Lt => field < value,
+                Operator::Le => field <= value,
+            };
+        }
+        match self.operator {
+            Operator::Eq => field == self.value,
+            Operator::Ne => field != self.value,
+            _ => false,
+        }
+    }
+}
+
+/// The time zone to show timestamps in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeZone {
+    Local,
+    Named(chrono_tz::Tz),
+}
+
+impl TimeZone {
+    /// Renders a timestamp in this time zone as RFC3339.
+    pub fn convert(self, timestamp: DateTime<FixedOffset>) -> String {
+        match self {
+            TimeZone::Local => timestamp
+                .with_timezone(&Local)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, false),
+            TimeZone::Named(tz) => timestamp
+                .with_timezone(&tz)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, false),
+        }
+    }
+}
+
+static RFC3339: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b\d{4}-\d{2}-\d{2}[Tt ]\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:[Zz]|[+-]\d{2}:\d{2})")
+        .unwrap()
+});
+
+/// Re-renders all RFC3339 timestamps with a time zone found in `text`.
+pub fn replace_timestamps(
+    text: &str,
+    render: impl Fn(DateTime<FixedOffset>) -> String,
+) -> Cow<'_, str> {
+    RFC3339.replace_all(text, |captures: &regex::Captures| {
+        DateTime::parse_from_rfc3339(&captures[0])
+            .map(&render)
+            .unwrap_or_else(|_| captures[0].to_string())
+    })
+}
+
+/// Formats the offset of a timestamp from a reference time like `-2m13s` or `+1h0m5s`.
+pub fn format_offset(offset: TimeDelta) -> String {
+    let seconds = offset.num_seconds();
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.unsigned_abs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}{}s", sign, s),
+        (0, m, s) => format!("{}{}m{}s", sign, m, s),
+        (h, m, s) => format!("{}{}h{}m{}s", sign, h, m, s),
+    }
+}
+
+/// Log levels from least to most severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        }
+    }
+
+    /// Parses one of the many spellings of log levels, see `normalize_level`.
+    pub fn parse(level: &str) -> Level {
+        match normalize_level(level) {
+            "trace" => Level::Trace,
+            "debug" => Level::Debug,
+            "warn" => Level::Warn,
+            "error" => Level::Error,
+            "fatal" => Level::Fatal,
+            _ => Level::Info,
+        }
+    }
+}
+
+static LOGFMT_LEVEL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)(?:^|\s)(?:level|lvl|severity)="?([a-z0-9]+)"#).unwrap());
+static PLAIN_LEVEL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(TRACE|DEBUG|INFO|WARN|WARNING|ERROR|FATAL|CRITICAL|PANIC)\b").unwrap()
+});
+
+/// Finds the log level of a JSON or logfmt line, or of a plain text line containing an
+/// upper case level like `ERROR`.
+pub fn detect_level(line: &str) -> Option<Level> {
+    if let Some(json) = maybe_parse_json(line) {
+        return find_level(&json).map(|level| Level::parse(&level));
+    }
+    LOGFMT_LEVEL
+        .captures(line)
+        .or_else(|| PLAIN_LEVEL.captures(line))
+        .map(|captures| Level::parse(&captures[1]))
+}
+
+/// Quotes a logfmt value if it is empty or contains spaces, quotes or `=`.
+pub fn logfmt_value(value: &str) -> Cow<'_, str> {
+    if value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=')
+    {
+        Cow::Owned(format!("{:?}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+static ANSI_ESCAPE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b(?:\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(?:\x07|\x1b\\)|[@-Z\\-_])").unwrap()
+});
+
+/// Removes ANSI escape sequences like colors, cursor movements and terminal titles.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    ANSI_ESCAPE.replace_all(text, "")
+}
+
+/// Tells whether a line continues a stack trace, like Java's `\tat ...` and `Caused by:`
+/// lines or the indented lines of Python tracebacks.
+pub fn is_continuation(line: &str) -> bool {
+    (line.starts_with([' ', '\t']) && !line.trim().is_empty())
+        || line.starts_with("at ")
+        || line.starts_with("Caused by")
+}
+
+/// How long to wait before retrying for the `attempt`th time, doubling from one second
+/// up to `max`.
+pub fn backoff(attempt: u32, max: Duration) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(attempt.saturating_sub(1))).min(max)
+}
+
+/// Cuts `text` to at most `max` characters, ending it with `…` if anything was cut.
+pub fn truncate(text: &str, max: usize) -> Cow<'_, str> {
+    match text.char_indices().nth(max) {
+        Some(_) => {
+            let end = text
+                .char_indices()
+                .nth(max.saturating_sub(1))
+                .map_or(0, |(index, _)| index);
+            Cow::Owned(format!("{}…", &text[..end]))
+        }
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Renders the given fields of a JSON or logfmt line as `key=value` pairs, where keys may
+/// be dotted paths into nested objects. Returns `None` if none of the fields are present.
+pub fn select_fields(json: &Value, fields: &[String]) -> Option<String> {
+    let pairs: Vec<String> = fields
+        .iter()
+        .filter_map(|field| {
+            let value = match lookup(json, field)? {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            Some(format!("{}={}", field, logfmt_value(&value)))
+        })
+        .collect();
+    (!pairs.is_empty()).then(|| pairs.join(" "))
+}
+
+/// Renders JSON indented by two spaces, with keys and values highlighted by type.
+pub fn highlight_json(json: &Value) -> String {
+    let mut out = String::new();
+    write_highlighted_json(json, 0, &mut out);
+    out
+}
+
+fn write_highlighted_json(json: &Value, depth: usize, out: &mut String) {
+    let indent = |depth: usize| "  ".repeat(depth);
+    match json {
+        Value::Object(fields) if !fields.is_empty() => {
+            out.push_str("{\n");
+            for (index, (key, value)) in fields.iter().enumerate() {
+                let key = Value::String(key.clone()).to_string();
+                out.push_str(&format!("{}{}: ", indent(depth + 1), key.blue()));
+                write_highlighted_json(value, depth + 1, out);
+                out.push_str(if index + 1 < fields.len() {
+                    ",\n"
+                } else {
+                    "\n"
+                });
+            }
+            out.push_str(&format!("{}}}", indent(depth)));
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("[\n");
+            for (index, item) in items.iter().enumerate() {
+                out.push_str(&indent(depth + 1));
+                write_highlighted_json(item, depth + 1, out);
+                out.push_str(if index + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{}]", indent(depth)));
+        }
+        Value::String(_) => out.push_str(&json.to_string().green().to_string()),
+        Value::Number(_) => out.push_str(&json.to_string().yellow().to_string()),
+        Value::Bool(_) | Value::Null => out.push_str(&json.to_string().magenta().to_string()),
+        _ => out.push_str(&json.to_string()),
+    }
+}
+
+/// Summarizes a JSON or logfmt log line as `[level] ts: msg`, leaving out missing parts.
+///
+/// Returns `None` if the line has no message.
+pub fn get_pretty_json(json: &Value, normalize_levels: bool) -> Option<String> {
+    let msg = find_field(json, &MESSAGE_KEYS)?;
+    let mut pretty = String::new();
+    if let Some(level) = find_level(json) {
+        let level = if normalize_levels {
+            normalize_level(&level).to_string()
+        } else {
+            level
+        };
+        pretty.push_str(&format!("[{}] ", level));
+    }
+    if let Some(ts) = find_field(json, &TIMESTAMP_KEYS) {
+        pretty.push_str(&format!("{}: ", ts));
+    }
+    pretty.push_str(&msg);
+    Some(pretty)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use k8s_openapi::serde_json::json;
+
+    fn rgb(color: Color) -> (u8, u8, u8) {
+        (color.r, color.g, color.b)
+    }
+
+    #[test]
+    fn test_color_for_name_is_stable() {
+        assert_eq!(
+            rgb(color_for_name("web-0", "")),
+            rgb(color_for_name("web-0", ""))
+        );
+        assert_eq!(
+            rgb(color_for_name("web-0", "salt")),
+            rgb(color_for_name("web-0", "salt"))
+        );
+    }
+
+    #[test]
+    fn test_color_for_name_differs_between_names() {
+        assert_ne!(
+            rgb(color_for_name("web-0", "")),
+            rgb(color_for_name("web-1", ""))
+        );
+    }
+
+    #[test]
+    fn test_color_salt_changes_color() {
+        assert_ne!(
+            rgb(color_for_name("web-0", "")),
+            rgb(color_for_name("web-0", "salt"))
+        );
+        assert_ne!(
+            rgb(color_for_name("web-0", "salt")),
+            rgb(color_for_name("web-0", "pepper"))
+        );
+    }
+
+    #[test]
+    fn test_colors_are_readable() {
+        for name in ["web-0", "web-1", "api-0", "worker-7", "db-0"] {
+            for resource in [None, Some("deployment/web")] {
+                let luma = color_for_pod(name, resource, "").luma();
+                assert!(
+                    (MIN_LUMA - 0.01..=MAX_LUMA + 0.01).contains(&luma),
+                    "{}",
+                    luma
+                );
+            }
+        }
+        assert_eq!(rgb(Color { r: 0, g: 0, b: 0 }.readable()), (89, 89, 89));
+    }
+
+    #[test]
+    fn test_palette_colors() {
+        let color = Palette::Colorblind.color_for_pod("web-0", None, "");
+        assert!(OKABE_ITO.contains(&rgb(color)));
+        assert_eq!(
+            rgb(Palette::Tableau.color_for_pod("web-0", None, "")),
+            rgb(Palette::Tableau.color_for_pod("web-0", None, ""))
+        );
+    }
+
+    #[test]
+    fn test_color_from_hsl() {
+        assert_eq!(rgb(Color::from_hsl(0.0, 1.0, 0.5)), (255, 0, 0));
+        assert_eq!(rgb(Color::from_hsl(120.0, 1.0, 0.5)), (0, 255, 0));
+        assert_eq!(rgb(Color::from_hsl(-120.0, 1.0, 0.5)), (0, 0, 255));
+        assert_eq!(rgb(Color::from_hsl(0.0, 0.0, 1.0)), (255, 255, 255));
+    }
+
+    #[test]
+    fn test_color_for_pod_relates_replicas() {
+        let hue = |color: Color| {
+            let (r, g, b) = (color.r as f64, color.g as f64, color.b as f64);
+            (3f64.sqrt() * (g - b)).atan2(2.0 * r - g - b).to_degrees()
+        };
+        let web_0 = hue(color_for_pod("web-0", Some("deployment/web"), ""));
+        let web_1 = hue(color_for_pod("web-1", Some("deployment/web"), ""));
+        let difference = (web_0 - web_1).rem_euclid(360.0);
+
+        assert!(difference.min(360.0 - difference) <= 45.0);
+        assert_eq!(
+            rgb(color_for_pod("web-0", Some("deployment/web"), "")),
+            rgb(color_for_pod("web-0", Some("deployment/web"), ""))
+        );
+        assert_eq!(
+            rgb(color_for_pod("web-0", None, "")),
+            rgb(color_for_name("web-0", ""))
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("100MB"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("1gib"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("0M").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("10 MB").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31mERROR\x1b[0m db down"),
+            "ERROR db down"
+        );
+        assert_eq!(strip_ansi("\x1b]0;title\x07done\x1b[2K"), "done");
+        assert!(matches!(strip_ansi("plain"), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn test_is_continuation() {
+        assert!(is_continuation("\tat com.example.Main.run(Main.java:12)"));
+        assert!(is_continuation("  File \"app.py\", line 3, in <module>"));
+        assert!(is_continuation("at Object.<anonymous> (index.js:1:7)"));
+        assert!(is_continuation("Caused by: java.io.IOException"));
+        assert!(!is_continuation("GET /health 200"));
+        assert!(!is_continuation("   "));
+        assert!(!is_continuation("attempt 3 failed"));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let max = Duration::from_secs(60);
+        assert_eq!(backoff(1, max), Duration::from_secs(1));
+        assert_eq!(backoff(3, max), Duration::from_secs(4));
+        assert_eq!(backoff(20, max), max);
+        assert_eq!(backoff(u32::MAX, max), max);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("hello world", 5), "hell…");
+        assert_eq!(truncate("äöüäöü", 3), "äö…");
+        assert_eq!(truncate("hello", 0), "…");
+    }
+
+    #[test]
+    fn test_use_color() {
+        assert!(use_color(false, None, true));
+        assert!(use_color(false, Some("".into()), true));
+        assert!(!use_color(true, None, true));
+        assert!(!use_color(false, Some("1".into()), true));
+        assert!(!use_color(false, None, false));
+    }
+
+    #[test]
+    fn test_maybe_parse_json() {
+        assert!(maybe_parse_json(r#"{"msg": "hello"}"#).is_some());
+        assert!(maybe_parse_json("plain text").is_none());
+        assert!(maybe_parse_json("{not json").is_none());
+    }
+
+    #[test]
+    fn test_get_pretty_json() {
+        let json = maybe_parse_json(
+            r#"{"level": "Warning", "ts": "2024-01-01T00:00:00Z", "msg": "disk almost full"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            get_pretty_json(&json, false).unwrap(),
+            "[Warning] 2024-01-01T00:00:00Z: disk almost full"
+        );
+        assert_eq!(
+            get_pretty_json(&json, true).unwrap(),
+            "[warn] 2024-01-01T00:00:00Z: disk almost full"
+        );
+
+        let json = maybe_parse_json(r#"{"message": "hi"}"#).unwrap();
+        assert_eq!(get_pretty_json(&json, true).unwrap(), "hi");
+
+        let json = maybe_parse_json(r#"{"level": 30}"#).unwrap();
+        assert!(get_pretty_json(&json, true).is_none());
+    }
 
     #[test]
-    fn test_randomness() {
-        let color1 = get_rnd_color();
-        let color2 = get_rnd_color();
-        assert!(color1.r != color2.r || color1.g != color2.g || color1.b != color2.b);
+    fn test_normalize_numeric_levels() {
+        assert_eq!(normalize_level("0"), "fatal");
+        assert_eq!(normalize_level("3"), "error");
+        assert_eq!(normalize_level("4"), "warn");
+        assert_eq!(normalize_level("6"), "info");
+        assert_eq!(normalize_level("7"), "debug");
+        assert_eq!(normalize_level("10"), "trace");
+        assert_eq!(normalize_level("30"), "info");
+        assert_eq!(normalize_level("40"), "warn");
+        assert_eq!(normalize_level("60"), "fatal");
+    }
+
+    #[test]
+    fn test_normalize_single_letter_levels() {
+        assert_eq!(normalize_level("T"), "trace");
+        assert_eq!(normalize_level("D"), "debug");
+        assert_eq!(normalize_level("I"), "info");
+        assert_eq!(normalize_level("W"), "warn");
+        assert_eq!(normalize_level("E"), "error");
+        assert_eq!(normalize_level("F"), "fatal");
+    }
+
+    #[test]
+    fn test_normalize_canonical_levels() {
+        for level in ["trace", "debug", "info", "warn", "error", "fatal"] {
+            assert_eq!(normalize_level(level), level);
+        }
+        assert_eq!(normalize_level("Warning"), "warn");
+        assert_eq!(normalize_level("ERR"), "error");
+        assert_eq!(normalize_level("something"), "info");
+    }
+
+    #[test]
+    fn test_pod_name_regex() {
+        assert!(pod_name_regex("web-0").unwrap().is_none());
+        assert!(pod_name_regex("web.example-0").unwrap().is_none());
+
+        let regex = pod_name_regex("api-.*").unwrap().unwrap();
+        assert!(regex.is_match("api-7d9f-x2"));
+        assert!(!regex.is_match("old-api-7d9f-x2"));
+
+        assert!(pod_name_regex("api-(").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_seconds() {
+        assert_eq!(parse_duration_seconds("45s"), Ok(45));
+        assert_eq!(parse_duration_seconds("10m"), Ok(600));
+        assert_eq!(parse_duration_seconds("1h30m"), Ok(5400));
+        assert_eq!(parse_duration_seconds("2d"), Ok(172800));
+
+        assert!(parse_duration_seconds("10").is_err());
+        assert!(parse_duration_seconds("m").is_err());
+        assert!(parse_duration_seconds("0s").is_err());
+        assert!(parse_duration_seconds("10x").is_err());
+        assert!(parse_duration_seconds("999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_label_selector() {
+        let selector: LabelSelector = k8s_openapi::serde_json::from_value(json!({
+            "matchLabels": { "app": "web" },
+            "matchExpressions": [
+                { "key": "tier", "operator": "In", "values": ["api", "db"] },
+                { "key": "env", "operator": "NotIn", "values": ["dev"] },
+                { "key": "release", "operator": "Exists" },
+                { "key": "canary", "operator": "DoesNotExist" }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            label_selector(&selector).unwrap(),
+            "app=web,tier in (api,db),env notin (dev),release,!canary"
+        );
+    }
+
+    #[test]
+    fn test_label_selector_rejects_unknown_operators() {
+        let selector: LabelSelector = k8s_openapi::serde_json::from_value(json!({
+            "matchExpressions": [{ "key": "tier", "operator": "Gt", "values": ["1"] }]
+        }))
+        .unwrap();
+
+        assert!(label_selector(&selector).is_err());
+    }
+
+    #[test]
+    fn test_detect_level() {
+        assert_eq!(
+            detect_level(r#"{"severity": "WARNING", "msg": "slow"}"#),
+            Some(Level::Warn)
+        );
+        assert_eq!(
+            detect_level(r#"ts=2024-01-01 level="error" msg="db down""#),
+            Some(Level::Error)
+        );
+        assert_eq!(
+            detect_level("2024-01-01 12:00:00 DEBUG cache miss"),
+            Some(Level::Debug)
+        );
+        assert_eq!(detect_level(r#"{"msg": "no level"}"#), None);
+        assert_eq!(detect_level("handled an error gracefully"), None);
+        assert!(Level::Warn < Level::Error);
+    }
+
+    #[test]
+    fn test_maybe_parse_logfmt() {
+        let fields = maybe_parse_logfmt(
+            r#"ts=2024-01-01T12:00:00Z level=info msg="order \"7\" shipped" took=12ms"#,
+        )
+        .unwrap();
+        assert_eq!(
+            fields,
+            json!({
+                "ts": "2024-01-01T12:00:00Z",
+                "level": "info",
+                "msg": "order \"7\" shipped",
+                "took": "12ms"
+            })
+        );
+        assert_eq!(maybe_parse_logfmt("empty= next=1").unwrap()["empty"], "");
+
+        assert!(maybe_parse_logfmt("plain text").is_none());
+        assert!(maybe_parse_logfmt("level=info done").is_none());
+        assert!(maybe_parse_logfmt("status=503 plain text").is_none());
+        assert!(maybe_parse_logfmt(r#"msg="unterminated"#).is_none());
+        assert!(maybe_parse_logfmt("").is_none());
+    }
+
+    #[test]
+    fn test_get_pretty_logfmt() {
+        let fields = maybe_parse_structured("level=WARN ts=12:00 msg=slow").unwrap();
+        assert_eq!(
+            get_pretty_json(&fields, true).unwrap(),
+            "[warn] 12:00: slow"
+        );
+    }
+
+    #[test]
+    fn test_time_zone_convert() {
+        let berlin = TimeZone::Named(chrono_tz::Europe::Berlin);
+        let convert = |text: &str| replace_timestamps(text, |ts| berlin.convert(ts)).into_owned();
+
+        assert_eq!(convert("2024-01-01T12:00:00Z"), "2024-01-01T13:00:00+01:00");
+        assert_eq!(
+            convert("2024-07-01T12:00:00.5+00:00"),
+            "2024-07-01T14:00:00.500+02:00"
+        );
+        assert_eq!(
+            convert("ts=2024-01-01T12:00:00Z msg=\"started at 2024-01-01 11:00:00\""),
+            "ts=2024-01-01T13:00:00+01:00 msg=\"started at 2024-01-01 11:00:00\""
+        );
+    }
+
+    #[test]
+    fn test_format_offset() {
+        assert_eq!(format_offset(TimeDelta::seconds(-133)), "-2m13s");
+        assert_eq!(format_offset(TimeDelta::milliseconds(5500)), "+5s");
+        assert_eq!(format_offset(TimeDelta::seconds(3605)), "+1h0m5s");
+        assert_eq!(format_offset(TimeDelta::milliseconds(-300)), "0s");
+    }
+
+    #[test]
+    fn test_logfmt_value() {
+        assert_eq!(logfmt_value("web-1"), "web-1");
+        assert_eq!(logfmt_value(""), r#""""#);
+        assert_eq!(
+            logfmt_value("order 7 \"failed\""),
+            r#""order 7 \"failed\"""#
+        );
+        assert_eq!(logfmt_value("a=b"), r#""a=b""#);
+    }
+
+    #[test]
+    fn test_json_filter_parse() {
+        let filter: JsonFilter = "status>=500".parse().unwrap();
+        assert_eq!(filter.path, "status");
+        assert_eq!(filter.operator, Operator::Ge);
+        assert_eq!(filter.value, "500");
+
+        let filter: JsonFilter = "user.name != bob".parse().unwrap();
+        assert_eq!(filter.path, "user.name");
+        assert_eq!(filter.operator, Operator::Ne);
+        assert_eq!(filter.value, "bob");
+
+        assert!("status".parse::<JsonFilter>().is_err());
+        assert!("=500".parse::<JsonFilter>().is_err());
+    }
+
+    #[test]
+    fn test_json_filter_matches() {
+        let json = json!({
+            "status": 503,
+            "user": { "id": "42", "name": "alice" },
+            "log.level": "warn"
+        });
+        let matches = |filter: &str| filter.parse::<JsonFilter>().unwrap().matches(&json);
+
+        assert!(matches("status>=500"));
+        assert!(!matches("status<500"));
+        assert!(matches("user.id=42"));
+        assert!(matches("user.name=alice"));
+        assert!(!matches("user.name>alice"));
+        assert!(matches("log.level=warn"));
+        assert!(matches("missing!=1"));
+        assert!(!matches("missing=1"));
+    }
+
+    #[test]
+    fn test_parse_resource_spec() {
+        let (gvk, name) = parse_resource_spec("apps/v1/Deployment/web").unwrap();
+        assert_eq!(gvk, GroupVersionKind::gvk("apps", "v1", "Deployment"));
+        assert_eq!(name, "web");
+
+        let (gvk, name) = parse_resource_spec("v1/ReplicationController/db").unwrap();
+        assert_eq!(
+            gvk,
+            GroupVersionKind::gvk("", "v1", "ReplicationController")
+        );
+        assert_eq!(name, "db");
+
+        assert!(parse_resource_spec("Deployment/web").is_err());
+        assert!(parse_resource_spec("apps//Deployment/web").is_err());
     }
 }